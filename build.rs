@@ -1,18 +1,20 @@
 use {
-    std::{
-        env,
-        io,
-    },
+    std::io,
     winresource::WindowsResource,
 };
 
+// Also printed by --version on Windows
+const INTERNAL_NAME: &str = "Настройка списка ДОМЕНОВ для фикса by Priler";
+
 fn main() -> io::Result<()> {
+    println!("cargo:rustc-env=INTERNAL_NAME={}", INTERNAL_NAME);
+
     if cfg!(target_os = "windows") {
-        let mut res = winresource::WindowsResource::new();
+        let mut res = WindowsResource::new();
         res
             // This path can be absolute, or relative to your crate root.
             .set_icon("icon.ico")
-            .set("InternalName", "Настройка списка ДОМЕНОВ для фикса by Priler");
+            .set("InternalName", INTERNAL_NAME);
 
         res.compile()?;
     }
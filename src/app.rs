@@ -0,0 +1,1094 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::sync::mpsc::Receiver;
+
+use crate::cli::Args;
+use crate::keys::Keymap;
+use crate::search::{ContentIndex, CONTENT_PREFIX};
+use crate::timings::Timings;
+use crate::{counts, errors, favorites, file_ops, state, wizard, VISIBLE_ITEMS};
+
+/// How many recently toggled lists the "Недавние" section keeps
+pub const RECENT_MAX: usize = 3;
+
+/// Footer category of the selected lists whose name has none
+pub const OTHER_CATEGORY: &str = "другое";
+
+/// The fixed rows above the lists, absent with --no-action-rows
+#[derive(Debug, Clone, Copy, PartialEq, Hash)]
+pub enum ControlRow {
+    Save,
+    Cancel,
+}
+
+impl ControlRow {
+    pub fn label(self) -> &'static str {
+        match self {
+            ControlRow::Save => "СОХРАНИТЬ СПИСОК",
+            ControlRow::Cancel => "ОТМЕНА",
+        }
+    }
+}
+
+#[derive(Debug, Hash)]
+pub struct FileEntry {
+    pub name: String,
+    pub selected: bool,
+    /// Part of selected.txt as it was loaded or last reset, shown as the S column
+    pub saved: bool,
+    /// Set for the SAVE / CANCEL rows, which aren't files
+    pub control: Option<ControlRow>,
+    /// Distinct domains in the file, None for control rows, unreadable files and, with
+    /// --lazy-counts, files not counted yet (see App::pending_counts)
+    pub domain_count: Option<usize>,
+    /// The file was modified within --changed-hours when the folder was scanned
+    pub changed_recently: bool,
+}
+
+impl FileEntry {
+    pub fn control(row: ControlRow) -> FileEntry {
+        FileEntry {
+            name: row.label().to_string(),
+            selected: false,
+            saved: false,
+            control: Some(row),
+            domain_count: None,
+            changed_recently: false,
+        }
+    }
+
+    pub fn is_control(&self) -> bool {
+        self.control.is_some()
+    }
+
+    pub fn is_empty_list(&self) -> bool {
+        self.domain_count == Some(0)
+    }
+}
+
+/// Domain count column of the list rows, cycled with %
+#[derive(Debug, Clone, Copy, PartialEq, Hash)]
+pub enum CountDisplay {
+    Hidden,
+    Absolute,
+    /// The count and its share of the selected domains
+    Share,
+}
+
+impl CountDisplay {
+    pub fn next(self) -> CountDisplay {
+        match self {
+            CountDisplay::Hidden => CountDisplay::Absolute,
+            CountDisplay::Absolute => CountDisplay::Share,
+            CountDisplay::Share => CountDisplay::Hidden,
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            CountDisplay::Hidden => "скрыто",
+            CountDisplay::Absolute => "в строках",
+            CountDisplay::Share => "в строках с долей от выбранных",
+        }
+    }
+}
+
+/// Per-file operations offered by the submenu opened with Right-arrow
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FileAction {
+    Preview,
+    Dedup,
+    Validate,
+    Rename,
+    Delete,
+}
+
+impl FileAction {
+    pub const ALL: [FileAction; 5] = [
+        FileAction::Preview,
+        FileAction::Dedup,
+        FileAction::Validate,
+        FileAction::Rename,
+        FileAction::Delete,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            FileAction::Preview => "Просмотр",
+            FileAction::Dedup => "Удалить дубликаты",
+            FileAction::Validate => "Проверить домены",
+            FileAction::Rename => "Переименовать",
+            FileAction::Delete => "Удалить файл",
+        }
+    }
+
+    /// Actions that change the list file on disk, refused with --review
+    pub fn modifies_file(self) -> bool {
+        matches!(self, FileAction::Dedup | FileAction::Rename | FileAction::Delete)
+    }
+}
+
+/// What the event loop is currently doing. Every mode except `List` remembers the
+/// file it was opened for, so it keeps operating on it even if the list changes.
+#[derive(Debug)]
+pub enum Mode {
+    List,
+    Submenu { file: String, cursor: usize },
+    /// cursor is the line being edited after Tab, None while only scrolling. While D shows the
+    /// file as deduplication would leave it, lines are that and file_lines what is on disk.
+    Preview {
+        file: String,
+        lines: Vec<String>,
+        size: u64,
+        scroll: usize,
+        cursor: Option<usize>,
+        file_lines: Option<Vec<String>>,
+    },
+    Report { title: String, lines: Vec<String>, scroll: usize },
+    /// Summary of what SAVE is about to do, confirmed with Enter
+    ConfirmSave { lines: Vec<String>, scroll: usize },
+    Rename { file: String, input: String },
+    /// Typing the path W writes the merged selection to
+    WriteAs { input: String },
+    /// Typing the filter query opened with /
+    Search,
+    /// A draft newer than selected.txt was found on startup, asking whether to restore it
+    RestoreDraft { names: Vec<String> },
+    /// A page of the setup wizard, cursor is the choice on the template page
+    Wizard { step: wizard::Step, cursor: usize },
+    ConfirmDelete { file: String },
+    /// Z or I would change more lists than --confirm-over, names is the selection to set:
+    /// the saved one to go back to, or with inverse the lists that aren't in it
+    ConfirmReset { names: Vec<String>, changes: usize, inverse: bool },
+    /// Bulk renames to the naming convention, Enter renames the files in renames
+    ConfirmNormalize { renames: Vec<(String, String)>, lines: Vec<String>, scroll: usize },
+    /// Per-file duplicates of the selected lists, Enter removes them from every file in files
+    ConfirmDedupAll { files: Vec<String>, lines: Vec<String>, scroll: usize },
+    /// The lists marked with X, Enter deletes every file in files
+    ConfirmDeleteMarked { files: Vec<String>, lines: Vec<String>, scroll: usize },
+}
+
+/// Two saved selections shown side by side with --diff
+pub struct Comparison {
+    /// File names for the header, A first
+    pub names: (String, String),
+    pub a: HashSet<String>,
+    pub b: HashSet<String>,
+}
+
+impl Comparison {
+    /// "A  ", "  B", "A+B" or blanks for a list in neither
+    pub fn tag(&self, name: &str) -> &'static str {
+        match (self.a.contains(name), self.b.contains(name)) {
+            (true, true) => "A+B",
+            (true, false) => "A  ",
+            (false, true) => "  B",
+            (false, false) => "   ",
+        }
+    }
+}
+
+pub struct App {
+    pub args: Args,
+    pub lists_dir: PathBuf,
+    /// Absolute lists_dir for the header breadcrumb
+    pub lists_dir_display: String,
+    pub config_path: PathBuf,
+    pub entries: Vec<FileEntry>,
+    pub current_index: usize,
+    pub scroll_offset: usize,
+    pub status: Option<String>,
+    pub mode: Mode,
+    /// Show selected lists above the unselected ones instead of plain alphabetical order
+    pub sort_by_selection: bool,
+    /// The user reordered lists with Shift+↑↓, their order is kept as is and is the merge order,
+    /// which only shows in a sorted output through the per-list numbers
+    pub custom_order: bool,
+    /// Only lists whose names contain this text are shown,
+    /// or with a leading & the lists containing a matching domain
+    pub filter: String,
+    /// Match names exactly as typed. Content search ignores it: domains are stored lowercase.
+    pub case_sensitive: bool,
+    pub content: ContentIndex,
+    /// Matching domains per list for the current content search
+    pub match_counts: HashMap<String, usize>,
+    /// A file operation is running, keys typed meanwhile are filtered once it's done
+    pub busy: bool,
+    /// Characters hidden at the start of every list name, scrolled with Shift+←→
+    pub name_offset: usize,
+    /// Recently toggled lists, newest first, persisted in the state file
+    pub recent: Vec<String>,
+    /// Position in the "Недавние" section when the cursor is there; current_index then
+    /// points at the same list in the main part, so every action applies to the file itself
+    pub recent_cursor: Option<usize>,
+    /// Lists that were in a saved selection at least once (state::EVER_SAVED plus the current one)
+    pub ever_saved: HashSet<String>,
+    /// Number of saved selections each list was in (state::SAVE_COUNTS), lists never saved are missing
+    pub save_counts: HashMap<String, usize>,
+    /// Show save_counts as "×12" before the names (C)
+    pub show_save_counts: bool,
+    /// Long preview lines wrap onto the next rows instead of ending in "…" (W in the preview)
+    pub preview_wrap: bool,
+    /// Audit view: only show the lists that never were in a saved selection
+    pub audit: bool,
+    /// Starred lists, persisted in favorites::FAVORITES_FILE independently of the selection
+    pub favorites: HashSet<String>,
+    /// Only show the starred lists
+    pub favorites_only: bool,
+    /// Only show the selected lists, for a last look at the chosen set (H)
+    pub selected_only: bool,
+    /// Lists marked with X for the batch delete (Delete), independent of the selection
+    pub marked: HashSet<String>,
+    /// Set with --diff, the list rows then show A / B instead of the selection
+    pub compare: Option<Comparison>,
+    /// Why the merged output can't be written, found at startup and shown above the lists
+    /// for the whole session instead of surprising at SAVE
+    pub output_warning: Option<String>,
+    /// Lists whose domain_count the --lazy-counts thread hasn't delivered yet
+    pub pending_counts: HashSet<String>,
+    pub count_receiver: Option<Receiver<(String, Option<usize>)>>,
+    pub timings: Rc<Timings>,
+    /// (selected lists, their domains) per category for the footer, adjusted on every change
+    /// instead of summing all lists on each draw. Rebuilt by recount_selected.
+    pub selected_domains: BTreeMap<String, (usize, usize)>,
+    pub count_display: CountDisplay,
+    /// In the selection step of the wizard: a banner above the lists, ← goes back to the templates
+    pub wizard: bool,
+    /// Remapped list view keys from [keys] in selector.toml
+    pub keymap: Keymap,
+}
+
+/// A row the cursor can be on
+#[derive(Clone, Copy, PartialEq)]
+enum Row {
+    Entry(usize),
+    Recent(usize),
+}
+
+impl App {
+    pub fn num_control_entries(&self) -> usize {
+        self.entries.iter().filter(|e| e.is_control()).count()
+    }
+
+    /// The control row under the cursor, None on a list or when there are no rows at all
+    pub fn current_control(&self) -> Option<ControlRow> {
+        self.entries.get(self.current_index).and_then(|e| e.control)
+    }
+
+    /// The single place that moves the cursor to an entry: out of range indices are clamped
+    /// and the viewport follows. Any change to entries should end up here or in clamp_cursor.
+    pub fn set_cursor(&mut self, index: usize) {
+        self.current_index = index.min(self.entries.len().saturating_sub(1));
+        self.ensure_visible();
+    }
+
+    /// The highlighted entry if it is a list file (not SAVE/CANCEL). None when the filter
+    /// hides every list and the cursor is only parked on a hidden one.
+    pub fn current_file(&self) -> Option<&FileEntry> {
+        self.entries.get(self.current_index).filter(|e| !e.is_control() && self.matches_filter(e))
+    }
+
+    /// Names of the selected lists in display order, which is also the merge order
+    pub fn selected_names(&self) -> Vec<&str> {
+        self.entries.iter()
+            .filter(|e| e.selected && !e.is_control())
+            .map(|e| e.name.as_str())
+            .collect()
+    }
+
+    /// Rebuild selected_domains after the selection was replaced as a whole (load, reset, draft)
+    pub fn recount_selected(&mut self) {
+        self.selected_domains.clear();
+        for index in 0..self.entries.len() {
+            if self.entries[index].selected {
+                self.tally(index, true);
+            }
+        }
+    }
+
+    /// Domains of all selected lists, what the footer shows and the shares are taken of
+    pub fn selected_total(&self) -> usize {
+        self.selected_domains.values().map(|(_, domains)| domains).sum()
+    }
+
+    /// Add the selected entry at index to selected_domains, or take it away
+    fn tally(&mut self, index: usize, add: bool) {
+        let entry = &self.entries[index];
+        if entry.is_control() {
+            return;
+        }
+        let category = file_ops::category_of(&entry.name, &self.args.prefix, &self.args.suffix)
+            .unwrap_or(OTHER_CATEGORY);
+        let domains = entry.domain_count.unwrap_or(0);
+        let (lists, total) = self.selected_domains.entry(category.to_string()).or_default();
+        if add {
+            *lists += 1;
+            *total += domains;
+        } else {
+            *lists -= 1;
+            *total -= domains;
+            if *lists == 0 {
+                self.selected_domains.remove(category);
+            }
+        }
+    }
+
+    /// Whether the selection differs from the one loaded or last reset (the S column)
+    pub fn is_dirty(&self) -> bool {
+        self.entries.iter().any(|e| !e.is_control() && e.selected != e.saved)
+    }
+
+    pub fn file_position(&self, name: &str) -> Option<usize> {
+        self.entries.iter().position(|e| !e.is_control() && e.name == name)
+    }
+
+    /// The query of a content search ("&youtube"), None for a name filter or an empty query
+    pub fn content_query(&self) -> Option<String> {
+        self.filter.strip_prefix(CONTENT_PREFIX)
+            .filter(|query| !query.is_empty())
+            .map(str::to_lowercase)
+    }
+
+    /// The text to highlight in previews, without the content search marker
+    pub fn highlight_term(&self) -> &str {
+        self.filter.strip_prefix(CONTENT_PREFIX).unwrap_or(&self.filter)
+    }
+
+    pub fn matches_filter(&self, entry: &FileEntry) -> bool {
+        if entry.is_control() {
+            return true;
+        }
+        if self.audit && self.ever_saved.contains(&entry.name) {
+            return false;
+        }
+        if self.favorites_only && !self.favorites.contains(&entry.name) {
+            return false;
+        }
+        if self.selected_only && !entry.selected {
+            return false;
+        }
+        if self.highlight_term().is_empty() {
+            return true;
+        }
+
+        if self.content_query().is_some() {
+            self.match_counts.get(&entry.name).is_some_and(|&count| count > 0)
+        } else {
+            if self.case_sensitive {
+                entry.name.contains(&self.filter)
+            } else {
+                entry.name.to_lowercase().contains(&self.filter.to_lowercase())
+            }
+        }
+    }
+
+    /// Indices into entries of the list files currently shown
+    pub fn visible_files(&self) -> Vec<usize> {
+        self.entries.iter()
+            .enumerate()
+            .filter(|(_, entry)| !entry.is_control() && self.matches_filter(entry))
+            .map(|(index, _)| index)
+            .collect()
+    }
+
+    /// Entry indices of the lists in the "Недавние" section, empty when it's hidden
+    pub fn recent_rows(&self) -> Vec<usize> {
+        if self.args.no_recent || !self.filter.is_empty() || self.audit || self.favorites_only || self.selected_only {
+            return Vec::new();
+        }
+        self.recent.iter().filter_map(|name| self.file_position(name)).collect()
+    }
+
+    /// List rows that fit in the scroll area below the "Недавние" section
+    pub fn page_size(&self) -> usize {
+        match self.recent_rows().len() {
+            0 => VISIBLE_ITEMS,
+            recent => VISIBLE_ITEMS - recent - 1, // +1 for the section title
+        }
+    }
+
+    /// Rows the cursor can land on: control rows, the recent section, then the visible files
+    fn navigable_rows(&self) -> Vec<Row> {
+        let recent = (0..self.recent_rows().len()).map(Row::Recent);
+        (0..self.num_control_entries()).map(Row::Entry)
+            .chain(recent)
+            .chain(self.visible_files().into_iter().map(Row::Entry))
+            .collect()
+    }
+
+    fn current_row(&self) -> Row {
+        match self.recent_cursor {
+            Some(pos) => Row::Recent(pos),
+            None => Row::Entry(self.current_index),
+        }
+    }
+
+    fn set_row(&mut self, row: Row) {
+        match row {
+            Row::Entry(index) => {
+                self.recent_cursor = None;
+                self.set_cursor(index);
+            }
+            Row::Recent(pos) => {
+                self.recent_cursor = Some(pos);
+                self.set_cursor(self.recent_rows()[pos]);
+            }
+        }
+    }
+
+    /// False at the top, the cursor doesn't wrap
+    pub fn move_up(&mut self) -> bool {
+        let rows = self.navigable_rows();
+        match rows.iter().position(|&row| row == self.current_row()) {
+            Some(pos) if pos > 0 => {
+                self.set_row(rows[pos - 1]);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// False at the bottom
+    pub fn move_down(&mut self) -> bool {
+        let rows = self.navigable_rows();
+        match rows.iter().position(|&row| row == self.current_row()) {
+            Some(pos) if pos + 1 < rows.len() => {
+                self.set_row(rows[pos + 1]);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Type-ahead: move to the next visible list whose name, without the common prefix, starts
+    /// with letter, wrapping around. Repeating the letter cycles through the matches.
+    pub fn jump_to_letter(&mut self, letter: char) -> bool {
+        let visible = self.visible_files();
+        let start = match self.recent_cursor {
+            Some(_) => 0,
+            None => visible.iter().position(|&index| index == self.current_index).map_or(0, |pos| pos + 1),
+        };
+        let letter = letter.to_lowercase().collect::<String>();
+
+        let found = (0..visible.len())
+            .map(|offset| visible[(start + offset) % visible.len()])
+            .find(|&index| {
+                let name = &self.entries[index].name;
+                name.strip_prefix(self.args.prefix.as_str()).unwrap_or(name).to_lowercase().starts_with(&letter)
+            });
+        match found {
+            Some(index) => {
+                self.set_row(Row::Entry(index));
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Adjust scroll_offset so the highlighted file is inside the viewport
+    pub fn ensure_visible(&mut self) {
+        let visible = self.visible_files();
+        let page_size = self.page_size();
+        self.scroll_offset = self.scroll_offset.min(visible.len().saturating_sub(page_size));
+        if self.recent_cursor.is_some() {
+            return;
+        }
+
+        let Some(file_index) = visible.iter().position(|&index| index == self.current_index) else {
+            return;
+        };
+        if self.scroll_offset > file_index {
+            self.scroll_offset = file_index;
+        } else if file_index >= self.scroll_offset + page_size {
+            self.scroll_offset = file_index - page_size + 1;
+        }
+    }
+
+    /// Keep the cursor on an existing, visible row; a hidden file moves it to the first visible one
+    pub fn clamp_cursor(&mut self) {
+        if self.entries.is_empty() {
+            // No lists and the action rows are hidden
+            self.current_index = 0;
+            self.scroll_offset = 0;
+            return;
+        }
+
+        self.current_index = self.current_index.min(self.entries.len() - 1);
+        let recent_len = self.recent_rows().len();
+        match self.recent_cursor {
+            Some(pos) if pos < recent_len => self.current_index = self.recent_rows()[pos],
+            _ => self.recent_cursor = None,
+        }
+
+        let current_hidden = !self.matches_filter(&self.entries[self.current_index]);
+        if current_hidden {
+            self.current_index = self.visible_files().first().copied().unwrap_or(0);
+        }
+        self.ensure_visible();
+    }
+
+    /// Call after the filter changed
+    pub fn apply_filter(&mut self) {
+        self.scroll_offset = 0;
+        self.refresh_filter();
+    }
+
+    /// Switch the audit view on or off, returns how many lists it shows when on
+    pub fn toggle_audit(&mut self) -> usize {
+        self.audit = !self.audit;
+        self.recent_cursor = None;
+        self.scroll_offset = 0;
+        self.clamp_cursor();
+        self.visible_files().len()
+    }
+
+    /// Switch to showing only the selected lists or back, returns how many lists it shows when on.
+    /// A list deselected meanwhile disappears, the cursor moves to a visible one.
+    pub fn toggle_selected_only(&mut self) -> usize {
+        self.selected_only = !self.selected_only;
+        self.recent_cursor = None;
+        self.scroll_offset = 0;
+        self.clamp_cursor();
+        self.visible_files().len()
+    }
+
+    /// Star or unstar the highlighted list, its selection stays as it is. Returns whether it's
+    /// starred now, None without a list under the cursor.
+    pub fn toggle_favorite(&mut self) -> Option<errors::Result<bool>> {
+        let name = self.current_file()?.name.clone();
+        let starred = !self.favorites.remove(&name);
+        if starred {
+            self.favorites.insert(name);
+        }
+        // Unstarring in the favorites view hides the list, the cursor moves to a visible one
+        self.clamp_cursor();
+        Some(favorites::write(self.args.state_dir(), &self.favorites).map(|()| starred))
+    }
+
+    /// Mark the highlighted list for the batch delete or unmark it. Returns whether it's marked
+    /// now, None without a list under the cursor.
+    pub fn toggle_marked(&mut self) -> Option<bool> {
+        let name = self.current_file()?.name.clone();
+        let marked = !self.marked.remove(&name);
+        if marked {
+            self.marked.insert(name);
+        }
+        Some(marked)
+    }
+
+    /// Switch the favorites view on or off, returns how many lists it shows when on
+    pub fn toggle_favorites_only(&mut self) -> usize {
+        self.favorites_only = !self.favorites_only;
+        self.recent_cursor = None;
+        self.scroll_offset = 0;
+        self.clamp_cursor();
+        self.visible_files().len()
+    }
+
+    /// A star follows its list through a rename, a failed write only loses the change of name
+    fn rename_favorite(&mut self, old_name: &str, new_name: &str) {
+        if self.favorites.remove(old_name) {
+            self.favorites.insert(new_name.to_string());
+            let _ = favorites::write(self.args.state_dir(), &self.favorites);
+        }
+    }
+
+    /// Recount content search matches, loading the lists that aren't cached yet.
+    /// Also called when a loader delivers more files.
+    pub fn refresh_filter(&mut self) {
+        self.match_counts.clear();
+        if let Some(query) = self.content_query() {
+            let names: Vec<String> = self.entries.iter()
+                .filter(|e| !e.is_control())
+                .map(|e| e.name.clone())
+                .collect();
+            self.content.request(&self.lists_dir, &names);
+
+            for name in names {
+                if let Some(count) = self.content.count(&name, &query) {
+                    self.match_counts.insert(name, count);
+                }
+            }
+        }
+
+        self.clamp_cursor();
+    }
+
+    /// Re-read the domain count of a list that was changed on disk
+    pub fn recount(&mut self, name: &str) {
+        if let Some(index) = self.file_position(name) {
+            self.pending_counts.remove(name);
+            self.set_count(index, counts::count(&self.lists_dir, name));
+        }
+        self.invalidate_content(name);
+    }
+
+    /// Count a list right away if the background thread hasn't got to it yet
+    pub fn ensure_count(&mut self, index: usize) {
+        if self.pending_counts.remove(&self.entries[index].name) {
+            self.set_count(index, counts::count(&self.lists_dir, &self.entries[index].name));
+        }
+    }
+
+    /// Take in the counts the --lazy-counts thread finished. Returns true if anything arrived.
+    pub fn poll_counts(&mut self) -> bool {
+        let Some(receiver) = &self.count_receiver else {
+            return false;
+        };
+        let arrived: Vec<(String, Option<usize>)> = receiver.try_iter().collect();
+        let mut received = false;
+        for (name, count) in arrived {
+            // Counted meanwhile on demand, or renamed: the result is stale
+            if !self.pending_counts.remove(&name) {
+                continue;
+            }
+            if let Some(index) = self.file_position(&name) {
+                self.set_count(index, count);
+                received = true;
+            }
+        }
+        if self.pending_counts.is_empty() {
+            self.count_receiver = None;
+        }
+        received
+    }
+
+    fn set_count(&mut self, index: usize, count: Option<usize>) {
+        let selected = self.entries[index].selected;
+        if selected {
+            self.tally(index, false);
+        }
+        self.entries[index].domain_count = count;
+        if selected {
+            self.tally(index, true);
+        }
+    }
+
+    /// Drop cached contents of a list that was changed on disk
+    pub fn invalidate_content(&mut self, name: &str) {
+        self.content.invalidate(name);
+        self.refresh_filter();
+    }
+
+    /// Scroll the names sideways by a fixed step, never past the end of the longest name
+    pub fn scroll_names(&mut self, right: bool) {
+        const STEP: usize = 8;
+        if right {
+            let longest = self.entries.iter()
+                .filter(|e| !e.is_control())
+                .map(|e| e.name.chars().count())
+                .max()
+                .unwrap_or(0);
+            if self.name_offset + STEP < longest {
+                self.name_offset += STEP;
+            }
+        } else {
+            self.name_offset = self.name_offset.saturating_sub(STEP);
+        }
+    }
+
+    /// Flip the selection of the highlighted list. When the order depends on the selection
+    /// the entry moves, and the cursor follows it instead of staying at the old index.
+    pub fn toggle_current(&mut self) {
+        if self.current_file().is_none() {
+            return;
+        }
+        self.ensure_count(self.current_index);
+        let entry = &mut self.entries[self.current_index];
+
+        entry.selected = !entry.selected;
+        let (name, selected) = (entry.name.clone(), entry.selected);
+        self.tally(self.current_index, selected);
+        if self.sort_by_selection {
+            self.resort();
+        }
+        self.touch_recent(&name);
+    }
+
+    /// Move a list to the front of the recent ones. The cursor stays on it when it was in the section.
+    fn touch_recent(&mut self, name: &str) {
+        self.recent.retain(|recent| recent != name);
+        self.recent.insert(0, name.to_string());
+        self.recent.truncate(RECENT_MAX);
+        if self.recent_cursor.is_some() {
+            self.recent_cursor = Some(0);
+        }
+        self.save_recent();
+        self.clamp_cursor();
+    }
+
+    fn save_recent(&self) {
+        // Names can't contain '/', so it works as a separator. Losing this is harmless,
+        // a failed write shouldn't interrupt toggling.
+        let _ = state::set(self.args.state_dir(), state::RECENT, &self.recent.join("/"));
+    }
+
+    /// Re-sort the list files in the current order, keeping the cursor on the same entry
+    pub fn resort(&mut self) {
+        let current_name = self.current_file().map(|e| e.name.clone());
+        let num_control_entries = self.num_control_entries();
+
+        let files = &mut self.entries[num_control_entries..];
+        if self.custom_order {
+            // Order is user-defined, nothing to sort
+        } else if self.sort_by_selection {
+            files.sort_by(|a, b| b.selected.cmp(&a.selected).then_with(|| a.name.cmp(&b.name)));
+        } else {
+            files.sort_by(|a, b| a.name.cmp(&b.name));
+        }
+
+        match current_name.and_then(|name| self.file_position(&name)) {
+            Some(index) => self.set_cursor(index),
+            None => self.ensure_visible(),
+        }
+    }
+
+    /// Swap the highlighted list with its neighbour (Shift+↑↓). SAVE/CANCEL stay pinned.
+    /// Returns false when the entry can't move further.
+    pub fn move_current(&mut self, up: bool) -> bool {
+        // Neighbours are taken among the visible files, hidden ones are skipped over
+        let visible = self.visible_files();
+        let Some(pos) = visible.iter().position(|&index| index == self.current_index) else {
+            return false;
+        };
+
+        let target = if up {
+            if pos == 0 {
+                return false;
+            }
+            visible[pos - 1]
+        } else {
+            if pos + 1 >= visible.len() {
+                return false;
+            }
+            visible[pos + 1]
+        };
+
+        self.entries.swap(self.current_index, target);
+        self.custom_order = true;
+        self.sort_by_selection = false;
+        self.set_cursor(target);
+        true
+    }
+
+    pub fn remove_file(&mut self, name: &str) {
+        if let Some(index) = self.file_position(name) {
+            if self.entries[index].selected {
+                self.tally(index, false);
+            }
+            self.entries.remove(index);
+        }
+        if self.recent.iter().any(|recent| recent == name) {
+            self.recent.retain(|recent| recent != name);
+            self.save_recent();
+        }
+        if self.favorites.remove(name) {
+            let _ = favorites::write(self.args.state_dir(), &self.favorites);
+        }
+        self.marked.remove(name);
+
+        self.invalidate_content(name);
+    }
+
+    /// Follow a bulk rename on disk. Renamed lists keep their state, files that only became
+    /// lists through the rename are added as unselected.
+    pub fn apply_renames(&mut self, renames: &[(String, String)]) {
+        for (old_name, new_name) in renames {
+            match self.file_position(old_name) {
+                Some(index) => self.set_name(index, new_name),
+                None => {
+                    self.entries.push(FileEntry {
+                        name: new_name.clone(),
+                        selected: false,
+                        saved: false,
+                        control: None,
+                        domain_count: counts::count(&self.lists_dir, new_name),
+                        changed_recently: false,
+                    });
+                }
+            }
+            if let Some(recent) = self.recent.iter_mut().find(|recent| *recent == old_name) {
+                *recent = new_name.clone();
+            }
+            self.rename_favorite(old_name, new_name);
+            self.content.invalidate(old_name);
+        }
+        self.save_recent();
+        self.resort();
+        self.refresh_filter();
+        self.clamp_cursor();
+    }
+
+    /// The category may change with the name, so a selected entry is counted again
+    fn set_name(&mut self, index: usize, name: &str) {
+        let selected = self.entries[index].selected;
+        if selected {
+            self.tally(index, false);
+        }
+        // The background result would come under the old name and be dropped
+        if self.pending_counts.remove(&self.entries[index].name) {
+            self.entries[index].domain_count = counts::count(&self.lists_dir, name);
+        }
+        if self.marked.remove(&self.entries[index].name) {
+            self.marked.insert(name.to_string());
+        }
+        self.entries[index].name = name.to_string();
+        if selected {
+            self.tally(index, true);
+        }
+    }
+
+    /// Rename an entry, keeping the list sorted and the cursor on it
+    pub fn rename_file(&mut self, old_name: &str, new_name: &str) {
+        let Some(index) = self.file_position(old_name) else {
+            return;
+        };
+
+        self.set_name(index, new_name);
+        if let Some(recent) = self.recent.iter_mut().find(|recent| *recent == old_name) {
+            *recent = new_name.to_string();
+            self.save_recent();
+        }
+        self.rename_favorite(old_name, new_name);
+        self.set_cursor(index);
+        self.resort();
+        self.invalidate_content(old_name);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scratch;
+
+    /// An app on a scratch folder with the given (name, selected, domains) lists, in that order.
+    /// Nothing is read from the folder, it only keeps the state file out of the working directory.
+    fn app(test: &str, lists: &[(&str, bool, usize)], action_rows: bool) -> App {
+        let dir = scratch::dir(test);
+        let args = Args { dir: dir.clone(), no_action_rows: !action_rows, ..Args::default() };
+        let mut entries = if action_rows {
+            vec![FileEntry::control(ControlRow::Save), FileEntry::control(ControlRow::Cancel)]
+        } else {
+            Vec::new()
+        };
+        entries.extend(lists.iter().map(|&(name, selected, domains)| FileEntry {
+            name: name.to_string(),
+            selected,
+            saved: selected,
+            control: None,
+            domain_count: Some(domains),
+            changed_recently: false,
+        }));
+        let mut app = App {
+            args,
+            lists_dir: dir.clone(),
+            lists_dir_display: dir.display().to_string(),
+            config_path: dir.join("selected.txt"),
+            entries,
+            current_index: 0,
+            scroll_offset: 0,
+            status: None,
+            mode: Mode::List,
+            sort_by_selection: false,
+            custom_order: false,
+            filter: String::new(),
+            case_sensitive: false,
+            content: ContentIndex::new(),
+            match_counts: HashMap::new(),
+            busy: false,
+            name_offset: 0,
+            recent: Vec::new(),
+            recent_cursor: None,
+            ever_saved: HashSet::new(),
+            save_counts: HashMap::new(),
+            show_save_counts: false,
+            preview_wrap: false,
+            audit: false,
+            favorites: HashSet::new(),
+            favorites_only: false,
+            selected_only: false,
+            marked: HashSet::new(),
+            compare: None,
+            output_warning: None,
+            pending_counts: HashSet::new(),
+            count_receiver: None,
+            timings: Rc::new(Timings::new(false)),
+            selected_domains: BTreeMap::new(),
+            count_display: CountDisplay::Hidden,
+            wizard: false,
+            keymap: Keymap::default(),
+        };
+        app.recount_selected();
+        app
+    }
+
+    fn names(app: &App) -> Vec<&str> {
+        app.entries.iter().filter(|e| !e.is_control()).map(|e| e.name.as_str()).collect()
+    }
+
+    #[test]
+    fn the_cursor_follows_a_toggled_list_that_moves() {
+        let lists = [("list-a.txt", false, 1), ("list-b.txt", true, 1), ("list-c.txt", false, 1)];
+        let mut app = app("toggle-resort", &lists, true);
+        app.sort_by_selection = true;
+        app.resort();
+        assert_eq!(names(&app), ["list-b.txt", "list-a.txt", "list-c.txt"]);
+
+        // list-c.txt goes up next to list-b.txt
+        app.set_cursor(app.file_position("list-c.txt").unwrap());
+        app.toggle_current();
+        assert_eq!(names(&app), ["list-b.txt", "list-c.txt", "list-a.txt"]);
+        assert_eq!(app.current_file().map(|e| e.name.as_str()), Some("list-c.txt"));
+
+        // list-b.txt goes down below the selected ones
+        app.set_cursor(app.file_position("list-b.txt").unwrap());
+        app.toggle_current();
+        assert_eq!(names(&app), ["list-c.txt", "list-a.txt", "list-b.txt"]);
+        assert_eq!(app.current_file().map(|e| e.name.as_str()), Some("list-b.txt"));
+        assert_eq!(app.recent_cursor, None);
+    }
+
+    #[test]
+    fn an_empty_folder_without_action_rows_has_no_cursor_row() {
+        let mut app = app("cursor-empty", &[], false);
+        app.set_cursor(5);
+        assert_eq!(app.current_index, 0);
+        app.clamp_cursor();
+        assert_eq!((app.current_index, app.scroll_offset), (0, 0));
+        assert_eq!(app.current_control(), None);
+        assert!(app.current_file().is_none());
+        assert!(!app.move_down());
+    }
+
+    #[test]
+    fn set_cursor_clamps_to_the_last_entry() {
+        let mut app = app("cursor-clamp", &[("list-a.txt", false, 1), ("list-b.txt", false, 1)], true);
+        app.set_cursor(10);
+        assert_eq!(app.current_index, 3);
+        assert_eq!(app.current_file().map(|e| e.name.as_str()), Some("list-b.txt"));
+        app.set_cursor(0);
+        assert_eq!(app.current_control(), Some(ControlRow::Save));
+        assert!(app.current_file().is_none());
+    }
+
+    #[test]
+    fn a_filter_matching_nothing_leaves_no_current_file() {
+        let lists = [("list-a.txt", false, 1), ("list-b.txt", false, 1)];
+        let mut app = app("cursor-filter", &lists, true);
+        app.set_cursor(3);
+        app.filter = String::from("zzz");
+        app.apply_filter();
+        // Back on SAVE, the only rows left
+        assert_eq!(app.current_index, 0);
+        assert_eq!(app.current_control(), Some(ControlRow::Save));
+        assert!(app.current_file().is_none());
+
+        let mut app = self::app("cursor-filter-bare", &lists, false);
+        app.set_cursor(1);
+        app.filter = String::from("zzz");
+        app.apply_filter();
+        // Parked on a hidden list, which is neither a control row nor the current file
+        assert_eq!(app.current_index, 0);
+        assert_eq!(app.current_control(), None);
+        assert!(app.current_file().is_none());
+    }
+
+    #[test]
+    fn deleting_the_last_entry_moves_the_cursor_up() {
+        let lists = [("list-a.txt", false, 1), ("list-b.txt", true, 1)];
+        let mut app = app("cursor-delete", &lists, true);
+        app.set_cursor(3);
+        app.remove_file("list-b.txt");
+        app.clamp_cursor();
+        assert_eq!(app.current_index, 2);
+        assert_eq!(app.current_file().map(|e| e.name.as_str()), Some("list-a.txt"));
+        app.remove_file("list-a.txt");
+        app.clamp_cursor();
+        assert_eq!(app.current_index, 1);
+        assert_eq!(app.current_control(), Some(ControlRow::Cancel));
+
+        let mut app = self::app("cursor-delete-bare", &lists[..1], false);
+        app.remove_file("list-a.txt");
+        app.clamp_cursor();
+        assert_eq!(app.current_index, 0);
+        assert_eq!(app.current_control(), None);
+        assert!(app.current_file().is_none());
+    }
+
+    #[test]
+    fn the_cursor_stays_valid_through_filter_delete_and_rescan() {
+        let lists = [("list-a.txt", false, 1), ("list-b.txt", false, 1), ("list-c.txt", false, 1)];
+        let mut app = app("cursor-sequence", &lists, true);
+        app.filter = String::from("c");
+        app.apply_filter();
+        app.set_cursor(app.file_position("list-c.txt").unwrap());
+
+        // The only visible list goes, the cursor must not stay on a hidden one
+        app.remove_file("list-c.txt");
+        app.clamp_cursor();
+        assert!(app.current_file().is_none());
+        assert_eq!(app.current_control(), Some(ControlRow::Save));
+
+        app.filter.clear();
+        app.apply_filter();
+        assert_eq!(app.current_control(), Some(ControlRow::Save));
+        assert!(app.move_down() && app.move_down());
+        assert_eq!(app.current_file().map(|e| e.name.as_str()), Some("list-a.txt"));
+
+        // A rename that brings in a new list, like a rescan of the folder
+        app.set_cursor(app.file_position("list-b.txt").unwrap());
+        app.apply_renames(&[(String::from("other.txt"), String::from("list-0.txt"))]);
+        assert_eq!(names(&app), ["list-0.txt", "list-a.txt", "list-b.txt"]);
+        assert_eq!(app.current_file().map(|e| e.name.as_str()), Some("list-b.txt"));
+        assert_eq!(app.num_control_entries(), 2);
+    }
+
+    /// The running totals against the ones rebuilt from scratch
+    fn assert_totals_match(app: &mut App) {
+        let running = app.selected_domains.clone();
+        app.recount_selected();
+        assert_eq!(running, app.selected_domains);
+    }
+
+    #[test]
+    fn running_totals_survive_toggles_renames_and_removals() {
+        let lists = [
+            ("list-misc.txt", false, 3),
+            ("list-social-tg.txt", false, 5),
+            ("list-social-vk.txt", true, 10),
+            ("list-video-yt.txt", true, 7),
+        ];
+        let mut app = app("running-totals", &lists, true);
+        assert_eq!(app.selected_total(), 17);
+
+        app.set_cursor(app.file_position("list-social-tg.txt").unwrap());
+        app.toggle_current();
+        assert_totals_match(&mut app);
+        assert_eq!(app.selected_domains["social"], (2, 15));
+
+        // Into another category
+        app.rename_file("list-social-vk.txt", "list-video-vk.txt");
+        assert_totals_match(&mut app);
+        assert_eq!(app.selected_domains["video"], (2, 17));
+
+        app.set_cursor(app.file_position("list-video-yt.txt").unwrap());
+        app.toggle_current();
+        assert_totals_match(&mut app);
+
+        app.remove_file("list-social-tg.txt");
+        app.clamp_cursor();
+        assert_totals_match(&mut app);
+        assert!(!app.selected_domains.contains_key("social"));
+
+        app.apply_renames(&[(String::from("list-misc.txt"), String::from("list-social-misc.txt"))]);
+        app.set_cursor(app.file_position("list-social-misc.txt").unwrap());
+        app.toggle_current();
+        assert_totals_match(&mut app);
+        app.remove_file("list-video-yt.txt");
+        assert_totals_match(&mut app);
+
+        assert_eq!(app.selected_total(), 13);
+        assert_eq!(app.selected_domains.len(), 2);
+    }
+}
@@ -0,0 +1,26 @@
+//! CRC-32 (IEEE) fingerprint of the merged output, stored in the state file to notice later edits
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+pub fn file_crc32(path: &Path) -> io::Result<u32> {
+    Ok(crc32(&fs::read(path)?))
+}
+
+/// The form shown to users and kept in the state file
+pub fn format(crc: u32) -> String {
+    format!("{:08x}", crc)
+}
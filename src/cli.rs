@@ -0,0 +1,498 @@
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use crate::encoding::Encoding;
+use crate::glob::Pattern;
+use crate::log::Verbosity;
+use crate::merge::{Dnsmasq, LineEnding, OutputFormat, Semantics, SortOrder};
+use crate::template::{self, Template};
+use crate::width;
+
+/// Environment variable with the default lists folder, used when --dir isn't given
+pub const LISTS_DIR_ENV: &str = "ZAPRET_LISTS_DIR";
+
+/// Where the lists folder setting came from, reported by --doctor
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DirSource {
+    Flag,
+    Env,
+    Default,
+}
+
+impl DirSource {
+    pub fn describe(self) -> &'static str {
+        match self {
+            DirSource::Flag => "параметр --dir",
+            DirSource::Env => "переменная окружения ZAPRET_LISTS_DIR",
+            DirSource::Default => "по умолчанию",
+        }
+    }
+}
+
+/// Glyphs of the list rows: "> [*] name", set with --cursor-marker and friends
+#[derive(Debug, Clone, Copy)]
+pub struct Markers {
+    pub cursor: char,
+    pub selected: char,
+    pub unselected: char,
+}
+
+impl Default for Markers {
+    fn default() -> Self {
+        Markers { cursor: '>', selected: '*', unselected: ' ' }
+    }
+}
+
+/// Command line options. Everything is optional, running without flags behaves as before.
+#[derive(Debug)]
+pub struct Args {
+    /// Seconds to keep the result message on screen after SAVE, 0 exits right away
+    pub save_delay: u64,
+    /// Seconds without input before the selection is written to the draft, 0 disables drafts
+    pub draft_interval: u64,
+    /// Bulk changes touching more lists than this ask y/n first, 0 asks for any change
+    pub confirm_over: usize,
+    /// Count the domains of the lists in the background after startup instead of before it
+    pub lazy_counts: bool,
+    /// Lists modified within this many hours are highlighted, 0 turns it off
+    pub changed_hours: u64,
+    /// Hidden: print how long scanning, counting and merging took to stderr on exit
+    pub timings: bool,
+    /// How long the event loop waits for input before checking background work, in milliseconds
+    pub poll_ms: u64,
+    /// Folder with the list files, --dir > ZAPRET_LISTS_DIR > "lists"
+    pub dir: PathBuf,
+    pub dir_source: DirSource,
+    /// --state-dir: where the selection, .selector_state, the draft and the favorites are kept,
+    /// None keeps them in the lists folder
+    pub state_dir: Option<PathBuf>,
+    /// Only files named <prefix>*<suffix> are treated as lists
+    pub prefix: String,
+    pub suffix: String,
+    /// --all: any file with the suffix is a list, the prefix is cleared after parsing
+    pub all: bool,
+    /// Extra files whose domains are removed from the merged output
+    pub exclude: Vec<PathBuf>,
+    /// Strip schemes, ports and paths from entries while merging
+    pub clean: bool,
+    /// Merge internationalized domains in their punycode form, collapsing both spellings
+    pub punycode: bool,
+    /// Carry '#' comment lines from the lists into the merged output
+    pub keep_comments: bool,
+    /// Mark where each list's domains start in the merged output
+    pub separators: bool,
+    /// --pin-file FILE: its domains go first in the merged output, in the file's order
+    pub pin_file: Option<PathBuf>,
+    /// --semantics block|allow: what the merged file is for, a label in its header and the reports
+    pub semantics: Option<Semantics>,
+    /// Keep the previous merged output as <output>.bak
+    pub backup: bool,
+    /// Order of the merged output, --sort alpha|tld|length
+    pub sort: SortOrder,
+    /// Only rewrite selected.txt and the merged output when their content changes, see
+    /// MergeOptions::only_changed
+    pub git_friendly: bool,
+    /// Keep the case of the first occurrence of every domain in the merged output
+    pub preserve_case: bool,
+    /// --shuffle [SEED]: Some(None) without a seed, for a fresh order on every run
+    pub shuffle: Option<Option<u64>>,
+    /// Generation comment block at the top of the merged output, see MergeOptions::header
+    pub header: bool,
+    /// Newline of the merged output, --line-ending lf|crlf
+    pub line_ending: LineEnding,
+    /// What list files that aren't UTF-8 are read as, see encoding.rs
+    pub encoding: Encoding,
+    /// --format plain|dnsmasq, the target of dnsmasq lines comes from --dnsmasq-target
+    pub format: OutputFormat,
+    /// --split-size N: write list-ultimate-1.txt, -2.txt, ... of at most N domains each
+    pub split_size: Option<usize>,
+    /// --apply and --merge fail without writing when more than this share (0 to 1) of the
+    /// domains read are duplicates
+    pub max_dup_ratio: Option<f64>,
+    /// --top-n N: the headless merges take only the N selected lists with the most domains
+    pub top_n: Option<usize>,
+    /// Allow downloading remote (.url) lists while merging
+    pub allow_network: bool,
+    /// Enter on a list opens its actions submenu, leaving Space as the only toggle
+    pub enter_opens_menu: bool,
+    /// Profile file whose list names become the selection shown in the TUI
+    pub import: Option<PathBuf>,
+    /// --import-add: the profile is added to the loaded selection instead of replacing it
+    pub import_add: bool,
+    /// `--select 'list-social-*'`, lists matching any of these are selected on top of the saved ones
+    pub select: Vec<Pattern>,
+    /// Built-in starting selection used while there is no saved one
+    pub template: Option<&'static Template>,
+    pub markers: Markers,
+    /// Hide the SAVE / CANCEL rows, Ctrl+S saves and q or Esc quits
+    pub no_action_rows: bool,
+    /// Read-only TUI: navigation and preview only, SAVE just exits
+    pub review: bool,
+    /// `--diff a.txt b.txt`: read-only TUI marking which of the two selections has each list
+    pub diff: Option<(PathBuf, PathBuf)>,
+    /// Draw in the normal screen buffer so the last state stays in the scrollback
+    pub inline: bool,
+    /// Start with the setup wizard even if the folder was used before
+    pub wizard: bool,
+    /// `--bell on`: ring the terminal bell for list view keys that do nothing
+    pub bell: bool,
+    /// Don't show the "Недавние" section with the recently toggled lists
+    pub no_recent: bool,
+    /// Start with a case-sensitive name filter, Tab in the search prompt switches it
+    pub case_sensitive: bool,
+    /// Shell command run after a successful save or --apply, e.g. to restart zapret
+    pub post_save_cmd: Option<String>,
+    /// Shell command the merged output is piped through, its output is written instead
+    pub transform_cmd: Option<String>,
+    /// Keep the selection in selector.toml ([selection] files = [...]) instead of selected.txt
+    pub toml: bool,
+    /// Print the available lists with their domain counts and exit
+    pub list: bool,
+    /// Print diagnostics about the setup and exit
+    pub doctor: bool,
+    /// Merge the lists saved in selected.txt without the TUI and exit
+    pub apply: bool,
+    /// With --apply: skip the merge when the selection, its lists and the options are unchanged
+    pub if_changed: bool,
+    /// `--diff-merge a.txt b.txt`: print how the merges of two selections differ and exit
+    pub diff_merge: Option<(PathBuf, PathBuf)>,
+    /// `--merge a.txt b.txt`: merge exactly these lists, ignoring selected.txt, and exit
+    pub merge: Option<Vec<String>>,
+    /// -o: merged output file instead of list-ultimate.txt in the lists folder
+    pub output: Option<PathBuf>,
+    /// Write the numbers of every merge to this JSON file
+    pub stats_json: Option<PathBuf>,
+    /// Check every list and write the invalid entries to this JSON file, then exit
+    pub validate_json: Option<PathBuf>,
+    /// Print the version and exit
+    pub version: bool,
+    /// -q / -v for the headless modes
+    pub verbosity: Verbosity,
+}
+
+impl Default for Args {
+    fn default() -> Self {
+        Args {
+            save_delay: 5,
+            draft_interval: 30,
+            confirm_over: 20,
+            lazy_counts: false,
+            changed_hours: 24,
+            timings: false,
+            poll_ms: 100,
+            dir: PathBuf::from("lists"),
+            dir_source: DirSource::Default,
+            state_dir: None,
+            prefix: String::from("list-"),
+            suffix: String::from(".txt"),
+            all: false,
+            exclude: Vec::new(),
+            clean: false,
+            punycode: false,
+            keep_comments: false,
+            separators: false,
+            pin_file: None,
+            semantics: None,
+            backup: false,
+            sort: SortOrder::Alphabetical,
+            shuffle: None,
+            preserve_case: false,
+            git_friendly: false,
+            header: false,
+            line_ending: LineEnding::Lf,
+            encoding: Encoding::Utf8,
+            format: OutputFormat::Plain,
+            split_size: None,
+            max_dup_ratio: None,
+            top_n: None,
+            allow_network: false,
+            enter_opens_menu: false,
+            import: None,
+            import_add: false,
+            select: Vec::new(),
+            template: None,
+            markers: Markers::default(),
+            no_action_rows: false,
+            review: false,
+            diff: None,
+            diff_merge: None,
+            inline: false,
+            bell: false,
+            wizard: false,
+            no_recent: false,
+            case_sensitive: false,
+            post_save_cmd: None,
+            transform_cmd: None,
+            toml: false,
+            list: false,
+            doctor: false,
+            apply: false,
+            if_changed: false,
+            merge: None,
+            output: None,
+            stats_json: None,
+            validate_json: None,
+            version: false,
+            verbosity: Verbosity::Normal,
+        }
+    }
+}
+
+impl Args {
+    /// Folder of the selection and the other files the tool writes for itself
+    pub fn state_dir(&self) -> &Path {
+        self.state_dir.as_deref().unwrap_or(&self.dir)
+    }
+
+    pub fn parse() -> Result<Args, String> {
+        let mut args = Self::parse_from(std::env::args().skip(1))?;
+        if args.dir_source == DirSource::Default {
+            if let Some(dir) = std::env::var_os(LISTS_DIR_ENV).filter(|dir| !dir.is_empty()) {
+                args.dir = PathBuf::from(dir);
+                args.dir_source = DirSource::Env;
+            }
+        }
+
+        Ok(args)
+    }
+
+    pub fn parse_from(raw: impl IntoIterator<Item = String>) -> Result<Args, String> {
+        let mut args = Args::default();
+        let mut raw = raw.into_iter().peekable();
+        // Combined into args.format once everything is parsed
+        let mut dnsmasq = false;
+        let mut dnsmasq_directive = "server";
+        let mut dnsmasq_target = None;
+
+        while let Some(arg) = raw.next() {
+            // Both "--flag value" and "--flag=value" are accepted
+            let (flag, inline_value) = match arg.split_once('=') {
+                Some((flag, value)) if flag.starts_with("--") => (flag.to_string(), Some(value.to_string())),
+                _ => (arg, None),
+            };
+            let mut value = || inline_value.clone()
+                .or_else(|| raw.next())
+                .ok_or_else(|| format!("для {} требуется значение", flag));
+
+            match flag.as_str() {
+                "--save-delay" => args.save_delay = parse_number(&flag, value()?)?,
+                "--draft-interval" => args.draft_interval = parse_number(&flag, value()?)?,
+                "--lazy-counts" => args.lazy_counts = true,
+                "--changed-hours" => args.changed_hours = parse_number(&flag, value()?)?,
+                "--timings" => args.timings = true,
+                "--confirm-over" => args.confirm_over = parse_number(&flag, value()?)?,
+                "--poll-ms" => {
+                    // 0 would turn the idle loop into a busy one
+                    let poll_ms: u64 = parse_number(&flag, value()?)?;
+                    if poll_ms == 0 {
+                        return Err(String::from("--poll-ms: интервал должен быть больше нуля"));
+                    }
+                    args.poll_ms = poll_ms;
+                }
+                "--dir" => {
+                    args.dir = PathBuf::from(value()?);
+                    args.dir_source = DirSource::Flag;
+                }
+                "--state-dir" => args.state_dir = Some(PathBuf::from(value()?)),
+                "--prefix" => args.prefix = value()?,
+                "--suffix" => args.suffix = value()?,
+                "--all" => args.all = true,
+                "--exclude" => args.exclude.push(PathBuf::from(value()?)),
+                "--clean" => args.clean = true,
+                "--punycode" => args.punycode = true,
+                "--keep-comments" => args.keep_comments = true,
+                "--separators" => args.separators = true,
+                "--pin-file" => args.pin_file = Some(PathBuf::from(value()?)),
+                "--semantics" => {
+                    let semantics = value()?;
+                    args.semantics = Some(Semantics::parse(&semantics)
+                        .ok_or_else(|| format!("--semantics: ожидалось block или allow, получено \"{}\"", semantics))?);
+                }
+                "--backup" => args.backup = true,
+                "--header" => args.header = true,
+                "--line-ending" => {
+                    let ending = value()?;
+                    args.line_ending = LineEnding::parse(&ending)
+                        .ok_or_else(|| format!("--line-ending: ожидалось lf или crlf, получено \"{}\"", ending))?;
+                }
+                "--encoding" => {
+                    let name = value()?;
+                    args.encoding = Encoding::parse(&name)
+                        .ok_or_else(|| format!("--encoding: ожидалось utf-8 или cp1251, получено \"{}\"", name))?;
+                }
+                "--format" => {
+                    dnsmasq = match value()?.as_str() {
+                        "plain" => false,
+                        "dnsmasq" => true,
+                        other => return Err(format!("--format: ожидалось plain или dnsmasq, получено \"{}\"", other)),
+                    };
+                }
+                "--dnsmasq-directive" => {
+                    dnsmasq_directive = match value()?.as_str() {
+                        "server" => "server",
+                        "address" => "address",
+                        other => return Err(format!("--dnsmasq-directive: ожидалось server или address, получено \"{}\"", other)),
+                    };
+                }
+                "--dnsmasq-target" => dnsmasq_target = Some(value()?),
+                "--split-size" => {
+                    let size: usize = parse_number(&flag, value()?)?;
+                    if size == 0 {
+                        return Err(String::from("--split-size: размер части должен быть больше нуля"));
+                    }
+                    args.split_size = Some(size);
+                }
+                "--sort" => {
+                    let order = value()?;
+                    args.sort = SortOrder::parse(&order)
+                        .ok_or_else(|| format!("--sort: ожидалось alpha, tld или length, получено \"{}\"", order))?;
+                }
+                "--preserve-case" => args.preserve_case = true,
+                "--git-friendly" => args.git_friendly = true,
+                "--shuffle" => {
+                    // The seed is optional, the next argument is only taken when it is a number
+                    let seed = inline_value.clone().or_else(|| raw.next_if(|next| next.parse::<u64>().is_ok()));
+                    args.shuffle = Some(seed.map(|seed| parse_number(&flag, seed)).transpose()?);
+                }
+                "--max-dup-ratio" => {
+                    let ratio: f64 = parse_number(&flag, value()?)?;
+                    if !(0.0..=1.0).contains(&ratio) {
+                        return Err(format!("--max-dup-ratio: ожидалась доля от 0 до 1, получено {}", ratio));
+                    }
+                    args.max_dup_ratio = Some(ratio);
+                }
+                "--top-n" => {
+                    let count = parse_number(&flag, value()?)?;
+                    if count == 0 {
+                        return Err(String::from("--top-n: число списков должно быть больше нуля"));
+                    }
+                    args.top_n = Some(count);
+                }
+                "--allow-network" => args.allow_network = true,
+                "--enter-opens-menu" => args.enter_opens_menu = true,
+                "--import" => args.import = Some(PathBuf::from(value()?)),
+                "--select" => {
+                    let source = value()?;
+                    let pattern = Pattern::parse(&source).map_err(|e| format!("--select \"{}\": {}", source, e))?;
+                    args.select.push(pattern);
+                }
+                "--template" => {
+                    let name = value()?;
+                    let found = template::find(&name).ok_or_else(|| {
+                        format!("--template: нет шаблона \"{}\", есть: {}", name, template::describe_all())
+                    })?;
+                    args.template = Some(found);
+                }
+                "--import-add" => {
+                    args.import = Some(PathBuf::from(value()?));
+                    args.import_add = true;
+                }
+                "--cursor-marker" => args.markers.cursor = parse_marker(&flag, value()?)?,
+                "--selected-marker" => args.markers.selected = parse_marker(&flag, value()?)?,
+                "--unselected-marker" => args.markers.unselected = parse_marker(&flag, value()?)?,
+                "--no-action-rows" => args.no_action_rows = true,
+                "--review" => args.review = true,
+                "--diff" => {
+                    let first = PathBuf::from(value()?);
+                    let second = raw.next().ok_or_else(|| String::from("для --diff требуются два файла выбора"))?;
+                    args.diff = Some((first, PathBuf::from(second)));
+                }
+                "--diff-merge" => {
+                    let first = PathBuf::from(value()?);
+                    let second = raw.next().ok_or_else(|| String::from("для --diff-merge требуются два файла выбора"))?;
+                    args.diff_merge = Some((first, PathBuf::from(second)));
+                }
+                "--inline" => args.inline = true,
+                "--wizard" => args.wizard = true,
+                "--bell" => {
+                    args.bell = match value()?.as_str() {
+                        "on" => true,
+                        "off" => false,
+                        other => return Err(format!("--bell: ожидалось on или off, получено \"{}\"", other)),
+                    };
+                }
+                "--no-recent" => args.no_recent = true,
+                "--case-sensitive" => args.case_sensitive = true,
+                "--post-save-cmd" => args.post_save_cmd = Some(value()?),
+                "--transform-cmd" => args.transform_cmd = Some(value()?),
+                "--toml" => args.toml = true,
+                "--list" => args.list = true,
+                "--doctor" => args.doctor = true,
+                "--apply" => args.apply = true,
+                "--if-changed" => args.if_changed = true,
+                "--merge" => {
+                    // Takes every following argument up to the next flag
+                    let mut names: Vec<String> = inline_value.clone().into_iter().collect();
+                    while let Some(name) = raw.next_if(|next| !next.starts_with('-')) {
+                        names.push(name);
+                    }
+                    if names.is_empty() {
+                        return Err(String::from("для --merge требуются имена файлов"));
+                    }
+                    args.merge.get_or_insert_with(Vec::new).extend(names);
+                }
+                "-o" | "--output" => args.output = Some(PathBuf::from(value()?)),
+                "--stats-json" => args.stats_json = Some(PathBuf::from(value()?)),
+                "--validate-json" => args.validate_json = Some(PathBuf::from(value()?)),
+                "-V" | "--version" => args.version = true,
+                "-q" | "--quiet" => args.verbosity = Verbosity::Quiet,
+                "-v" | "--verbose" => args.verbosity = Verbosity::Verbose,
+                _ => return Err(format!("неизвестный параметр: {}", flag)),
+            }
+        }
+
+        // A guessed resolver or address would silently send the domains somewhere
+        args.format = match (dnsmasq, dnsmasq_target) {
+            (true, Some(target)) => OutputFormat::Dnsmasq(Dnsmasq { directive: dnsmasq_directive, target }),
+            (true, None) => return Err(String::from("для --format dnsmasq требуется --dnsmasq-target")),
+            (false, Some(_)) => return Err(String::from("--dnsmasq-target работает только вместе с --format dnsmasq")),
+            (false, None) => OutputFormat::Plain,
+        };
+
+        // Also over an explicit --prefix, asking for every file leaves nothing to match
+        if args.all {
+            args.prefix.clear();
+        }
+
+        // Comparing never changes anything
+        if args.diff.is_some() {
+            args.review = true;
+        }
+
+        if args.if_changed && !args.apply {
+            return Err(String::from("--if-changed работает только вместе с --apply"));
+        }
+
+        Ok(args)
+    }
+}
+
+fn parse_number<T: FromStr>(flag: &str, value: String) -> Result<T, String> {
+    value.parse().map_err(|_| format!("{}: ожидалось число, получено \"{}\"", flag, value))
+}
+
+/// A marker has to be one character one column wide, anything else would shift the rows
+fn parse_marker(flag: &str, value: String) -> Result<char, String> {
+    let mut chars = value.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) if width::char_width(c) == 1 => Ok(c),
+        _ => Err(format!("{}: ожидался один символ шириной в одну колонку, получено \"{}\"", flag, value)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Args, String> {
+        Args::parse_from(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn poll_ms_must_be_positive() {
+        assert_eq!(parse(&["--poll-ms", "250"]).unwrap().poll_ms, 250);
+        assert_eq!(parse(&["--poll-ms=1"]).unwrap().poll_ms, 1);
+        assert!(parse(&["--poll-ms", "0"]).is_err());
+        assert!(parse(&["--poll-ms=0"]).is_err());
+    }
+}
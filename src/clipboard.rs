@@ -0,0 +1,42 @@
+//! Copying text to the system clipboard through the platform's own tool, which needs no
+//! extra dependencies: clip on Windows, pbcopy on macOS, wl-copy, xclip or xsel elsewhere
+
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+const TOOLS: &[(&str, &[&str])] = if cfg!(windows) {
+    &[("clip", &[])]
+} else if cfg!(target_os = "macos") {
+    &[("pbcopy", &[])]
+} else {
+    &[
+        ("wl-copy", &[]),
+        ("xclip", &["-selection", "clipboard"]),
+        ("xsel", &["--clipboard", "--input"]),
+    ]
+};
+
+/// Hand text to the first tool that runs
+pub fn copy(text: &str) -> Result<(), String> {
+    for (program, args) in TOOLS {
+        let Ok(mut child) = Command::new(program)
+            .args(*args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+        else {
+            continue;
+        };
+
+        let written = child.stdin.take().map(|mut stdin| stdin.write_all(text.as_bytes()));
+        return match (written, child.wait()) {
+            (Some(Ok(())), Ok(status)) if status.success() => Ok(()),
+            (_, Ok(status)) => Err(format!("{} завершился с ошибкой ({})", program, status)),
+            (_, Err(e)) => Err(format!("{}: {}", program, e)),
+        };
+    }
+
+    let names: Vec<&str> = TOOLS.iter().map(|(program, _)| *program).collect();
+    Err(format!("буфер обмена недоступен, не найдено: {}", names.join(", ")))
+}
@@ -0,0 +1,29 @@
+//! Domain counts of the lists, the number the footer and the save summary show.
+//! With --lazy-counts they are filled in by a background thread after startup.
+
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+
+use crate::{file_ops, remote};
+
+/// Distinct domains of a list, None when it can't be read
+pub fn count(lists_dir: &Path, name: &str) -> Option<usize> {
+    file_ops::read_domains(&remote::content_path(lists_dir, name)).ok().map(|domains| domains.len())
+}
+
+/// Count the lists one after another in the given order, sending each result as it is done.
+/// The thread stops once the receiver is gone.
+pub fn spawn(lists_dir: &Path, names: Vec<String>) -> Receiver<(String, Option<usize>)> {
+    let (sender, receiver) = mpsc::channel();
+    let lists_dir = lists_dir.to_path_buf();
+    thread::spawn(move || {
+        for name in names {
+            let count = count(&lists_dir, &name);
+            if sender.send((name, count)).is_err() {
+                return;
+            }
+        }
+    });
+    receiver
+}
@@ -0,0 +1,36 @@
+//! Snapshots of the unsaved selection, written when the user goes idle so a closed window
+//! or a crash doesn't lose the work. selected.txt itself is only written by SAVE.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::file_ops;
+
+pub const DRAFT_FILE: &str = ".selected.draft";
+
+pub fn path(state_dir: &Path) -> PathBuf {
+    state_dir.join(DRAFT_FILE)
+}
+
+pub fn write(state_dir: &Path, names: &[&str]) -> io::Result<()> {
+    let content: String = names.iter().map(|name| format!("{}\n", name)).collect();
+    file_ops::replace_contents(&path(state_dir), content).map_err(io::Error::from)
+}
+
+/// Delete the draft, a missing one is fine
+pub fn remove(state_dir: &Path) {
+    let _ = fs::remove_file(path(state_dir));
+}
+
+/// Names in the draft if it was written after selected.txt (or there is no selected.txt)
+pub fn newer_than(state_dir: &Path, config_path: &Path) -> Option<Vec<String>> {
+    let draft_modified = fs::metadata(path(state_dir)).and_then(|m| m.modified()).ok()?;
+    let config_modified = fs::metadata(config_path).and_then(|m| m.modified()).ok();
+    if config_modified.is_some_and(|config| config >= draft_modified) {
+        return None;
+    }
+
+    let content = fs::read_to_string(path(state_dir)).ok()?;
+    Some(content.lines().map(str::trim).filter(|name| !name.is_empty()).map(String::from).collect())
+}
@@ -0,0 +1,85 @@
+//! --encoding: what a list file that isn't valid UTF-8 is read as. Files that are UTF-8 are read
+//! as they are whatever the setting, so a folder can mix both. Without a fallback such a file is
+//! an error for the operation that reads it and is skipped by the merge. The merged output, and
+//! the files the selector rewrites (deduplication, the edits of the preview), are UTF-8.
+
+use std::sync::OnceLock;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Encoding {
+    /// No fallback
+    #[default]
+    Utf8,
+    /// Windows-1251, what lists saved by Windows tools in Russian setups usually are
+    Cp1251,
+}
+
+impl Encoding {
+    pub fn parse(value: &str) -> Option<Encoding> {
+        match value.to_lowercase().as_str() {
+            "utf-8" | "utf8" => Some(Encoding::Utf8),
+            "cp1251" | "windows-1251" => Some(Encoding::Cp1251),
+            _ => None,
+        }
+    }
+}
+
+/// Set once from the command line before any list is read, the counting thread reads it too
+static FALLBACK: OnceLock<Encoding> = OnceLock::new();
+
+pub fn set_fallback(encoding: Encoding) {
+    let _ = FALLBACK.set(encoding);
+}
+
+/// The text of a file, None when it isn't UTF-8 and there is no fallback
+pub fn decode(bytes: Vec<u8>) -> Option<String> {
+    match String::from_utf8(bytes) {
+        Ok(text) => Some(text),
+        Err(e) => match FALLBACK.get().copied().unwrap_or_default() {
+            Encoding::Utf8 => None,
+            Encoding::Cp1251 => Some(e.as_bytes().iter().map(|&byte| cp1251_char(byte)).collect()),
+        },
+    }
+}
+
+/// 0x80..=0xBF of Windows-1251, from 0xC0 on it is А..я in order. 0x98 is unassigned and
+/// kept as the C1 control of the same number, like the WHATWG decoder does.
+const CP1251_HIGH: [char; 64] = [
+    'Ђ', 'Ѓ', '‚', 'ѓ', '„', '…', '†', '‡', '€', '‰', 'Љ', '‹', 'Њ', 'Ќ', 'Ћ', 'Џ',
+    'ђ', '‘', '’', '“', '”', '•', '–', '—', '\u{98}', '™', 'љ', '›', 'њ', 'ќ', 'ћ', 'џ',
+    '\u{a0}', 'Ў', 'ў', 'Ј', '¤', 'Ґ', '¦', '§', 'Ё', '©', 'Є', '«', '¬', '\u{ad}', '®', 'Ї',
+    '°', '±', 'І', 'і', 'ґ', 'µ', '¶', '·', 'ё', '№', 'є', '»', 'ј', 'Ѕ', 'ѕ', 'ї',
+];
+
+fn cp1251_char(byte: u8) -> char {
+    match byte {
+        0x00..=0x7f => char::from(byte),
+        0x80..=0xbf => CP1251_HIGH[usize::from(byte - 0x80)],
+        _ => char::from_u32(0x410 + u32::from(byte - 0xc0)).unwrap_or(char::REPLACEMENT_CHARACTER),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cp1251_bytes_decode_to_cyrillic() {
+        let bytes = b"\xef\xf0\xe8\xec\xe5\xf0.\xf0\xf4";
+        assert_eq!(bytes.iter().map(|&byte| cp1251_char(byte)).collect::<String>(), "пример.рф");
+        assert_eq!(cp1251_char(0xc0), 'А');
+        assert_eq!(cp1251_char(0xff), 'я');
+        assert_eq!(cp1251_char(0xa8), 'Ё');
+        assert_eq!(cp1251_char(0xb8), 'ё');
+        assert_eq!(cp1251_char(0x98), '\u{98}');
+        assert_eq!(cp1251_char(b'a'), 'a');
+    }
+
+    #[test]
+    fn encoding_names() {
+        assert_eq!(Encoding::parse("UTF-8"), Some(Encoding::Utf8));
+        assert_eq!(Encoding::parse("windows-1251"), Some(Encoding::Cp1251));
+        assert_eq!(Encoding::parse("CP1251"), Some(Encoding::Cp1251));
+        assert_eq!(Encoding::parse("koi8-r"), None);
+    }
+}
@@ -0,0 +1,93 @@
+//! Errors that say which file and which operation failed, instead of a bare
+//! "No such file or directory" from io::Error
+
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug)]
+pub enum SelectorError {
+    Read { path: PathBuf, source: io::Error },
+    Write { path: PathBuf, source: io::Error },
+    ScanDir { path: PathBuf, source: io::Error },
+    /// A file that was read but doesn't have the expected format
+    Parse { path: PathBuf, message: String },
+    /// Not UTF-8 and --encoding gives no fallback for it
+    Encoding { path: PathBuf },
+    /// A folder where a file is expected, e.g. a selected.txt left over from a botched copy
+    IsDirectory { path: PathBuf },
+    /// A user command (--transform-cmd) that failed or printed something unusable
+    Command { command: String, message: String },
+}
+
+pub type Result<T> = std::result::Result<T, SelectorError>;
+
+impl fmt::Display for SelectorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SelectorError::Read { path, source } => write!(f, "не удалось прочитать {}: {}", path.display(), source),
+            SelectorError::Write { path, source } => write!(f, "не удалось записать {}: {}", path.display(), source),
+            SelectorError::ScanDir { path, source } => {
+                write!(f, "не удалось прочитать папку {}: {}", path.display(), source)
+            }
+            SelectorError::Parse { path, message } => write!(f, "ошибка в {}: {}", path.display(), message),
+            SelectorError::Encoding { path } => {
+                write!(f, "{} не в UTF-8, файл в Windows-1251 читается с --encoding cp1251", path.display())
+            }
+            SelectorError::IsDirectory { path } => {
+                write!(f, "{} — это папка, не удаётся использовать", path.display())
+            }
+            SelectorError::Command { command, message } => write!(f, "команда «{}»: {}", command, message),
+        }
+    }
+}
+
+impl std::error::Error for SelectorError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SelectorError::Read { source, .. } |
+            SelectorError::Write { source, .. } |
+            SelectorError::ScanDir { source, .. } => Some(source),
+            SelectorError::Parse { .. } |
+            SelectorError::Encoding { .. } |
+            SelectorError::IsDirectory { .. } |
+            SelectorError::Command { .. } => None,
+        }
+    }
+}
+
+/// Lets the TUI loop keep returning io::Result, the message keeps the context
+impl From<SelectorError> for io::Error {
+    fn from(error: SelectorError) -> io::Error {
+        let kind = match &error {
+            SelectorError::Read { source, .. } |
+            SelectorError::Write { source, .. } |
+            SelectorError::ScanDir { source, .. } => source.kind(),
+            SelectorError::Parse { .. } | SelectorError::Encoding { .. } => io::ErrorKind::InvalidData,
+            SelectorError::IsDirectory { .. } => io::ErrorKind::InvalidInput,
+            SelectorError::Command { .. } => io::ErrorKind::Other,
+        };
+        io::Error::new(kind, error.to_string())
+    }
+}
+
+/// Attach the path and the operation to an io::Result: `fs::read(path).reading(path)?`
+pub trait Context<T> {
+    fn reading(self, path: &Path) -> Result<T>;
+    fn writing(self, path: &Path) -> Result<T>;
+    fn scanning(self, path: &Path) -> Result<T>;
+}
+
+impl<T> Context<T> for io::Result<T> {
+    fn reading(self, path: &Path) -> Result<T> {
+        self.map_err(|source| SelectorError::Read { path: path.to_path_buf(), source })
+    }
+
+    fn writing(self, path: &Path) -> Result<T> {
+        self.map_err(|source| SelectorError::Write { path: path.to_path_buf(), source })
+    }
+
+    fn scanning(self, path: &Path) -> Result<T> {
+        self.map_err(|source| SelectorError::ScanDir { path: path.to_path_buf(), source })
+    }
+}
@@ -0,0 +1,31 @@
+//! .selector_favorites in the state folder: starred lists, one name per line. Kept apart from
+//! the selection, so switching or resetting selections leaves the stars alone and merging
+//! never reads them.
+
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+
+use crate::errors;
+use crate::file_ops;
+
+pub const FAVORITES_FILE: &str = ".selector_favorites";
+
+/// The starred names, empty without the file
+pub fn read(state_dir: &Path) -> HashSet<String> {
+    fs::read_to_string(state_dir.join(FAVORITES_FILE))
+        .unwrap_or_default()
+        .lines()
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+/// Sorted, so the file doesn't reshuffle on every change
+pub fn write(state_dir: &Path, favorites: &HashSet<String>) -> errors::Result<()> {
+    let mut names: Vec<&String> = favorites.iter().collect();
+    names.sort();
+    let content: String = names.iter().map(|name| format!("{}\n", name)).collect();
+    file_ops::replace_contents(&state_dir.join(FAVORITES_FILE), content)
+}
@@ -0,0 +1,586 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+use crate::errors::{self, Context, SelectorError};
+use crate::{checksum, encoding, merge, remote, selection};
+use crate::OUTPUT_FILE;
+
+/// The lines of a file, decoded with the --encoding fallback when it isn't UTF-8
+pub fn read_lines(file_path: &Path) -> errors::Result<Vec<String>> {
+    let mut bytes = Vec::new();
+    File::open(file_path)
+        .and_then(|mut file| file.read_to_end(&mut bytes))
+        .reading(file_path)?;
+    let content = encoding::decode(bytes).ok_or_else(|| SelectorError::Encoding { path: file_path.to_path_buf() })?;
+    Ok(content.lines().map(String::from).collect())
+}
+
+/// The entry of a list line as written, trimmed and lowercased, or None for blanks and comments.
+/// It may still be anything, e.g. a URL; see parse_domain_line.
+pub fn entry_of(line: &str) -> Option<String> {
+    let entry = line.split('#').next()?.trim();
+    if entry.is_empty() {
+        None
+    } else {
+        Some(entry.to_lowercase())
+    }
+}
+
+/// Separates a domain from a note on why it is in the list: "example.com ## used by the app".
+/// The note is a comment like any other for counting and merging, the preview (R) and the
+/// validation reports show it. A line starting with # is a comment as a whole, "##" included.
+pub const REASON_DELIMITER: &str = "##";
+
+/// The note after REASON_DELIMITER on a line with an entry, None for comment lines and
+/// entries without one
+pub fn reason_of(line: &str) -> Option<&str> {
+    entry_of(line)?;
+    let (_, reason) = line.split_once(REASON_DELIMITER)?;
+    let reason = reason.trim();
+    (!reason.is_empty()).then_some(reason)
+}
+
+/// Written before an entry that is switched off for now, "#!example.com": unlike a note it is
+/// meant to come back. Being a comment it is left out of counting and merging like any other.
+pub const DISABLED_MARKER: &str = "#!";
+
+/// The entry of a line disabled with DISABLED_MARKER
+pub fn disabled_entry(line: &str) -> Option<String> {
+    entry_of(line.trim_start().strip_prefix(DISABLED_MARKER)?)
+}
+
+/// Written before a domain ("*.example.com") to say its subdomains are meant too. zapret
+/// hostlists match subdomains anyway, so the marker is dropped and the bare domain is used.
+pub const SUBDOMAINS_MARKER: &str = "*.";
+
+/// The domain of a list line, the one parser behind counts, merging and validation so they agree.
+/// Inline comments, surrounding whitespace (CR included), a SUBDOMAINS_MARKER and a trailing dot
+/// are dropped and the result is lowercased; blanks, comments (DISABLED_MARKER entries included)
+/// and entries that aren't valid domains give None.
+pub fn parse_domain_line(line: &str) -> Option<String> {
+    let entry = entry_of(line)?;
+    let domain = entry.strip_prefix(SUBDOMAINS_MARKER).unwrap_or(&entry);
+    let domain = domain.strip_suffix('.').unwrap_or(domain);
+    is_valid_domain(domain).then(|| domain.to_string())
+}
+
+/// The domain parse_domain_line found, in the case it is written in on the line, for
+/// --preserve-case. Just domain when the line spells it differently, e.g. from --clean.
+pub fn written_domain(line: &str, domain: &str) -> String {
+    let entry = line.split('#').next().unwrap_or_default().trim();
+    let written = entry.strip_prefix(SUBDOMAINS_MARKER).unwrap_or(entry);
+    let written = written.strip_suffix('.').unwrap_or(written);
+    if written.to_lowercase() == domain {
+        written.to_string()
+    } else {
+        domain.to_string()
+    }
+}
+
+/// Read the set of distinct domains of a list, ignoring blanks, comments and invalid entries
+pub fn read_domains(file_path: &Path) -> errors::Result<HashSet<String>> {
+    Ok(read_lines(file_path)?.iter().filter_map(|line| parse_domain_line(line)).collect())
+}
+
+pub fn is_valid_domain(domain: &str) -> bool {
+    let domain = domain.strip_suffix('.').unwrap_or(domain);
+    if domain.is_empty() || domain.len() > 253 {
+        return false;
+    }
+
+    domain.split('.').all(|label| {
+        !label.is_empty() &&
+            label.chars().count() <= 63 &&
+            !label.starts_with('-') &&
+            !label.ends_with('-') &&
+            label.chars().all(|c| c.is_alphanumeric() || c == '-')
+    })
+}
+
+/// Reduce an entry like "https://user@YouTube.com:443/watch?v=1" to "youtube.com".
+/// Returns None when what's left isn't a valid hostname.
+pub fn clean_host(entry: &str) -> Option<String> {
+    let rest = match entry.find("://") {
+        Some(pos) => &entry[pos + 3..],
+        None => entry,
+    };
+    let host = rest.split(['/', '?', '#']).next()?;
+    let host = host.rsplit('@').next()?;
+    let host = match host.rsplit_once(':') {
+        Some((host, port)) if port.chars().all(|c| c.is_ascii_digit()) => host,
+        _ => host,
+    };
+
+    let host = host.to_lowercase();
+    let host = host.strip_prefix(SUBDOMAINS_MARKER).unwrap_or(&host);
+    let host = host.strip_suffix('.').unwrap_or(host);
+    is_valid_domain(host).then(|| host.to_string())
+}
+
+/// Write through a temporary file so a failure never leaves a half-written list behind
+pub fn write_atomic(file_path: &Path, lines: &[String]) -> errors::Result<()> {
+    let content: String = lines.iter().map(|line| format!("{}\n", line)).collect();
+    replace_contents(file_path, &content)
+}
+
+/// write_atomic for content that isn't a list of lines. An interrupted run leaves the old
+/// file, at worst next to a stray .tmp, never a cut-off one.
+pub fn replace_contents(file_path: &Path, content: impl AsRef<[u8]>) -> errors::Result<()> {
+    let tmp_path = file_path.with_extension("tmp");
+    let write = || -> io::Result<()> {
+        let mut file = File::create(&tmp_path)?;
+        file.write_all(content.as_ref())?;
+        file.sync_all()?;
+        fs::rename(&tmp_path, file_path)
+    };
+    let written = write();
+    if written.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+    written.writing(file_path)
+}
+
+/// replace_contents unless the file holds exactly content already, so its modification time
+/// only changes with it (--git-friendly). Returns whether it was written.
+pub fn replace_if_changed(file_path: &Path, content: impl AsRef<[u8]>) -> errors::Result<bool> {
+    if fs::read(file_path).is_ok_and(|current| current == content.as_ref()) {
+        return Ok(false);
+    }
+    replace_contents(file_path, content)?;
+    Ok(true)
+}
+
+/// Whether SAVE will be able to write: creates and removes a probe file in the lists folder
+/// and opens the selection file for appending without changing it
+pub fn check_writable(lists_dir: &Path, config_path: &Path) -> errors::Result<()> {
+    let probe = lists_dir.join(".selector_write_probe");
+    File::create(&probe).and_then(|_| fs::remove_file(&probe)).writing(lists_dir)?;
+    if config_path.exists() {
+        fs::OpenOptions::new().append(true).open(config_path).writing(config_path)?;
+    }
+    Ok(())
+}
+
+/// Whether the merged output can be written: opens it for appending, creating it if needed.
+/// An existing file is left as it was, a file created just for the probe is removed again.
+pub fn check_output_writable(output: &Path) -> errors::Result<()> {
+    let existed = output.exists();
+    fs::OpenOptions::new().append(true).create(true).open(output).writing(output)?;
+    if !existed {
+        let _ = fs::remove_file(output);
+    }
+    Ok(())
+}
+
+/// "a.com" becomes "#a.com" and back. Uncommenting also drops one space after the '#', so
+/// "# a.com" from a hand-edited file turns into "a.com", and a disabled "#!a.com" is enabled.
+pub fn toggle_comment(line: &str) -> String {
+    let indent = &line[..line.len() - line.trim_start().len()];
+    if disabled_entry(line).is_some() {
+        return toggle_disabled(line);
+    }
+    match line.trim_start().strip_prefix('#') {
+        Some(rest) => format!("{}{}", indent, rest.strip_prefix(' ').unwrap_or(rest)),
+        None => format!("{}#{}", indent, line.trim_start()),
+    }
+}
+
+/// "a.com" becomes "#!a.com" and back, see DISABLED_MARKER
+pub fn toggle_disabled(line: &str) -> String {
+    let indent = &line[..line.len() - line.trim_start().len()];
+    match line.trim_start().strip_prefix(DISABLED_MARKER) {
+        Some(rest) => format!("{}{}", indent, rest),
+        None => format!("{}{}{}", indent, DISABLED_MARKER, line.trim_start()),
+    }
+}
+
+/// Split lines into the ones dedup keeps and the entries it drops: every repeat of an
+/// entry after its first occurrence. Comments and blank lines are always kept.
+pub fn split_duplicates(lines: &[String]) -> (Vec<String>, Vec<String>) {
+    let mut seen = HashSet::new();
+    let mut kept = Vec::with_capacity(lines.len());
+    let mut repeats = Vec::new();
+    for line in lines {
+        match entry_of(line) {
+            Some(entry) if !seen.insert(entry.clone()) => repeats.push(entry),
+            _ => kept.push(line.clone()),
+        }
+    }
+    (kept, repeats)
+}
+
+/// The entries dedup_file would remove, in file order, without changing the file
+pub fn find_duplicates(file_path: &Path) -> errors::Result<Vec<String>> {
+    Ok(split_duplicates(&read_lines(file_path)?).1)
+}
+
+/// Remove repeated domains keeping the first occurrence, comments and blank lines stay.
+/// Returns how many lines were removed; the file isn't touched when there are none.
+pub fn dedup_file(file_path: &Path) -> errors::Result<usize> {
+    let (kept, repeats) = split_duplicates(&read_lines(file_path)?);
+    if !repeats.is_empty() {
+        write_atomic(file_path, &kept)?;
+    }
+
+    Ok(repeats.len())
+}
+
+/// Returns 1-based line numbers of entries that don't look like domains
+pub fn validate_file(file_path: &Path) -> errors::Result<Vec<usize>> {
+    Ok(invalid_entries(file_path)?.into_iter().map(|entry| entry.line).collect())
+}
+
+/// An entry that isn't a valid domain
+pub struct InvalidEntry {
+    /// 1-based
+    pub line: usize,
+    /// As written but without the comment and the surrounding whitespace
+    pub text: String,
+    /// See REASON_DELIMITER
+    pub reason: Option<String>,
+}
+
+/// The rejected entries of a file
+pub fn invalid_entries(file_path: &Path) -> errors::Result<Vec<InvalidEntry>> {
+    let lines = read_lines(file_path)?;
+    Ok(lines.iter()
+        .enumerate()
+        .filter(|(_, line)| entry_of(line).is_some() && parse_domain_line(line).is_none())
+        .map(|(index, line)| InvalidEntry {
+            line: index + 1,
+            text: line.split('#').next().unwrap_or_default().trim().to_string(),
+            reason: reason_of(line).map(String::from),
+        })
+        .collect())
+}
+
+/// Groups of the named lists whose files are byte for byte the same. Files are compared by
+/// size first, only equally sized ones are read and hashed. Unreadable files are skipped.
+pub fn identical_files(lists_dir: &Path, names: &[&str]) -> Vec<Vec<String>> {
+    let mut by_size: BTreeMap<u64, Vec<&str>> = BTreeMap::new();
+    for name in names {
+        if let Ok(meta) = fs::metadata(remote::content_path(lists_dir, name)) {
+            by_size.entry(meta.len()).or_default().push(name);
+        }
+    }
+
+    let mut groups = Vec::new();
+    for same_size in by_size.values().filter(|names| names.len() > 1) {
+        let mut by_hash: BTreeMap<u32, Vec<String>> = BTreeMap::new();
+        for name in same_size {
+            if let Ok(crc) = checksum::file_crc32(&remote::content_path(lists_dir, name)) {
+                by_hash.entry(crc).or_default().push(name.to_string());
+            }
+        }
+        groups.extend(by_hash.into_values().filter(|names| names.len() > 1));
+    }
+    groups
+}
+
+pub fn is_list_name(name: &str, prefix: &str, suffix: &str) -> bool {
+    name.len() >= prefix.len() + suffix.len() &&
+        name.starts_with(prefix) &&
+        name.ends_with(suffix) &&
+        name != OUTPUT_FILE &&
+        !is_output_chunk(name) &&
+        !is_reserved(name, prefix)
+}
+
+/// Files of the lists folder that are never lists, whatever the naming pattern. With the
+/// default "list-" prefix none of them matches anyway, with --all they would.
+fn is_reserved(name: &str, prefix: &str) -> bool {
+    let lower = name.to_lowercase();
+    // Unless it's the exclude lists that are being looked for
+    let exclude_list = lower.starts_with(merge::EXCLUDE_PREFIX) && prefix != merge::EXCLUDE_PREFIX;
+    lower.starts_with('.') ||
+        exclude_list ||
+        lower == selection::SELECTED_FILE ||
+        lower == selection::TOML_FILE
+}
+
+/// "list-ultimate-3.txt", a part of the merged output written with --split-size
+fn is_output_chunk(name: &str) -> bool {
+    is_chunk_of(name, OUTPUT_FILE)
+}
+
+/// name is "<stem>-N.<ext>" for output "<stem>.<ext>", see merge::chunk_path
+fn is_chunk_of(name: &str, output: &str) -> bool {
+    let (stem, extension) = match output.rsplit_once('.') {
+        Some((stem, extension)) => (stem, Some(extension)),
+        None => (output, None),
+    };
+    let index = name.strip_prefix(stem).and_then(|rest| rest.strip_prefix('-'));
+    let index = match extension {
+        Some(extension) => index.and_then(|rest| rest.strip_suffix(extension)).and_then(|rest| rest.strip_suffix('.')),
+        None => index,
+    };
+    index.is_some_and(|index| !index.is_empty() && index.chars().all(|c| c.is_ascii_digit()))
+}
+
+/// Whether path is the -o output or one of its --split-size parts. Compared as canonical paths,
+/// so a different spelling of the lists folder or a symlink still matches. An output that
+/// doesn't exist yet can't be in the folder, only its parts are looked for then.
+pub fn is_output_path(path: &Path, output: &Path) -> bool {
+    let Some(output_name) = output.file_name().and_then(|name| name.to_str()) else {
+        return false;
+    };
+    let output_dir = match output.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    if let (Ok(path), Ok(output)) = (path.canonicalize(), output.canonicalize()) {
+        if path == output {
+            return true;
+        }
+    }
+    let same_dir = match (path.parent().map(Path::canonicalize), output_dir.canonicalize()) {
+        (Some(Ok(dir)), Ok(output_dir)) => dir == output_dir,
+        _ => false,
+    };
+    same_dir && path.file_name().and_then(|name| name.to_str()).is_some_and(|name| is_chunk_of(name, output_name))
+}
+
+/// Category of a list encoded in its name: "list-social-vk.txt" belongs to "social".
+/// Names with a single part ("list-youtube.txt") have no category.
+pub fn category_of<'a>(name: &'a str, prefix: &str, suffix: &str) -> Option<&'a str> {
+    let stem = name.strip_prefix(prefix)?.strip_suffix(suffix)?;
+    match stem.split_once('-') {
+        Some((category, rest)) if !category.is_empty() && !rest.is_empty() => Some(category),
+        _ => None,
+    }
+}
+
+/// Names of all list files in lists_dir (including remote .url lists), sorted so the order is stable
+pub fn scan_lists(lists_dir: &Path, prefix: &str, suffix: &str, output: Option<&Path>) -> errors::Result<Vec<String>> {
+    let mut names: Vec<String> = fs::read_dir(lists_dir).scanning(lists_dir)?
+        .filter_map(|entry| {
+            let entry = entry.ok()?;
+            if !entry.file_type().ok()?.is_file() {
+                return None;
+            }
+
+            let name = entry.file_name().into_string().ok()?;
+            let is_remote_list = is_list_name(&name, prefix, remote::REMOTE_SUFFIX);
+            if !is_list_name(&name, prefix, suffix) && !is_remote_list {
+                return None;
+            }
+            // A -o output named like a list would otherwise be merged into itself on the next save
+            if output.is_some_and(|output| is_output_path(&entry.path(), output)) {
+                return None;
+            }
+            Some(name)
+        })
+        .collect();
+
+    names.sort();
+    Ok(names)
+}
+
+/// Tab completion of a typed path: the last part is extended as far as the names in its
+/// folder agree, a folder gets its separator. None when nothing matches or nothing is added.
+pub fn complete_path(input: &str) -> Option<String> {
+    let (dir, partial) = match input.rfind(['/', std::path::MAIN_SEPARATOR]) {
+        Some(end) => (&input[..=end], &input[end + 1..]),
+        None => ("", input),
+    };
+    let read_from = if dir.is_empty() { Path::new(".") } else { Path::new(dir) };
+    let matches: Vec<(String, bool)> = fs::read_dir(read_from).ok()?
+        .filter_map(|entry| {
+            let entry = entry.ok()?;
+            let name = entry.file_name().into_string().ok()?;
+            let is_dir = entry.file_type().ok()?.is_dir();
+            name.starts_with(partial).then_some((name, is_dir))
+        })
+        .collect();
+
+    let (first, _) = matches.first()?;
+    let mut common = first.clone();
+    for (name, _) in &matches[1..] {
+        while !name.starts_with(common.as_str()) {
+            common.pop();
+        }
+    }
+    if let [(_, true)] = matches.as_slice() {
+        common.push(std::path::MAIN_SEPARATOR);
+    }
+    (common.len() > partial.len()).then(|| format!("{}{}", dir, common))
+}
+
+/// Renames proposed for the files of a lists folder, see normalized_name
+pub struct NormalizePlan {
+    /// (old name, new name), sorted by the old name
+    pub renames: Vec<(String, String)>,
+    /// (file, reason) for the ones left alone because the new name is taken
+    pub skipped: Vec<(String, String)>,
+}
+
+/// The conventional form of a list file name: lowercase, whitespace runs turned into hyphens
+/// and the prefix added when it's missing, "My YouTube.TXT" becomes "list-my-youtube.txt".
+/// None when the name is fine already or the file isn't meant as a list: another suffix,
+/// hidden files, exclude lists, the selection file and names that end up as the merged output.
+pub fn normalized_name(name: &str, prefix: &str, suffix: &str) -> Option<String> {
+    if is_reserved(name, prefix) {
+        return None;
+    }
+    let lower = name.to_lowercase();
+
+    let (stem, new_suffix) = match lower.strip_suffix(&suffix.to_lowercase()) {
+        Some(stem) => (stem, suffix),
+        None => (lower.strip_suffix(remote::REMOTE_SUFFIX)?, remote::REMOTE_SUFFIX),
+    };
+    let stem = stem.split_whitespace().collect::<Vec<_>>().join("-");
+    let stem = stem.strip_prefix(&prefix.to_lowercase()).unwrap_or(&stem);
+    if stem.is_empty() {
+        return None;
+    }
+
+    let normalized = format!("{}{}{}", prefix, stem, new_suffix);
+    let usable = is_list_name(&normalized, prefix, new_suffix);
+    (usable && normalized != name).then_some(normalized)
+}
+
+/// normalized_name for every file in lists_dir. A rename is skipped when its new name belongs
+/// to another file already or to more than one of the renamed files.
+pub fn plan_normalize(lists_dir: &Path, prefix: &str, suffix: &str) -> errors::Result<NormalizePlan> {
+    let mut proposed: Vec<(String, String)> = fs::read_dir(lists_dir).scanning(lists_dir)?
+        .filter_map(|entry| {
+            let entry = entry.ok()?;
+            if !entry.file_type().ok()?.is_file() {
+                return None;
+            }
+            let name = entry.file_name().into_string().ok()?;
+            let normalized = normalized_name(&name, prefix, suffix)?;
+            Some((name, normalized))
+        })
+        .collect();
+    proposed.sort();
+
+    let mut plan = NormalizePlan { renames: Vec::new(), skipped: Vec::new() };
+    for (old, new) in &proposed {
+        let shared = proposed.iter().filter(|(_, other)| other == new).count() > 1;
+        // Only the case differs: on Windows that's the same file, which is fine to rename
+        let taken = lists_dir.join(new).exists() && old.to_lowercase() != new.to_lowercase();
+        if shared {
+            plan.skipped.push((old.clone(), format!("{} получается и из другого файла", new)));
+        } else if taken {
+            plan.skipped.push((old.clone(), format!("{} уже существует", new)));
+        } else {
+            plan.renames.push((old.clone(), new.clone()));
+        }
+    }
+    Ok(plan)
+}
+
+/// Check that a new list name is usable for a rename inside lists_dir
+pub fn check_new_name(lists_dir: &Path, name: &str, prefix: &str, suffix: &str) -> Result<(), String> {
+    if name.contains(['/', '\\']) {
+        return Err(String::from("имя не должно содержать разделителей пути"));
+    }
+    if !is_list_name(name, prefix, suffix) {
+        return Err(format!("имя должно начинаться с {} и заканчиваться на {}", prefix, suffix));
+    }
+    if lists_dir.join(name).exists() {
+        return Err(String::from("файл с таким именем уже существует"));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scratch;
+
+    fn parse(line: &str) -> Option<String> {
+        parse_domain_line(line)
+    }
+
+    #[test]
+    fn a_domain_line_is_normalized() {
+        assert_eq!(parse("youtube.com").as_deref(), Some("youtube.com"));
+        assert_eq!(parse("  YouTube.COM \t").as_deref(), Some("youtube.com"));
+        assert_eq!(parse("youtube.com.").as_deref(), Some("youtube.com"));
+        assert_eq!(parse("youtube.com\r").as_deref(), Some("youtube.com"));
+        assert_eq!(parse("youtube.com # video").as_deref(), Some("youtube.com"));
+        assert_eq!(parse("youtube.com.#video\r").as_deref(), Some("youtube.com"));
+    }
+
+    #[test]
+    fn internationalized_domains_are_kept_in_unicode() {
+        assert_eq!(parse("пример.рф").as_deref(), Some("пример.рф"));
+        assert_eq!(parse("ПРИМЕР.РФ.").as_deref(), Some("пример.рф"));
+        assert_eq!(parse("xn--e1afmkfd.xn--p1ai").as_deref(), Some("xn--e1afmkfd.xn--p1ai"));
+    }
+
+    #[test]
+    fn the_subdomains_marker_is_dropped() {
+        assert_eq!(parse("*.example.com").as_deref(), Some("example.com"));
+        assert_eq!(parse(" *.Example.com. # all of it").as_deref(), Some("example.com"));
+        // Only as a prefix, elsewhere it is just an invalid entry
+        assert_eq!(parse("a.*.example.com"), None);
+        assert_eq!(parse("*."), None);
+    }
+
+    #[test]
+    fn a_reason_is_not_part_of_the_domain() {
+        let line = "example.com ## needed for the app login";
+        assert_eq!(parse(line).as_deref(), Some("example.com"));
+        assert_eq!(reason_of(line), Some("needed for the app login"));
+        assert_eq!(parse("## section"), None);
+        assert_eq!(reason_of("## section"), None);
+    }
+
+    #[test]
+    fn disabled_entries_and_comments_are_not_domains() {
+        assert_eq!(parse("#!example.com"), None);
+        assert_eq!(parse("  #! example.com"), None);
+        assert_eq!(disabled_entry("  #! Example.com").as_deref(), Some("example.com"));
+        assert_eq!(parse("#"), None);
+        assert_eq!(parse("# example.com"), None);
+        assert_eq!(parse("#example.com"), None);
+        assert_eq!(parse(""), None);
+        assert_eq!(parse("   \r"), None);
+    }
+
+    #[test]
+    fn invalid_entries_give_none() {
+        assert_eq!(parse("https://example.com/path"), None);
+        assert_eq!(parse("-example.com"), None);
+        assert_eq!(parse("example..com"), None);
+        assert_eq!(parse("exa mple.com"), None);
+    }
+
+    #[test]
+    fn duplicates_after_the_first_occurrence_are_split_off() {
+        let lines: Vec<String> = ["# header", "a.com", "", "B.com # note", "a.com", "# header", "b.com", "c.com"]
+            .iter()
+            .map(|line| line.to_string())
+            .collect();
+        let (kept, repeats) = split_duplicates(&lines);
+        assert_eq!(kept, ["# header", "a.com", "", "B.com # note", "# header", "c.com"]);
+        assert_eq!(repeats, ["a.com", "b.com"]);
+    }
+
+    #[test]
+    fn the_output_is_not_scanned_as_a_list() {
+        let root = scratch::dir("scan-output");
+        let lists = root.join("lists");
+        fs::create_dir(&lists).unwrap();
+        for name in ["list-a.txt", "list-combined.txt", "list-combined-1.txt", "list-combined-2.txt", "list-other-1.txt"] {
+            fs::write(lists.join(name), "example.com\n").unwrap();
+        }
+        let scan = |output: &Path| scan_lists(&lists, "list-", ".txt", Some(output)).unwrap();
+        let expected = ["list-a.txt", "list-other-1.txt"];
+
+        assert_eq!(scan(&lists.join("list-combined.txt")), expected);
+        assert_eq!(scan(&root.join("./lists/../lists/list-combined.txt")), expected);
+        // Only the parts are on disk, as after a --split-size merge
+        fs::remove_file(lists.join("list-combined.txt")).unwrap();
+        assert_eq!(scan(&root.join("./lists/../lists/list-combined.txt")), expected);
+        // An output elsewhere leaves the folder alone
+        let all = scan(&root.join("list-combined.txt"));
+        assert_eq!(all, ["list-a.txt", "list-combined-1.txt", "list-combined-2.txt", "list-other-1.txt"]);
+    }
+}
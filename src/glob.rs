@@ -0,0 +1,118 @@
+//! Shell-style wildcards for list names: `*`, `?` and `[a-z]` / `[!0-9]` classes
+
+#[derive(Debug, Clone)]
+enum Token {
+    Literal(char),
+    /// `?`, any one character
+    Any,
+    /// `*`, any run of characters, also an empty one
+    Star,
+    Class { negated: bool, ranges: Vec<(char, char)> },
+}
+
+#[derive(Debug, Clone)]
+pub struct Pattern {
+    source: String,
+    tokens: Vec<Token>,
+}
+
+impl Pattern {
+    pub fn parse(source: &str) -> Result<Pattern, String> {
+        if source.is_empty() {
+            return Err(String::from("пустой шаблон"));
+        }
+
+        let chars: Vec<char> = source.chars().collect();
+        let mut tokens = Vec::new();
+        let mut pos = 0;
+        while pos < chars.len() {
+            let c = chars[pos];
+            pos += 1;
+            tokens.push(match c {
+                '*' => Token::Star,
+                '?' => Token::Any,
+                '[' => parse_class(&chars, &mut pos)?,
+                ']' => return Err(String::from("лишняя ']'")),
+                c => Token::Literal(c),
+            });
+        }
+
+        Ok(Pattern { source: source.to_string(), tokens })
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.source
+    }
+
+    pub fn matches(&self, name: &str) -> bool {
+        let name: Vec<char> = name.chars().collect();
+        // Backtracking only to the most recent star is enough for these patterns
+        let (mut t, mut n) = (0, 0);
+        let mut star: Option<(usize, usize)> = None;
+        while n < name.len() {
+            match self.tokens.get(t) {
+                Some(Token::Star) => {
+                    star = Some((t, n));
+                    t += 1;
+                }
+                Some(token) if token_matches(token, name[n]) => {
+                    t += 1;
+                    n += 1;
+                }
+                _ => match star {
+                    Some((star_t, star_n)) => {
+                        t = star_t + 1;
+                        n = star_n + 1;
+                        star = Some((star_t, star_n + 1));
+                    }
+                    None => return false,
+                },
+            }
+        }
+        self.tokens[t..].iter().all(|token| matches!(token, Token::Star))
+    }
+}
+
+/// A `[...]` class, pos starts after the opening bracket and ends after the closing one
+fn parse_class(chars: &[char], pos: &mut usize) -> Result<Token, String> {
+    let negated = matches!(chars.get(*pos), Some('!' | '^'));
+    if negated {
+        *pos += 1;
+    }
+
+    let mut ranges = Vec::new();
+    // A ']' right after the opening bracket is a member, not the end
+    let mut first = true;
+    loop {
+        let Some(&start) = chars.get(*pos) else {
+            return Err(String::from("не закрыта '['"));
+        };
+        *pos += 1;
+        if start == ']' && !first {
+            return Ok(Token::Class { negated, ranges });
+        }
+        first = false;
+
+        // "a-z" is a range, a '-' before the closing bracket is a plain member
+        let mut end = start;
+        if chars.get(*pos) == Some(&'-') && chars.get(*pos + 1).is_some_and(|&c| c != ']') {
+            end = chars[*pos + 1];
+            *pos += 2;
+            if end < start {
+                return Err(format!("неверный диапазон {}-{}", start, end));
+            }
+        }
+        ranges.push((start, end));
+    }
+}
+
+fn token_matches(token: &Token, c: char) -> bool {
+    match token {
+        Token::Literal(literal) => *literal == c,
+        Token::Any => true,
+        Token::Star => false,
+        Token::Class { negated, ranges } => {
+            ranges.iter().any(|&(start, end)| (start..=end).contains(&c)) != *negated
+        }
+    }
+}
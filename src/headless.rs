@@ -0,0 +1,502 @@
+//! Modes that run without the TUI and never touch the terminal settings
+
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+
+use crate::cli::Args;
+use crate::errors::SelectorError;
+use crate::log::Log;
+use crate::timings::{self, Timings};
+use crate::{check_lists_dir, checksum, counts, display_path, file_ops, hook, merge, remote, selection, state, stats_json, validate_json, OUTPUT_FILE};
+
+/// `--version`: crate version, plus the resource InternalName on Windows builds
+pub fn print_version() {
+    println!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
+    if cfg!(windows) {
+        println!("{}", env!("INTERNAL_NAME"));
+    }
+}
+
+/// `--doctor`: describe how the tool sees its environment, for bug reports
+pub fn print_doctor(args: &Args) -> io::Result<()> {
+    let log = Log::new(args.verbosity);
+    let yes_no = |value: bool| if value { "да" } else { "нет" };
+    let lists_dir = &args.dir;
+
+    log.info(format!("Папка со списками: {}", display_path(lists_dir)));
+    log.info(format!("Источник настройки: {}", args.dir_source.describe()));
+    if let Some(state_dir) = &args.state_dir {
+        log.info(format!("Папка состояния (--state-dir): {}", display_path(state_dir)));
+    }
+    log.info(format!("Папка существует: {}", yes_no(lists_dir.is_dir())));
+    if let Err(e) = check_lists_dir(lists_dir, false) {
+        log.error(format!("Проблема: {}", e));
+        return Ok(());
+    }
+
+    match file_ops::scan_lists(lists_dir, &args.prefix, &args.suffix, args.output.as_deref()) {
+        Ok(names) => {
+            log.info(format!("Найдено списков ({}*{}): {}", args.prefix, args.suffix, names.len()));
+            for name in &names {
+                log.detail(format!("  {}", name));
+            }
+        }
+        Err(e) => log.error(format!("Проблема: {}", e)),
+    }
+
+    let config_path = selection::path(args);
+    let config_name = config_path.file_name().unwrap_or_default().to_string_lossy();
+    match selection::read(&config_path) {
+        Ok(saved) if config_path.exists() => log.info(format!("{}: есть, выбрано: {}", config_name, saved.names.len())),
+        Ok(_) => log.info(format!("{}: нет", config_name)),
+        Err(e) => log.error(format!("Проблема: {}", e)),
+    }
+    // The files the merge writes with these options, -o and --split-size included
+    let output_path = args.output.clone().unwrap_or_else(|| lists_dir.join(OUTPUT_FILE));
+    let output_name = display_path(&output_path);
+    let output_files = merge::output_files(&output_path, args.split_size);
+    if args.split_size.is_some() {
+        log.info(format!("{}: частей: {}", output_name, output_files.len()));
+    } else {
+        log.info(format!("{}: {}", output_name, yes_no(output_path.is_file())));
+    }
+    let content: io::Result<Vec<u8>> = output_files.iter()
+        .map(fs::read)
+        .try_fold(Vec::new(), |mut content, part| {
+            content.extend(part?);
+            Ok(content)
+        });
+    let written = content.ok().filter(|_| !output_files.is_empty());
+    if let (Some(stored), Some(written)) = (state::get(args.state_dir(), state::OUTPUT_CHECKSUM), written) {
+        let actual = checksum::format(checksum::crc32(&written));
+        if actual == stored {
+            log.info(format!("Контрольная сумма {}: {}, совпадает с последним объединением", output_name, actual));
+        } else {
+            log.error(format!("Внимание: {} изменён после последнего объединения ({} вместо {})", output_name, actual, stored));
+        }
+    }
+
+    Ok(())
+}
+
+/// `--diff-merge A B`: the domains only the merge of A has as "- domain" lines, then the ones
+/// only the merge of B has as "+ domain", each sorted. A selection that doesn't exist as given is
+/// looked up where selected.txt is kept. Returns 0 when both merges are the same, 1 when they
+/// differ and 2 when a selection or a list can't be read, like diff(1).
+pub fn diff_merge(args: &Args, a: &Path, b: &Path) -> i32 {
+    let log = Log::new(args.verbosity);
+    let lists_dir = &args.dir;
+    let options = match merge::MergeOptions::from_args(args, lists_dir) {
+        Ok(options) => options,
+        Err(e) => {
+            log.error(format!("Ошибка: {}", e));
+            return 2;
+        }
+    };
+    let available = match file_ops::scan_lists(lists_dir, &args.prefix, &args.suffix, args.output.as_deref()) {
+        Ok(available) => available,
+        Err(e) => {
+            log.error(format!("Ошибка: {}", e));
+            return 2;
+        }
+    };
+
+    let mut merged = Vec::new();
+    for path in [a, b] {
+        let path = if path.exists() { path.to_path_buf() } else { args.state_dir().join(path) };
+        if !path.is_file() {
+            log.error(format!("Ошибка: {} не найден", path.display()));
+            return 2;
+        }
+        let names = match selection::read(&path) {
+            Ok(saved) => saved.names,
+            Err(e) => {
+                log.error(format!("Ошибка: {}", e));
+                return 2;
+            }
+        };
+        let (present, missing): (Vec<&str>, Vec<&str>) = names.iter()
+            .map(String::as_str)
+            .partition(|name| available.iter().any(|available| available == name));
+        for name in &missing {
+            log.error(format!("Внимание: {} из {} не найден, пропущен", name, path.display()));
+        }
+        match merge::build_merged_set(lists_dir, &present, &options) {
+            Ok(domains) => merged.push(domains),
+            Err(e) => {
+                log.error(format!("Ошибка при объединении файлов: {}", e));
+                return 2;
+            }
+        }
+    }
+
+    let (merged_a, merged_b) = (&merged[0], &merged[1]);
+    let removed: Vec<&String> = merged_a.difference(merged_b).collect();
+    let added: Vec<&String> = merged_b.difference(merged_a).collect();
+    let mut stdout = io::stdout().lock();
+    for domain in &removed {
+        let _ = writeln!(stdout, "- {}", domain);
+    }
+    for domain in &added {
+        let _ = writeln!(stdout, "+ {}", domain);
+    }
+
+    if removed.is_empty() && added.is_empty() { 0 } else { 1 }
+}
+
+/// `--list`: tab-separated "name<TAB>domain count" lines for scripts
+pub fn print_lists(args: &Args) -> io::Result<()> {
+    let mut stdout = io::stdout().lock();
+    for name in file_ops::scan_lists(&args.dir, &args.prefix, &args.suffix, args.output.as_deref())? {
+        let count = match file_ops::read_domains(&remote::content_path(&args.dir, &name)) {
+            Ok(domains) => domains.len(),
+            // Reported on stderr so the lines on stdout stay parseable
+            Err(e @ SelectorError::Encoding { .. }) => {
+                eprintln!("{}", e);
+                continue;
+            }
+            Err(e) => return Err(e.into()),
+        };
+        writeln!(stdout, "{}\t{}", name, count)?;
+    }
+
+    Ok(())
+}
+
+/// `--validate-json PATH`: check every list and write the invalid entries to PATH. Returns 1 when
+/// any entry is invalid or a list can't be read, so a CI job fails on it.
+pub fn validate_json(args: &Args, path: &Path) -> i32 {
+    let log = Log::new(args.verbosity);
+    let lists_dir = &args.dir;
+    let names = match file_ops::scan_lists(lists_dir, &args.prefix, &args.suffix, args.output.as_deref()) {
+        Ok(names) => names,
+        Err(e) => {
+            log.error(format!("Ошибка: {}", e));
+            return 1;
+        }
+    };
+
+    let mut files = Vec::new();
+    let mut failed = Vec::new();
+    for name in names {
+        let content = remote::content_path(lists_dir, &name);
+        // Nothing downloaded yet, there is nothing to check
+        if remote::is_remote(&name) && !content.exists() {
+            log.detail(format!("{}: нет загруженной копии, пропущен", name));
+            continue;
+        }
+        match file_ops::invalid_entries(&content) {
+            Ok(invalid) => {
+                if !invalid.is_empty() {
+                    log.detail(format!("{}: некорректных строк: {}", name, invalid.len()));
+                }
+                files.push(validate_json::FileReport { name, invalid });
+            }
+            Err(e) => {
+                log.error(format!("Ошибка: {}", e));
+                failed.push((name, e.to_string()));
+            }
+        }
+    }
+
+    if let Err(e) = validate_json::write(path, lists_dir, &files, &failed) {
+        log.error(format!("Ошибка: не удалось записать отчёт: {}", e));
+        return 1;
+    }
+    let invalid: usize = files.iter().map(|file| file.invalid.len()).sum();
+    let with_invalid = files.iter().filter(|file| !file.invalid.is_empty()).count();
+    log.info(format!(
+        "Проверено списков: {}, некорректных строк: {} (в списках: {}), отчёт: {}",
+        files.len(), invalid, with_invalid, path.display()
+    ));
+    if invalid > 0 || !failed.is_empty() { 1 } else { 0 }
+}
+
+/// `--apply`: regenerate the merged list from the saved selection. Returns the process exit code.
+pub fn apply(args: &Args, timings: &Timings) -> i32 {
+    let log = Log::new(args.verbosity);
+    let lists_dir = &args.dir;
+
+    let config_path = selection::path(args);
+    let selection = match selection::read(&config_path) {
+        Ok(selection) => selection.names,
+        Err(e) => {
+            log.error(format!("Ошибка: {}", e));
+            return 1;
+        }
+    };
+
+    timings.begin(timings::SCAN);
+    let available = file_ops::scan_lists(lists_dir, &args.prefix, &args.suffix, args.output.as_deref());
+    timings.end(timings::SCAN);
+    let available = match available {
+        Ok(available) => available,
+        Err(e) => {
+            log.error(format!("Ошибка: {}", e));
+            return 1;
+        }
+    };
+
+    let (present, missing): (Vec<&str>, Vec<&str>) = selection.iter()
+        .map(String::as_str)
+        .partition(|name| available.iter().any(|available| available == name));
+    for name in &missing {
+        log.error(format!("Внимание: {} из {} не найден, пропущен", name, config_path.display()));
+    }
+    let present = top_n(&log, args, lists_dir, present);
+
+    let options = match merge::MergeOptions::from_args(args, lists_dir) {
+        Ok(options) => options,
+        Err(e) => {
+            log.error(format!("Ошибка при объединении файлов: {}", e));
+            return 1;
+        }
+    };
+
+    // Downloads can change remote lists at any time, so they always count as changed
+    let sources = sources_fingerprint(lists_dir, &config_path, &present, &options);
+    let mut output = options.output.clone().unwrap_or_else(|| lists_dir.join(OUTPUT_FILE));
+    if options.split_size.is_some() {
+        output = merge::chunk_path(&output, 1);
+    }
+    let refreshes = options.allow_network && present.iter().any(|name| remote::is_remote(name));
+    if args.if_changed && !refreshes && output.exists()
+        && state::get(args.state_dir(), state::SOURCES_CHECKSUM) == Some(checksum::format(sources))
+    {
+        log.info("Изменений нет, объединение пропущено");
+        return 0;
+    }
+
+    let Some(stats) = merge_guarded(&log, args, lists_dir, &present, &options, timings) else {
+        return 1;
+    };
+
+    report_merge(&log, &stats);
+    if let Err(e) = state::set(args.state_dir(), state::SOURCES_CHECKSUM, &checksum::format(sources)) {
+        log.error(format!("Внимание: не удалось сохранить состояние: {}", e));
+    }
+    let stats_code = write_stats_json(&log, args, &stats);
+    let hook_code = match &args.post_save_cmd {
+        Some(command) => run_post_save_cmd(&log, command),
+        None => 0,
+    };
+    stats_code.max(hook_code)
+}
+
+/// Monitoring relies on the file, so failing to write it fails the run
+fn write_stats_json(log: &Log, args: &Args, stats: &merge::MergeStats) -> i32 {
+    let Some(path) = &args.stats_json else {
+        return 0;
+    };
+    match stats_json::write(path, stats) {
+        Ok(()) => 0,
+        Err(e) => {
+            log.error(format!("Ошибка: не удалось записать статистику: {}", e));
+            1
+        }
+    }
+}
+
+/// CRC-32 over the options and the contents of the selection file, the selected lists
+/// and the exclude files and the denylist. Missing files count as empty.
+fn sources_fingerprint(lists_dir: &Path, config_path: &Path, present: &[&str], options: &merge::MergeOptions) -> u32 {
+    let mut data = format!("{:?}", options).into_bytes();
+    let list_paths = present.iter().map(|name| remote::content_path(lists_dir, name));
+    let sources = std::iter::once(config_path.to_path_buf())
+        .chain(list_paths)
+        .chain(options.exclude_files.iter().cloned())
+        .chain(options.denylist.clone())
+        .chain(options.pin_file.clone());
+    for path in sources {
+        data.extend_from_slice(path.to_string_lossy().as_bytes());
+        data.push(0);
+        data.extend_from_slice(&checksum::file_crc32(&path).unwrap_or(0).to_be_bytes());
+    }
+    checksum::crc32(&data)
+}
+
+fn run_post_save_cmd(log: &Log, command: &str) -> i32 {
+    match hook::run(command) {
+        Ok(()) => {
+            log.detail(format!("Команда после сохранения выполнена: {}", command));
+            0
+        }
+        Err(e) => {
+            log.error(format!("Ошибка команды после сохранения ({}): {}", command, e));
+            1
+        }
+    }
+}
+
+/// `--merge a.txt b.txt [-o out.txt]`: merge the named lists only, selected.txt is neither read nor written
+pub fn merge_only(args: &Args, names: &[String], timings: &Timings) -> i32 {
+    let log = Log::new(args.verbosity);
+    let lists_dir = &args.dir;
+
+    let missing: Vec<&String> = names.iter()
+        .filter(|name| !lists_dir.join(name).is_file())
+        .collect();
+    if !missing.is_empty() {
+        for name in missing {
+            log.error(format!("Ошибка: {} не найден в {}", name, display_path(lists_dir)));
+        }
+        return 1;
+    }
+    if let Some(output) = &args.output {
+        let inputs: Vec<&String> = names.iter()
+            .filter(|name| file_ops::is_output_path(&lists_dir.join(name), output))
+            .collect();
+        if !inputs.is_empty() {
+            for name in inputs {
+                log.error(format!("Ошибка: {} — это файл результата (-o), он не может быть объединён сам с собой", name));
+            }
+            return 1;
+        }
+    }
+
+    let selected: Vec<&str> = names.iter().map(String::as_str).collect();
+    let selected = top_n(&log, args, lists_dir, selected);
+    let options = match merge::MergeOptions::from_args(args, lists_dir) {
+        Ok(options) => options,
+        Err(e) => {
+            log.error(format!("Ошибка при объединении файлов: {}", e));
+            return 1;
+        }
+    };
+    match merge_guarded(&log, args, lists_dir, &selected, &options, timings) {
+        Some(stats) => {
+            report_merge(&log, &stats);
+            write_stats_json(&log, args, &stats)
+        }
+        None => 1,
+    }
+}
+
+/// --top-n: only the N lists with the most domains, kept in their selection order. Equal counts
+/// go by file name, unreadable lists count as empty and the merge reports them.
+fn top_n<'a>(log: &Log, args: &Args, lists_dir: &Path, selected: Vec<&'a str>) -> Vec<&'a str> {
+    let Some(limit) = args.top_n else {
+        return selected;
+    };
+    let mut ranked: Vec<(&str, usize)> = selected.iter()
+        .map(|name| (*name, counts::count(lists_dir, name).unwrap_or(0)))
+        .collect();
+    ranked.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+    let describe = |names: &[&str]| -> String {
+        let described: Vec<String> = names.iter()
+            .map(|name| {
+                let count = ranked.iter().find(|(ranked, _)| ranked == name).map_or(0, |(_, count)| *count);
+                format!("{} ({})", name, count)
+            })
+            .collect();
+        described.join(", ")
+    };
+
+    let (included, excluded): (Vec<&str>, Vec<&str>) = selected.iter()
+        .partition(|name| ranked.iter().take(limit).any(|(ranked, _)| ranked == *name));
+    log.info(format!("--top-n {}: взяты списки: {}", limit, describe(&included)));
+    if !excluded.is_empty() {
+        log.info(format!("Не вошли в --top-n {}: {}", limit, describe(&excluded)));
+    }
+    included
+}
+
+/// merge::prepare and merge::write with the --max-dup-ratio check in between. Failures are
+/// logged here, None means the run failed and nothing was written.
+fn merge_guarded(
+    log: &Log,
+    args: &Args,
+    lists_dir: &Path,
+    selected: &[&str],
+    options: &merge::MergeOptions,
+    timings: &Timings
+) -> Option<merge::MergeStats> {
+    timings.begin(timings::MERGE);
+    let prepared = merge::prepare(lists_dir, selected, options, &mut |_, _| false);
+    timings.end(timings::MERGE);
+    let prepared = match prepared {
+        Ok(Some(prepared)) => prepared,
+        Ok(None) => unreachable!("a merge without a stop condition was stopped"),
+        Err(e) => {
+            log.error(format!("Ошибка при объединении файлов: {}", e));
+            return None;
+        }
+    };
+
+    if let Some(max) = args.max_dup_ratio {
+        let ratio = prepared.stats().duplicate_ratio();
+        if ratio > max {
+            log.error(format!(
+                "Ошибка: доля дубликатов {:.1}% больше допустимой {:.1}% (--max-dup-ratio), ничего не записано",
+                ratio * 100.0, max * 100.0
+            ));
+            return None;
+        }
+        log.info(format!("Доля дубликатов: {:.1}% (допустимо до {:.1}%)", ratio * 100.0, max * 100.0));
+    }
+
+    timings.begin(timings::MERGE_WRITE);
+    let written = merge::write(lists_dir, prepared, options);
+    timings.end(timings::MERGE_WRITE);
+    match written {
+        Ok(stats) => Some(stats),
+        Err(e) => {
+            log.error(format!("Ошибка при объединении файлов: {}", e));
+            None
+        }
+    }
+}
+
+fn report_merge(log: &Log, stats: &merge::MergeStats) {
+    for file in &stats.per_file {
+        log.detail(format!("{}: прочитано доменов: {}, повторов: {}", file.name, file.domains, file.duplicates));
+    }
+    for warning in &stats.warnings {
+        log.error(format!("Внимание: {}", warning));
+    }
+    if !stats.invalid.is_empty() {
+        log.error(format!("Внимание: отброшено некорректных строк: {}", stats.invalid.len()));
+    }
+    log.info(format!(
+        "Объединено списков: {}, доменов: {}, дубликатов удалено: {}, исключено: {}, контрольная сумма: {}",
+        stats.per_file.len(),
+        stats.written,
+        stats.duplicates,
+        stats.excluded,
+        checksum::format(stats.checksum)
+    ));
+    if stats.pinned > 0 {
+        log.info(format!("Закреплено в начале списка: {}", stats.pinned));
+    }
+    if stats.idn_collapsed > 0 {
+        log.info(format!("Сведено повторов IDN (кириллица и punycode): {}", stats.idn_collapsed));
+    }
+    if stats.case_collapsed > 0 {
+        log.info(format!("Сведено повторов в другом регистре: {}", stats.case_collapsed));
+    }
+    if stats.denied > 0 {
+        log.info(format!("Убрано по {}: {}", merge::DENYLIST_FILE, stats.denied));
+    }
+    if let Some(semantics) = stats.semantics {
+        log.info(format!("Назначение: {}", semantics.label()));
+    }
+    if stats.unchanged > 0 {
+        log.info(format!("Без изменений, не переписано файлов: {}", stats.unchanged));
+    }
+    if let Some(seed) = stats.shuffle_seed {
+        log.info(format!("Порядок перемешан, повторить его: --shuffle {}", seed));
+    }
+    if let Some((added, removed)) = stats.changes {
+        log.info(format!("Изменения с прошлого объединения: +{} / -{}", added, removed));
+    }
+    if let Some(backup) = &stats.backup {
+        log.info(format!("Предыдущий список сохранён в {}", backup.display()));
+    }
+    if !stats.chunks.is_empty() {
+        log.info(format!("Записано частей: {}", stats.chunks.len()));
+        for chunk in &stats.chunks {
+            log.detail(format!("  {}", chunk.display()));
+        }
+    }
+}
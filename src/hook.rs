@@ -0,0 +1,73 @@
+//! --post-save-cmd: a user command run after a successful merge, e.g. to restart zapret, and
+//! --transform-cmd: a user command the merged output is piped through before it is written.
+//! They go through the system shell as given, so they can do anything the user can.
+
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+use std::thread;
+
+fn shell(command: &str) -> Command {
+    if cfg!(windows) {
+        let mut shell = Command::new("cmd");
+        shell.args(["/C", command]);
+        shell
+    } else {
+        let mut shell = Command::new("sh");
+        shell.args(["-c", command]);
+        shell
+    }
+}
+
+/// Run the command and wait for it. Its output is captured rather than shown, the TUI owns
+/// the terminal; on failure the last line of stderr is part of the error.
+pub fn run(command: &str) -> Result<(), String> {
+    let output = shell(command).output().map_err(|e| format!("не удалось запустить: {}", e))?;
+    check_status(&output)
+}
+
+/// Pipe input through the command and return what it printed. A failure, or output that
+/// isn't UTF-8, is an error and nothing of the output is used.
+pub fn transform(command: &str, input: &str) -> Result<String, String> {
+    let mut child = shell(command)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("не удалось запустить: {}", e))?;
+
+    // Written from another thread, a command that prints before it has read everything
+    // would block on a full stdout pipe otherwise
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    let input = input.to_string();
+    let writer = thread::spawn(move || stdin.write_all(input.as_bytes()));
+    let output = child.wait_with_output().map_err(|e| format!("не удалось дождаться завершения: {}", e))?;
+    let written = writer.join().unwrap_or(Ok(()));
+
+    check_status(&output)?;
+    // A command that exits fine without reading its input is its own business, a broken
+    // pipe is only an error when it failed as well
+    if let Err(e) = written {
+        if e.kind() != std::io::ErrorKind::BrokenPipe {
+            return Err(format!("не удалось передать список: {}", e));
+        }
+    }
+    String::from_utf8(output.stdout).map_err(|_| String::from("вывод не в UTF-8"))
+}
+
+fn check_status(output: &Output) -> Result<(), String> {
+    if output.status.success() {
+        return Ok(());
+    }
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let last_line = stderr.lines().rev().find(|line| !line.trim().is_empty()).unwrap_or("").trim();
+    let status = match output.status.code() {
+        Some(code) => format!("код выхода {}", code),
+        None => String::from("прерван сигналом"),
+    };
+    if last_line.is_empty() {
+        Err(status)
+    } else {
+        Err(format!("{}: {}", status, last_line))
+    }
+}
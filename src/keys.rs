@@ -0,0 +1,277 @@
+//! [keys] in selector.toml of the state folder: keys of the list view remapped by action
+//! name, e.g.
+//!
+//! ```toml
+//! [keys]
+//! down = "j"
+//! up = "k"
+//! save = "ctrl+w"
+//! ```
+//!
+//! A key is a single character or one of the names in NAMED_KEYS, optionally after "ctrl+",
+//! "alt+" or "shift+". A mapped key is translated to the default key of its action before the
+//! list view handles it, so the default keys keep working unless another action takes them.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+use crate::selection;
+
+/// Action names for [keys] with their default keys
+pub const ACTIONS: &[(&str, KeyCode, KeyModifiers)] = &[
+    ("up", KeyCode::Up, KeyModifiers::NONE),
+    ("down", KeyCode::Down, KeyModifiers::NONE),
+    ("toggle", KeyCode::Char(' '), KeyModifiers::NONE),
+    ("menu", KeyCode::Right, KeyModifiers::NONE),
+    ("move-up", KeyCode::Up, KeyModifiers::SHIFT),
+    ("move-down", KeyCode::Down, KeyModifiers::SHIFT),
+    ("scroll-left", KeyCode::Left, KeyModifiers::SHIFT),
+    ("scroll-right", KeyCode::Right, KeyModifiers::SHIFT),
+    ("save", KeyCode::Char('s'), KeyModifiers::CONTROL),
+    ("search", KeyCode::Char('/'), KeyModifiers::NONE),
+    ("help", KeyCode::Char('?'), KeyModifiers::NONE),
+    ("reset", KeyCode::Char('z'), KeyModifiers::NONE),
+    ("inverse-saved", KeyCode::Char('i'), KeyModifiers::NONE),
+    ("overlap", KeyCode::Char('v'), KeyModifiers::NONE),
+    ("saved-file", KeyCode::Char('s'), KeyModifiers::NONE),
+    ("dedup", KeyCode::Char('d'), KeyModifiers::NONE),
+    ("normalize", KeyCode::Char('n'), KeyModifiers::NONE),
+    ("favorite", KeyCode::Char('*'), KeyModifiers::NONE),
+    ("favorites", KeyCode::Char('f'), KeyModifiers::NONE),
+    ("selected-only", KeyCode::Char('h'), KeyModifiers::NONE),
+    ("audit", KeyCode::Char('a'), KeyModifiers::NONE),
+    ("mark", KeyCode::Char('x'), KeyModifiers::NONE),
+    ("delete-marked", KeyCode::Delete, KeyModifiers::NONE),
+    ("copy-name", KeyCode::Char('y'), KeyModifiers::NONE),
+    ("copy-path", KeyCode::Char('Y'), KeyModifiers::NONE),
+    ("sort", KeyCode::Char('o'), KeyModifiers::NONE),
+    ("counts", KeyCode::Char('%'), KeyModifiers::NONE),
+    ("save-counts", KeyCode::Char('c'), KeyModifiers::NONE),
+    ("merge", KeyCode::Char('m'), KeyModifiers::NONE),
+    ("write-as", KeyCode::Char('w'), KeyModifiers::NONE),
+    ("quit", KeyCode::Char('q'), KeyModifiers::NONE),
+];
+
+/// Names of the keys that aren't a single character
+const NAMED_KEYS: &[(&str, KeyCode)] = &[
+    ("up", KeyCode::Up),
+    ("down", KeyCode::Down),
+    ("left", KeyCode::Left),
+    ("right", KeyCode::Right),
+    ("enter", KeyCode::Enter),
+    ("space", KeyCode::Char(' ')),
+    ("tab", KeyCode::Tab),
+    ("backspace", KeyCode::Backspace),
+    ("delete", KeyCode::Delete),
+    ("insert", KeyCode::Insert),
+    ("home", KeyCode::Home),
+    ("end", KeyCode::End),
+    ("pageup", KeyCode::PageUp),
+    ("pagedown", KeyCode::PageDown),
+];
+
+type Key = (KeyCode, KeyModifiers);
+
+#[derive(Default)]
+pub struct Keymap {
+    /// Typed key to the default key of the action it is mapped to
+    bindings: HashMap<Key, Key>,
+}
+
+impl Keymap {
+    /// The [keys] table of selector.toml in state_dir, with a warning for every mapping that
+    /// isn't used. Without the file or the table nothing is remapped.
+    pub fn load(state_dir: &Path) -> (Keymap, Vec<String>) {
+        let path = state_dir.join(selection::TOML_FILE);
+        let Ok(content) = fs::read_to_string(&path) else {
+            return (Keymap::default(), Vec::new());
+        };
+        let table: toml::Table = match content.parse() {
+            Ok(table) => table,
+            Err(e) => {
+                let e: toml::de::Error = e;
+                return (Keymap::default(), vec![format!("[keys] не прочитан, {}: {}", selection::TOML_FILE, e.message())]);
+            }
+        };
+        match table.get("keys") {
+            Some(toml::Value::Table(keys)) => Keymap::from_table(keys),
+            Some(_) => (Keymap::default(), vec![String::from("[keys] должен быть таблицей, клавиши по умолчанию")]),
+            None => (Keymap::default(), Vec::new()),
+        }
+    }
+
+    fn from_table(keys: &toml::Table) -> (Keymap, Vec<String>) {
+        let mut warnings = Vec::new();
+        let mut by_key: HashMap<Key, Vec<&str>> = HashMap::new();
+        for (action, spec) in keys {
+            let Some(&(name, code, modifiers)) = ACTIONS.iter().find(|(name, ..)| name == action) else {
+                warnings.push(format!("[keys]: неизвестное действие «{}»", action));
+                continue;
+            };
+            let Some(key) = spec.as_str().and_then(parse) else {
+                warnings.push(format!("[keys] {}: не клавиша: {}, оставлена {}", name, spec, describe((code, modifiers))));
+                continue;
+            };
+            if key == (KeyCode::Char('c'), KeyModifiers::CONTROL) {
+                warnings.push(format!("[keys] {}: Ctrl+C всегда выход, оставлена {}", name, describe((code, modifiers))));
+                continue;
+            }
+            by_key.entry(key).or_default().push(name);
+        }
+
+        let mut bindings = HashMap::new();
+        for (key, actions) in by_key {
+            if let [action] = actions[..] {
+                let (_, code, modifiers) = ACTIONS.iter().find(|(name, ..)| *name == action).expect("known action");
+                bindings.insert(key, (*code, *modifiers));
+            } else {
+                let mut actions = actions;
+                actions.sort();
+                warnings.push(format!(
+                    "[keys]: {} назначена для {}, для них клавиши по умолчанию",
+                    describe(key), actions.join(", ")
+                ));
+            }
+        }
+        warnings.sort();
+        (Keymap { bindings }, warnings)
+    }
+
+    /// The key the list view should handle for the one typed
+    pub fn translate(&self, key: KeyEvent) -> KeyEvent {
+        match self.bindings.get(&normalize(key.code, key.modifiers)) {
+            Some(&(code, modifiers)) => KeyEvent::new(code, modifiers),
+            None => key,
+        }
+    }
+}
+
+/// "ctrl+s", "J", "pagedown", "shift+up"
+fn parse(spec: &str) -> Option<Key> {
+    let mut modifiers = KeyModifiers::NONE;
+    let mut rest = spec.trim();
+    loop {
+        let lower = rest.to_lowercase();
+        let (modifier, length) = if lower.starts_with("ctrl+") {
+            (KeyModifiers::CONTROL, 5)
+        } else if lower.starts_with("alt+") {
+            (KeyModifiers::ALT, 4)
+        } else if lower.starts_with("shift+") {
+            (KeyModifiers::SHIFT, 6)
+        } else {
+            break;
+        };
+        modifiers |= modifier;
+        rest = &rest[length..];
+    }
+
+    let mut chars = rest.chars();
+    let code = match (chars.next(), chars.next()) {
+        (Some(c), None) => KeyCode::Char(c),
+        _ => NAMED_KEYS.iter().find(|(name, _)| rest.eq_ignore_ascii_case(name))?.1,
+    };
+    Some(normalize(code, modifiers))
+}
+
+/// Shift is part of the character itself, terminals differ in whether they report it too.
+/// With Ctrl they report the letter lowercase whatever Shift is, so "ctrl+W" is Ctrl+W.
+fn normalize(code: KeyCode, modifiers: KeyModifiers) -> Key {
+    let modifiers = modifiers & (KeyModifiers::CONTROL | KeyModifiers::ALT | KeyModifiers::SHIFT);
+    match code {
+        KeyCode::Char(c) if modifiers.contains(KeyModifiers::CONTROL) => {
+            (KeyCode::Char(c.to_lowercase().next().unwrap_or(c)), modifiers - KeyModifiers::SHIFT)
+        }
+        KeyCode::Char(c) if modifiers.contains(KeyModifiers::SHIFT) && c.is_alphabetic() => {
+            (KeyCode::Char(c.to_uppercase().next().unwrap_or(c)), modifiers - KeyModifiers::SHIFT)
+        }
+        KeyCode::Char(_) => (code, modifiers - KeyModifiers::SHIFT),
+        _ => (code, modifiers),
+    }
+}
+
+fn describe((code, modifiers): Key) -> String {
+    let mut text = String::new();
+    if modifiers.contains(KeyModifiers::CONTROL) {
+        text.push_str("Ctrl+");
+    }
+    if modifiers.contains(KeyModifiers::ALT) {
+        text.push_str("Alt+");
+    }
+    if modifiers.contains(KeyModifiers::SHIFT) {
+        text.push_str("Shift+");
+    }
+    match code {
+        KeyCode::Char(' ') => text.push_str("space"),
+        KeyCode::Char(c) => text.push(c),
+        _ => text.push_str(NAMED_KEYS.iter().find(|(_, named)| *named == code).map_or("?", |(name, _)| name)),
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keymap(toml: &str) -> (Keymap, Vec<String>) {
+        Keymap::from_table(&toml.parse().unwrap())
+    }
+
+    fn key(code: KeyCode, modifiers: KeyModifiers) -> KeyEvent {
+        KeyEvent::new(code, modifiers)
+    }
+
+    #[test]
+    fn key_specs() {
+        assert_eq!(parse("j"), Some((KeyCode::Char('j'), KeyModifiers::NONE)));
+        assert_eq!(parse("J"), Some((KeyCode::Char('J'), KeyModifiers::NONE)));
+        assert_eq!(parse("shift+j"), Some((KeyCode::Char('J'), KeyModifiers::NONE)));
+        assert_eq!(parse("Ctrl+W"), Some((KeyCode::Char('w'), KeyModifiers::CONTROL)));
+        assert_eq!(parse("ctrl+shift+w"), Some((KeyCode::Char('w'), KeyModifiers::CONTROL)));
+        assert_eq!(parse("ctrl+alt+end"), Some((KeyCode::End, KeyModifiers::CONTROL | KeyModifiers::ALT)));
+        assert_eq!(parse("shift+up"), Some((KeyCode::Up, KeyModifiers::SHIFT)));
+        assert_eq!(parse("PageDown"), Some((KeyCode::PageDown, KeyModifiers::NONE)));
+        assert_eq!(parse("%"), Some((KeyCode::Char('%'), KeyModifiers::NONE)));
+        assert_eq!(parse("ctrl+"), None);
+        assert_eq!(parse("jk"), None);
+    }
+
+    #[test]
+    fn a_mapped_key_does_what_its_action_does() {
+        let (keymap, warnings) = keymap("down = \"j\"\nsave = \"ctrl+w\"");
+        assert!(warnings.is_empty());
+        assert_eq!(keymap.translate(key(KeyCode::Char('j'), KeyModifiers::NONE)), key(KeyCode::Down, KeyModifiers::NONE));
+        assert_eq!(keymap.translate(key(KeyCode::Char('w'), KeyModifiers::CONTROL)), key(KeyCode::Char('s'), KeyModifiers::CONTROL));
+        // Default keys keep working
+        assert_eq!(keymap.translate(key(KeyCode::Down, KeyModifiers::NONE)), key(KeyCode::Down, KeyModifiers::NONE));
+    }
+
+    #[test]
+    fn conflicts_and_mistakes_keep_the_defaults() {
+        let (keymap, warnings) = keymap("up = \"k\"\ndown = \"k\"\nfly = \"f\"\nhelp = \"ctrl+c\"\nsort = 5");
+        assert_eq!(warnings.len(), 4);
+        assert!(warnings.iter().any(|warning| warning.contains("down, up")));
+        assert_eq!(keymap.translate(key(KeyCode::Char('k'), KeyModifiers::NONE)), key(KeyCode::Char('k'), KeyModifiers::NONE));
+        assert!(keymap.bindings.is_empty());
+    }
+
+    #[test]
+    fn an_uppercase_ctrl_binding_fires_and_ctrl_c_stays_reserved() {
+        let (keymap, warnings) = keymap("save = \"Ctrl+W\"\nhelp = \"ctrl+C\"");
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("Ctrl+C"));
+        let save = key(KeyCode::Char('s'), KeyModifiers::CONTROL);
+        assert_eq!(keymap.translate(key(KeyCode::Char('w'), KeyModifiers::CONTROL)), save);
+        assert_eq!(keymap.translate(key(KeyCode::Char('W'), KeyModifiers::CONTROL | KeyModifiers::SHIFT)), save);
+        assert_eq!(keymap.translate(key(KeyCode::Char('c'), KeyModifiers::CONTROL)), key(KeyCode::Char('c'), KeyModifiers::CONTROL));
+    }
+
+    #[test]
+    fn ctrl_bindings_differing_in_case_conflict() {
+        let (keymap, warnings) = keymap("save = \"ctrl+w\"\nmerge = \"ctrl+W\"");
+        assert_eq!(warnings.len(), 1);
+        assert!(keymap.bindings.is_empty());
+    }
+}
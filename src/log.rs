@@ -0,0 +1,37 @@
+use std::fmt::Display;
+
+/// Output level of the headless modes (--apply, --list, --doctor), the TUI ignores it
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub enum Verbosity {
+    /// Errors only
+    Quiet,
+    Normal,
+    /// Per-file details as well
+    Verbose,
+}
+
+pub struct Log {
+    verbosity: Verbosity,
+}
+
+impl Log {
+    pub fn new(verbosity: Verbosity) -> Log {
+        Log { verbosity }
+    }
+
+    pub fn info(&self, message: impl Display) {
+        if self.verbosity >= Verbosity::Normal {
+            println!("{}", message);
+        }
+    }
+
+    pub fn detail(&self, message: impl Display) {
+        if self.verbosity >= Verbosity::Verbose {
+            println!("{}", message);
+        }
+    }
+
+    pub fn error(&self, message: impl Display) {
+        eprintln!("{}", message);
+    }
+}
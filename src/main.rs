@@ -1,314 +1,2145 @@
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::env;
 use std::fs::{self, File};
-use std::io::{self, Write, Read};
-use std::path::Path;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use crossterm::{
     execute,
     event::{self, Event, KeyCode, KeyEvent, KeyModifiers},
     terminal::{self, ClearType, EnterAlternateScreen, LeaveAlternateScreen},
-    style::{self, Stylize},
+    style::Stylize,
     cursor::{self, Hide, Show},
-    queue,
 };
 
-#[derive(Debug)]
-struct FileEntry {
-    name: String,
-    selected: bool,
-    is_control: bool,
-}
+mod app;
+mod checksum;
+mod cli;
+mod clipboard;
+mod counts;
+mod draft;
+mod encoding;
+mod errors;
+mod favorites;
+mod file_ops;
+mod glob;
+mod headless;
+mod hook;
+mod keys;
+mod log;
+mod merge;
+mod punycode;
+mod remote;
+#[cfg(test)]
+mod scratch;
+mod search;
+mod selection;
+mod state;
+mod stats_json;
+mod template;
+mod timings;
+mod ui;
+mod validate_json;
+mod width;
+mod wizard;
+
+use app::{App, ControlRow, CountDisplay, FileAction, FileEntry, Mode};
+use cli::Args;
+use timings::Timings;
 
 const VISIBLE_ITEMS: usize = 15;
 const HEADER_LINES: usize = 5; // Header text + empty line + control buttons + empty line + separator
 const SCROLL_AREA_HEIGHT: usize = VISIBLE_ITEMS + 2; // +2 for scroll indicators
+const STATUS_LINE: usize = HEADER_LINES + SCROLL_AREA_HEIGHT + 1; // One empty line below the scroll area
+const FOOTER_LINE: usize = STATUS_LINE + 1;
+
+// Event loop timeout while background work is delivering results
+const FAST_POLL_MS: u64 = 16;
+
+// Merged result of all selected lists, never offered for selection itself
+const OUTPUT_FILE: &str = "list-ultimate.txt";
+
+// Added and removed domains each listed in the save summary, the rest is counted
+const CHANGES_SHOWN: usize = 200;
+
+// Status shown when --review refuses a key
+const READ_ONLY: &str = "режим только для чтения";
+
+// List authors can put this as the first line to have the list pre-selected on first run
+const DEFAULT_ON_HEADER: &str = "# default: on";
 
 fn main() -> io::Result<()> {
+    let args = match Args::parse() {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("Ошибка: {}", e);
+            std::process::exit(2);
+        }
+    };
+
+    encoding::set_fallback(args.encoding);
+
+    if args.version {
+        headless::print_version();
+        return Ok(());
+    }
+
+    if args.doctor {
+        return headless::print_doctor(&args);
+    }
+
+    let headless = args.list || args.apply || args.merge.is_some() || args.validate_json.is_some()
+        || args.diff_merge.is_some();
+    if let Err(e) = check_lists_dir(&args.dir, !headless) {
+        eprintln!("Ошибка: {}", e);
+        std::process::exit(1);
+    }
+
+    if args.list {
+        return headless::print_lists(&args);
+    }
+    if let Some(path) = &args.validate_json {
+        std::process::exit(headless::validate_json(&args, path));
+    }
+    if let Some((a, b)) = &args.diff_merge {
+        std::process::exit(headless::diff_merge(&args, a, b));
+    }
+
+    if let Some(state_dir) = &args.state_dir {
+        if let Err(e) = fs::create_dir_all(state_dir) {
+            eprintln!("Ошибка: --state-dir {}: {}", display_path(state_dir), e);
+            std::process::exit(1);
+        }
+    }
+
+    // --merge alone never touches the selection
+    if args.merge.is_none() {
+        if let Err(e) = selection::check(&selection::path(&args)) {
+            eprintln!("Ошибка: {}", e);
+            std::process::exit(1);
+        }
+    }
+
+    let timings = Rc::new(Timings::new(args.timings));
+    if let Some(names) = &args.merge {
+        let code = headless::merge_only(&args, names, &timings);
+        timings.print();
+        std::process::exit(code);
+    }
+    if args.apply {
+        let code = headless::apply(&args, &timings);
+        timings.print();
+        std::process::exit(code);
+    }
+
     terminal::enable_raw_mode()?;
     let mut stdout = io::stdout();
-    execute!(stdout, EnterAlternateScreen, Hide)?;
+    let inline = args.inline;
+    if inline {
+        execute!(stdout, Hide)?;
+    } else {
+        execute!(stdout, EnterAlternateScreen, Hide)?;
+    }
 
-    let result = run_app(&mut stdout);
+    let result = run_app(&mut stdout, args, Rc::clone(&timings));
 
-    execute!(stdout, Show, LeaveAlternateScreen)?;
+    if inline {
+        // Leave the last screen in place and continue below it, or below whatever save printed
+        let row = cursor::position().map_or(FOOTER_LINE as u16, |(_, row)| row.max(FOOTER_LINE as u16));
+        execute!(stdout, Show, cursor::MoveTo(0, row + 1))?;
+    } else {
+        execute!(stdout, Show, LeaveAlternateScreen)?;
+    }
     terminal::disable_raw_mode()?;
+    timings.print();
 
-    result
+    // Printed after the terminal is restored, with the message rather than the debug form
+    if let Err(e) = result {
+        eprintln!("Ошибка: {}", e);
+        std::process::exit(1);
+    }
+    Ok(())
 }
 
-fn draw_screen(
-    stdout: &mut io::Stdout,
-    entries: &[FileEntry],
-    current_index: usize,
-    scroll_offset: usize,
-    clear_screen: bool
-) -> io::Result<()> {
-    if clear_screen {
-        queue!(stdout, terminal::Clear(ClearType::All))?;
+/// Whether the file was modified less than hours ago, never with 0. A modification time in
+/// the future (clock skew, files copied from another machine) counts as recent.
+fn changed_within(path: &Path, hours: u64) -> bool {
+    if hours == 0 {
+        return false;
     }
+    let Ok(modified) = fs::metadata(path).and_then(|meta| meta.modified()) else {
+        return false;
+    };
+    modified.elapsed().map_or(true, |age| age < Duration::from_secs(hours * 3600))
+}
 
-    queue!(stdout, cursor::MoveTo(0, 0))?;
+/// Make sure the lists folder is usable before any terminal setup, so problems are reported
+/// as readable messages instead of an io::Error from read_dir later on
+fn check_lists_dir(lists_dir: &Path, create: bool) -> Result<(), String> {
+    if lists_dir.is_dir() {
+        return Ok(());
+    }
 
-    // Header section
-    writeln!(stdout, "Используйте ↑↓ для навигации, ПРОБЕЛ или ENTER для выбора\n")?;
+    if lists_dir.exists() {
+        return Err(format!("'{}' существует, но это не папка", lists_dir.display()));
+    }
 
-    // Draw control options first
-    let control_entries: Vec<_> = entries.iter()
-        .enumerate()
-        .filter(|(_, entry)| entry.is_control)
-        .collect();
+    if !create {
+        return Err(format!("папка '{}' не найдена", lists_dir.display()));
+    }
 
-    for (index, entry) in &control_entries {
-        let name = if entry.name == "SAVE LIST" {
-            "СОХРАНИТЬ СПИСОК"
-        } else {
-            "ОТМЕНА"
-        };
+    fs::create_dir_all(lists_dir)
+        .map_err(|e| format!("не удалось создать папку '{}': {}", lists_dir.display(), e))
+}
 
-        let line = format!(
-            "{}  {}",
-            if *index == current_index { ">" } else { " " },
-            name
-        );
+/// Absolute form of a path for display, without the \\?\ prefix canonicalize adds on Windows
+fn display_path(path: &Path) -> String {
+    let absolute = fs::canonicalize(path)
+        .or_else(|_| env::current_dir().map(|dir| dir.join(path)))
+        .unwrap_or_else(|_| path.to_path_buf());
 
-        if *index == current_index {
-            writeln!(stdout, "{}", line.reverse())?;
-        } else {
-            writeln!(stdout, "{}", line)?;
-        }
+    let display = absolute.display().to_string();
+    match display.strip_prefix(r"\\?\") {
+        Some(stripped) => stripped.to_string(),
+        None => display,
+    }
+}
+
+/// "999 байт", "1.0 КБ", "1.5 МБ", one decimal from a kilobyte up
+fn human_size(bytes: u64) -> String {
+    const KB: u64 = 1024;
+    const MB: u64 = KB * 1024;
+    match bytes {
+        0..KB => format!("{} байт", bytes),
+        KB..MB => format!("{:.1} КБ", bytes as f64 / KB as f64),
+        _ => format!("{:.1} МБ", bytes as f64 / MB as f64),
     }
+}
 
-    writeln!(stdout)?; // Extra empty line after control options
-    writeln!(stdout)?; // Separator line
+fn has_default_on_header(file_path: &Path) -> bool {
+    let Ok(file) = File::open(file_path) else {
+        return false;
+    };
 
-    // Clear the scroll area
-    for _ in 0..SCROLL_AREA_HEIGHT {
-        writeln!(stdout, "{}", " ".repeat(50))?; // Clear line with spaces
+    let mut first_line = String::new();
+    match BufReader::new(file).read_line(&mut first_line) {
+        Ok(_) => first_line.trim() == DEFAULT_ON_HEADER,
+        Err(_) => false,
     }
+}
 
-    // Move back to start of scroll area
-    queue!(stdout, cursor::MoveTo(0, HEADER_LINES as u16))?;
+/// Names that would collide on a case-insensitive filesystem (Windows, macOS),
+/// each group formatted as "List-A.txt / list-a.txt"
+fn case_collisions(file_entries: &[FileEntry]) -> Vec<String> {
+    let mut groups: BTreeMap<String, Vec<&str>> = BTreeMap::new();
+    for entry in file_entries {
+        groups.entry(entry.name.to_lowercase()).or_default().push(&entry.name);
+    }
 
-    // Get file entries (non-control entries)
-    let file_entries: Vec<_> = entries.iter()
-        .enumerate()
-        .filter(|(_, entry)| !entry.is_control)
-        .collect();
+    groups.into_values()
+        .filter(|names| names.len() > 1)
+        .map(|names| names.join(" / "))
+        .collect()
+}
 
-    let total_files = file_entries.len();
-    let visible_end = scroll_offset.saturating_add(VISIBLE_ITEMS).min(total_files);
+/// Apply a shared selection (`--import`): a list of file names, '#' comments allowed.
+/// Nothing is saved, the result shows up in the TUI for review. Returns the status note.
+fn import_profile(profile: &Path, add: bool, file_entries: &mut [FileEntry]) -> String {
+    let names: Vec<String> = match selection::read(profile) {
+        Ok(selection) => selection.names.into_iter().filter(|name| !name.starts_with('#')).collect(),
+        Err(e) => return format!("Ошибка: {}", e),
+    };
 
-    // Show scroll indicator if needed
-    if scroll_offset > 0 {
-        writeln!(stdout, " ↑ Прокрутите вверх для большего количества файлов")?;
-    } else {
-        writeln!(stdout)?; // Keep spacing consistent
-    }
-
-    // Draw visible file entries
-    let visible_entries = &file_entries[scroll_offset..visible_end];
-    for entry in visible_entries {
-        let real_index = entries.iter().position(|e| e.name == entry.1.name).unwrap();
-        let line = format!(
-            "{} {} {}",
-            if real_index == current_index { ">" } else { " " },
-            if entry.1.selected { "[*]" } else { "[ ]" },
-            entry.1.name
-        );
-
-        if real_index == current_index {
-            writeln!(stdout, "{}", line.reverse())?;
-        } else {
-            writeln!(stdout, "{}", line)?;
-        }
+    for entry in file_entries.iter_mut() {
+        let listed = names.contains(&entry.name);
+        entry.selected = listed || (add && entry.selected);
     }
 
-    // Move to the bottom scroll indicator position
-    queue!(stdout, cursor::MoveTo(0, (HEADER_LINES + VISIBLE_ITEMS + 1) as u16))?;
+    let missing: Vec<&str> = names.iter()
+        .filter(|name| !file_entries.iter().any(|e| &e.name == *name))
+        .map(String::as_str)
+        .collect();
+    let mut note = format!(
+        "Импортирован профиль {} (выбрано: {}), проверьте и сохраните",
+        profile.display(),
+        file_entries.iter().filter(|e| e.selected).count()
+    );
+    if !missing.is_empty() {
+        note.push_str(&format!(", не найдены: {}", missing.join(", ")));
+    }
+    note
+}
 
-    // Show scroll indicator if needed
-    if visible_end < total_files {
-        writeln!(stdout, " ↓ Прокрутите вниз для большего количества файлов")?;
+/// `--select` patterns on top of the loaded selection, returns the status note
+fn select_by_patterns(patterns: &[glob::Pattern], file_entries: &mut [FileEntry]) -> String {
+    let mut matched = 0;
+    for entry in file_entries.iter_mut() {
+        if patterns.iter().any(|pattern| pattern.matches(&entry.name)) {
+            entry.selected = true;
+            matched += 1;
+        }
     }
 
-    stdout.flush()
+    let mut note = format!("Выбрано по шаблонам --select: {}", matched);
+    let unmatched: Vec<&str> = patterns.iter()
+        .filter(|pattern| !file_entries.iter().any(|entry| pattern.matches(&entry.name)))
+        .map(glob::Pattern::as_str)
+        .collect();
+    if !unmatched.is_empty() {
+        note.push_str(&format!(", ничего не нашли: {}", unmatched.join(", ")));
+    }
+    note
 }
 
-fn join_selected_files(lists_dir: &Path, selected_entries: &[&FileEntry]) -> io::Result<()> {
-    let ultimate_path = lists_dir.join("list-ultimate.txt");
-    let mut ultimate_file = File::create(ultimate_path)?;
+fn load_app(args: Args, timings: Rc<Timings>) -> io::Result<App> {
+    let lists_dir = args.dir.as_path();
+    let state_dir = args.state_dir().to_path_buf();
 
-    for entry in selected_entries {
-        let file_path = lists_dir.join(&entry.name);
-        if file_path.exists() {
-            let mut content = String::new();
-            File::open(&file_path)?.read_to_string(&mut content)?;
+    let config_path = selection::path(&args);
+    let has_config = config_path.exists();
+    let selection = selection::read(&config_path)?;
+    let config_name = config_path.file_name().unwrap_or_default().to_string_lossy().into_owned();
+    let selected_files = &selection.names;
 
-            // Write filename as a header
-            // writeln!(ultimate_file, "// === {} ===", entry.name)?;
-            // Write the content
-            writeln!(ultimate_file, "{}", content.trim())?;
-            // Add a separator
-            // writeln!(ultimate_file)?;
+    // Create control entries first
+    let mut entries = if args.no_action_rows {
+        Vec::new()
+    } else {
+        vec![FileEntry::control(ControlRow::Save), FileEntry::control(ControlRow::Cancel)]
+    };
+
+    // Add file entries
+    timings.begin(timings::SCAN);
+    let names = file_ops::scan_lists(lists_dir, &args.prefix, &args.suffix, args.output.as_deref())?;
+    timings.end(timings::SCAN);
+    // With --lazy-counts the phase ends in run_app, once the thread has counted everything
+    timings.begin(timings::COUNT);
+    let mut file_entries: Vec<FileEntry> = names
+        .into_iter()
+        .map(|name| {
+            // Without a saved selection fall back to the template, or else to the defaults
+            // shipped by list authors
+            let selected = if has_config {
+                selected_files.contains(&name)
+            } else if let Some(template) = args.template {
+                template.files.contains(&name.as_str())
+            } else {
+                has_default_on_header(&lists_dir.join(&name))
+            };
+
+            // Lazily the thread started below fills it in
+            let domain_count = if args.lazy_counts { None } else { counts::count(lists_dir, &name) };
+            FileEntry {
+                saved: selected_files.contains(&name),
+                changed_recently: changed_within(&lists_dir.join(&name), args.changed_hours),
+                name,
+                selected,
+                control: None,
+                domain_count,
+            }
+        })
+        .collect();
+    if !args.lazy_counts {
+        timings.end(timings::COUNT);
+    }
+
+    // selected.txt is order-significant: if it isn't alphabetical the user reordered the lists,
+    // so those come first in the saved order followed by the rest alphabetically
+    let saved_order: Vec<&String> = selected_files.iter()
+        .filter(|name| file_entries.iter().any(|e| &e.name == *name))
+        .collect();
+    let custom_order = saved_order.windows(2).any(|pair| pair[0] > pair[1]);
+    if custom_order {
+        file_entries.sort_by_key(|entry| {
+            saved_order.iter()
+                .position(|name| **name == entry.name)
+                .unwrap_or(saved_order.len())
+        });
+    }
+
+    let mut notes = Vec::new();
+    match args.template {
+        Some(template) if has_config => {
+            notes.push(format!("Шаблон «{}» не применён: выбор уже сохранён в {}", template.name, config_name));
+        }
+        Some(template) => {
+            let applied = file_entries.iter().filter(|e| e.selected).count();
+            let mut note = format!("Применён шаблон «{}» (выбрано: {})", template.name, applied);
+            let missing: Vec<&str> = template.files.iter()
+                .filter(|name| !file_entries.iter().any(|e| e.name == **name))
+                .copied()
+                .collect();
+            if !missing.is_empty() {
+                note.push_str(&format!(", нет файлов: {}", missing.join(", ")));
+            }
+            notes.push(note);
+        }
+        None => {}
+    }
+    if !has_config && args.template.is_none() {
+        let defaults_applied = file_entries.iter().filter(|e| e.selected).count();
+        if defaults_applied > 0 {
+            notes.push(format!("Применён выбор по умолчанию (выбрано: {})", defaults_applied));
         }
     }
 
-    Ok(())
-}
+    if let Some(profile) = &args.import {
+        notes.push(import_profile(profile, args.import_add, &mut file_entries));
+    }
 
-fn run_app(stdout: &mut io::Stdout) -> io::Result<()> {
-    let lists_dir = Path::new("lists");
-    if !lists_dir.exists() {
-        fs::create_dir(lists_dir)?;
+    // Better to know before the toggling than at SAVE
+    if !args.review {
+        if let Err(e) = file_ops::check_writable(lists_dir, &config_path) {
+            notes.insert(0, format!(
+                "Внимание: сохранить не получится, {}. Укажите копию папки через --dir или смотрите с --review",
+                e
+            ));
+        }
     }
 
-    let config_path = lists_dir.join("selected.txt");
-    let mut selected_files = Vec::new();
-    if config_path.exists() {
-        let mut content = String::new();
-        File::open(&config_path)?.read_to_string(&mut content)?;
-        selected_files = content.lines().map(String::from).collect();
+    let output_warning = if args.review {
+        None
+    } else {
+        let output = args.output.clone().unwrap_or_else(|| lists_dir.join(OUTPUT_FILE));
+        let output = match args.split_size {
+            Some(_) => merge::chunk_path(&output, 1),
+            None => output,
+        };
+        file_ops::check_output_writable(&output).err().map(|e| e.to_string())
+    };
+
+    if !args.select.is_empty() {
+        notes.push(select_by_patterns(&args.select, &mut file_entries));
     }
 
-    // Create control entries first
-    let mut entries = vec![
-        FileEntry {
-            name: String::from("SAVE LIST"),
-            selected: false,
-            is_control: true,
-        },
-        FileEntry {
-            name: String::from("CANCEL"),
-            selected: false,
-            is_control: true,
-        },
-    ];
+    if selection.dropped > 0 {
+        notes.push(format!(
+            "{}: пропущено пустых и повторяющихся строк: {}, файл будет перезаписан при сохранении",
+            config_name, selection.dropped
+        ));
+    }
 
-    // Add file entries
-    let mut file_entries: Vec<FileEntry> = fs::read_dir(lists_dir)?
-        .filter_map(|entry| {
-            let entry = entry.ok()?;
-            let name = entry.file_name().into_string().ok()?;
-            if name.starts_with("list-") &&
-                name.ends_with(".txt") &&
-                name != "list-ultimate.txt" {
-                Some(FileEntry {
-                    name: name.clone(),
-                    selected: selected_files.contains(&name),
-                    is_control: false,
-                })
-            } else {
-                None
+    let unknown: Vec<&str> = selected_files.iter()
+        .filter(|name| !file_entries.iter().any(|e| &e.name == *name))
+        .map(String::as_str)
+        .collect();
+    if !unknown.is_empty() {
+        notes.push(format!("{}: не найдены: {}", config_name, unknown.join(", ")));
+    }
+
+    let collisions = case_collisions(&file_entries);
+    if !collisions.is_empty() {
+        notes.push(format!("Внимание: имена отличаются только регистром: {}", collisions.join("; ")));
+    }
+
+    // Comparing is about the two files only, the loaded selection and its notes don't count
+    let compare = match &args.diff {
+        Some((a, b)) => {
+            let comparison = load_comparison(lists_dir, a, b)?;
+            for entry in &mut file_entries {
+                entry.selected = false;
+                entry.saved = false;
             }
-        })
+            notes = vec![comparison_note(&comparison, &file_entries)];
+            Some(comparison)
+        }
+        None => None,
+    };
+
+    // After the comparison, a mistake in [keys] is worth seeing in any mode
+    let (keymap, key_warnings) = keys::Keymap::load(&state_dir);
+    notes.extend(key_warnings);
+
+    let status = if notes.is_empty() { None } else { Some(notes.join(" · ")) };
+
+    let recent: Vec<String> = state::get(&state_dir, state::RECENT)
+        .unwrap_or_default()
+        .split('/')
+        .filter(|name| file_entries.iter().any(|e| e.name == *name))
+        .take(app::RECENT_MAX)
+        .map(String::from)
         .collect();
 
-    file_entries.sort_by(|a, b| a.name.cmp(&b.name));
     entries.extend(file_entries);
 
-    let mut current_index = 0;
-    let mut scroll_offset = 0;
-    let num_control_entries = entries.iter().filter(|e| e.is_control).count();
+    // The current selection counts too, it may have been saved before this was recorded
+    let mut ever_saved: HashSet<String> = state::get(&state_dir, state::EVER_SAVED)
+        .unwrap_or_default()
+        .split('/')
+        .filter(|name| !name.is_empty())
+        .map(String::from)
+        .collect();
+    ever_saved.extend(selected_files.iter().cloned());
+
+    // Counting started after the audit data, a list known from there was saved at least once
+    let mut save_counts: HashMap<String, usize> = state::get(&state_dir, state::SAVE_COUNTS)
+        .unwrap_or_default()
+        .split('/')
+        .filter_map(|item| item.rsplit_once(':'))
+        .filter_map(|(name, count)| Some((name.to_string(), count.parse().ok()?)))
+        .collect();
+    for name in &ever_saved {
+        save_counts.entry(name.clone()).or_insert(1);
+    }
+
+    let case_sensitive = args.case_sensitive;
+    let favorites = favorites::read(&state_dir);
+
+    // Selected lists first, the footer total waits for them
+    let (pending_counts, count_receiver) = if args.lazy_counts {
+        let (selected, rest): (Vec<&FileEntry>, Vec<&FileEntry>) = entries.iter()
+            .filter(|e| !e.is_control())
+            .partition(|e| e.selected);
+        let order: Vec<String> = selected.into_iter().chain(rest).map(|e| e.name.clone()).collect();
+        (order.iter().cloned().collect(), Some(counts::spawn(lists_dir, order)))
+    } else {
+        (HashSet::new(), None)
+    };
+    let mut app = App {
+        lists_dir: lists_dir.to_path_buf(),
+        lists_dir_display: display_path(lists_dir),
+        args,
+        config_path,
+        entries,
+        current_index: 0,
+        scroll_offset: 0,
+        status,
+        mode: Mode::List,
+        sort_by_selection: false,
+        custom_order,
+        filter: String::new(),
+        case_sensitive,
+        content: search::ContentIndex::new(),
+        match_counts: HashMap::new(),
+        busy: false,
+        name_offset: 0,
+        recent,
+        recent_cursor: None,
+        ever_saved,
+        save_counts,
+        show_save_counts: false,
+        preview_wrap: false,
+        audit: false,
+        favorites,
+        favorites_only: false,
+        selected_only: false,
+        marked: HashSet::new(),
+        compare,
+        output_warning,
+        pending_counts,
+        count_receiver,
+        selected_domains: BTreeMap::new(),
+        count_display: CountDisplay::Hidden,
+        timings,
+        keymap,
+        wizard: false,
+    };
+    app.recount_selected();
+
+    // Both answers would change something, and review shows the saved selection anyway
+    if app.args.review {
+        return Ok(app);
+    }
+    if let Some(names) = draft::newer_than(app.args.state_dir(), &app.config_path) {
+        app.mode = Mode::RestoreDraft { names };
+    } else if app.compare.is_none() {
+        match wizard::start(app.args.state_dir(), app.args.wizard, has_config) {
+            Some(wizard::Step::Selection) => app.wizard = true,
+            Some(step) => app.mode = Mode::Wizard { step, cursor: 0 },
+            None => {}
+        }
+    }
+
+    Ok(app)
+}
+
+/// The two selections of --diff. A path that doesn't exist as given is looked up in the lists folder,
+/// so `--diff selected.txt old.txt` works from anywhere.
+fn load_comparison(lists_dir: &Path, a: &Path, b: &Path) -> io::Result<app::Comparison> {
+    let read = |path: &Path| -> io::Result<(String, HashSet<String>)> {
+        let path = if path.exists() { path.to_path_buf() } else { lists_dir.join(path) };
+        if !path.exists() {
+            return Err(io::Error::new(io::ErrorKind::NotFound, format!("{} не найден", path.display())));
+        }
+        let name = path.file_name().unwrap_or_default().to_string_lossy().into_owned();
+        Ok((name, selection::read(&path)?.names.into_iter().collect()))
+    };
+    let (a_name, a) = read(a)?;
+    let (b_name, b) = read(b)?;
+    Ok(app::Comparison { names: (a_name, b_name), a, b })
+}
 
-    draw_screen(stdout, &entries, current_index, scroll_offset, true)?;
+/// "A = sel-a.txt, B = sel-b.txt · только A: 2, только B: 1, в обоих: 5", plus the names
+/// of either selection that aren't in the folder
+fn comparison_note(comparison: &app::Comparison, entries: &[FileEntry]) -> String {
+    let (a, b) = (&comparison.a, &comparison.b);
+    let mut note = format!(
+        "A = {}, B = {} · только A: {}, только B: {}, в обоих: {}",
+        comparison.names.0,
+        comparison.names.1,
+        a.difference(b).count(),
+        b.difference(a).count(),
+        a.intersection(b).count()
+    );
+    let mut missing: Vec<&str> = a.union(b)
+        .filter(|name| !entries.iter().any(|e| &e.name == *name))
+        .map(String::as_str)
+        .collect();
+    if !missing.is_empty() {
+        missing.sort();
+        note.push_str(&format!(" · нет в папке: {}", missing.join(", ")));
+    }
+    note
+}
+
+fn run_app(stdout: &mut io::Stdout, args: Args, timings: Rc<Timings>) -> io::Result<()> {
+    let mut app = load_app(args, timings)?;
+
+    ui::draw(stdout, &app, true)?;
+
+    // Keys typed while a file operation was running, replayed after it
+    let mut typeahead = VecDeque::new();
+    // Input since the last draft, written once the user has been idle for draft_interval
+    let mut last_input = Instant::now();
+    let mut draft_due = false;
 
     'main: loop {
-        if let Ok(true) = event::poll(Duration::from_millis(16)) {
-            if let Ok(Event::Key(key)) = event::read() {
-                let mut redraw = true;
-
-                match key {
-                    KeyEvent {
-                        code: KeyCode::Up,
-                        kind: event::KeyEventKind::Press,
-                        ..
-                    } => {
-                        if current_index > 0 {
-                            current_index -= 1;
-                            if current_index >= num_control_entries {
-                                let file_index = current_index - num_control_entries;
-                                if scroll_offset > file_index {
-                                    scroll_offset = file_index;
-                                }
-                            }
-                        }
+        // Content search results come in from the loader thread
+        if app.content.poll() {
+            app.refresh_filter();
+            if matches!(app.mode, Mode::List | Mode::Search) {
+                ui::draw(stdout, &app, false)?;
+            }
+        }
+
+        if app.poll_counts() {
+            if app.pending_counts.is_empty() {
+                app.timings.end(timings::COUNT);
+            }
+            if matches!(app.mode, Mode::List | Mode::Search) {
+                ui::draw(stdout, &app, false)?;
+            }
+        }
+
+        let draft_interval = Duration::from_secs(app.args.draft_interval);
+        if draft_due && !draft_interval.is_zero() && !app.args.review && last_input.elapsed() >= draft_interval {
+            draft_due = false;
+            if let Err(e) = save_draft(&app) {
+                app.status = Some(format!("Не удалось сохранить черновик: {}", e));
+                ui::draw(stdout, &app, false)?;
+            }
+        }
+
+        let key = match typeahead.pop_front() {
+            Some(key) => key,
+            None => {
+                // Input wakes the poll right away, the timeout only paces the background checks.
+                // Content search results are picked up faster so the list fills in smoothly.
+                let loading = app.content.is_loading() || !app.pending_counts.is_empty();
+                let poll_ms = if loading { FAST_POLL_MS } else { app.args.poll_ms };
+                if !matches!(event::poll(Duration::from_millis(poll_ms)), Ok(true)) {
+                    continue;
+                }
+                match event::read() {
+                    Ok(Event::Key(key)) if key.kind == event::KeyEventKind::Press => key,
+                    Ok(Event::Resize(..)) => {
+                        // The layout depends on the width, see ui::NARROW_WIDTH
+                        ui::draw(stdout, &app, true)?;
+                        continue;
                     }
-                    KeyEvent {
-                        code: KeyCode::Down,
-                        kind: event::KeyEventKind::Press,
-                        ..
-                    } => {
-                        if current_index < entries.len() - 1 {
-                            current_index += 1;
-                            if current_index >= num_control_entries {
-                                let file_index = current_index - num_control_entries;
-                                if file_index >= scroll_offset + VISIBLE_ITEMS {
-                                    scroll_offset = file_index - VISIBLE_ITEMS + 1;
-                                }
-                            }
-                        }
+                    _ => continue,
+                }
+            }
+        };
+
+        last_input = Instant::now();
+        draft_due = true;
+
+        if let KeyEvent {
+            code: KeyCode::Char('c'),
+            modifiers: KeyModifiers::CONTROL,
+            ..
+        } = key {
+            break 'main Ok(());
+        }
+
+        // Any mode switch changes the whole layout, so it needs a full redraw
+        let mode_before = std::mem::discriminant(&app.mode);
+        let frame = ui::Frame::of(&app);
+        let redraw = match app.mode {
+            Mode::List => {
+                let key = app.keymap.translate(key);
+                match handle_list_key(&mut app, key)? {
+                    Some(exit) => break 'main exit,
+                    None => true,
+                }
+            }
+            Mode::Submenu { .. } => handle_submenu_key(&mut app, key)?,
+            Mode::Preview { .. } => handle_preview_key(&mut app, key)?,
+            Mode::Report { .. } => handle_text_view_key(&mut app, key),
+            Mode::ConfirmSave { .. } => {
+                match handle_confirm_save_key(stdout, &mut app, key)? {
+                    Some(exit) => break 'main exit,
+                    None => true,
+                }
+            }
+            Mode::Rename { .. } => handle_rename_key(&mut app, key)?,
+            Mode::WriteAs { .. } => handle_write_as_key(&mut app, key)?,
+            Mode::Search => handle_search_key(&mut app, key),
+            Mode::ConfirmDelete { .. } => handle_confirm_delete_key(&mut app, key)?,
+            Mode::ConfirmReset { .. } => handle_confirm_reset_key(&mut app, key),
+            Mode::ConfirmDedupAll { .. } => handle_confirm_dedup_all_key(&mut app, key)?,
+            Mode::ConfirmDeleteMarked { .. } => handle_confirm_delete_marked_key(&mut app, key)?,
+            Mode::ConfirmNormalize { .. } => handle_confirm_normalize_key(&mut app, key)?,
+            Mode::RestoreDraft { .. } => handle_restore_draft_key(&mut app, key),
+            Mode::Wizard { .. } => handle_wizard_key(&mut app, key),
+        };
+
+        // Whatever was typed during a file operation is stale: toggles or a "y" could hit
+        // the wrong file, so only navigation survives
+        if app.busy {
+            app.busy = false;
+            while let Ok(true) = event::poll(Duration::ZERO) {
+                if let Ok(Event::Key(key)) = event::read() {
+                    if key.kind == event::KeyEventKind::Press && is_navigation(&key) {
+                        typeahead.push_back(key);
                     }
-                    KeyEvent {
-                        code: KeyCode::Char(' ') | KeyCode::Enter,
-                        kind: event::KeyEventKind::Press,
-                        ..
-                    } => {
-                        match entries[current_index].name.as_str() {
-                            "SAVE LIST" => {
-                                // Save selected files to config
-                                let mut file = File::create(&config_path)?;
-                                let selected_entries: Vec<_> = entries.iter()
-                                    .filter(|e| e.selected && !e.is_control)
-                                    .collect();
-
-                                for entry in &selected_entries {
-                                    writeln!(file, "{}", entry.name)?;
-                                }
-
-                                // Join selected files into list-ultimate.txt
-                                if let Err(e) = join_selected_files(lists_dir, &selected_entries) {
-                                    execute!(
-                                        stdout,
-                                        cursor::MoveToNextLine(1),
-                                        terminal::Clear(ClearType::FromCursorDown)
-                                    )?;
-                                    println!("{}", format!("Ошибка при объединении файлов: {}. Выход через 5 секунд...", e).red());
-                                } else {
-                                    execute!(
-                                        stdout,
-                                        cursor::MoveToNextLine(1),
-                                        terminal::Clear(ClearType::FromCursorDown)
-                                    )?;
-                                    println!("{}", "Успешно! Список сохранен и файлы объединены. Выход через 5 секунд...".green());
-                                }
-
-                                stdout.flush()?;
-                                thread::sleep(Duration::from_secs(5));
-                                break 'main Ok(());
-                            }
-                            "CANCEL" => break 'main Ok(()),
-                            _ => {
-                                entries[current_index].selected = !entries[current_index].selected;
-                            }
-                        }
+                }
+            }
+        }
+
+        let mode_changed = mode_before != std::mem::discriminant(&app.mode);
+        if mode_changed {
+            ui::draw(stdout, &app, true)?;
+        } else if redraw {
+            ui::redraw(stdout, &app, &frame)?;
+        }
+    }
+}
+
+/// Write the selection to the draft, or drop the draft when nothing differs from selected.txt
+fn save_draft(app: &App) -> io::Result<()> {
+    let names = app.selected_names();
+    let saved = selection::read(&app.config_path)?;
+    if names == saved.names {
+        draft::remove(app.args.state_dir());
+        Ok(())
+    } else {
+        draft::write(app.args.state_dir(), &names)
+    }
+}
+
+/// Keys that only move around and never change files or the selection. Ctrl+C is kept too:
+/// raw mode turns it into a key, so it can't cut an operation short and quits once it's done.
+fn is_navigation(key: &KeyEvent) -> bool {
+    let ctrl_c = key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL);
+    let movement = !key.modifiers.contains(KeyModifiers::SHIFT) && matches!(
+        key.code,
+        KeyCode::Up | KeyCode::Down | KeyCode::Left | KeyCode::PageUp | KeyCode::PageDown | KeyCode::Esc
+    );
+    ctrl_c || movement
+}
+
+/// Show "занято..." while a file operation runs; run_app clears the flag once the handler returns
+fn begin_busy(app: &mut App) -> io::Result<()> {
+    app.busy = true;
+    app.status = Some(String::from("занято..."));
+    ui::draw(&mut io::stdout(), app, true)
+}
+
+/// Returns Some when the app should exit, None to keep going
+fn handle_list_key(app: &mut App, key: KeyEvent) -> io::Result<Option<io::Result<()>>> {
+    match key.code {
+        KeyCode::Up | KeyCode::Down if key.modifiers.contains(KeyModifiers::SHIFT) && refuse_in_review(app) => {}
+        KeyCode::Up | KeyCode::Down if key.modifiers.contains(KeyModifiers::SHIFT) => {
+            let moved = app.move_current(key.code == KeyCode::Up);
+            if moved {
+                // The default output is sorted, the order only shows where the first list matters
+                let args = &app.args;
+                app.status = Some(String::from(if args.keep_comments || args.separators || args.preserve_case {
+                    "Порядок изменён, он определяет приоритет при объединении"
+                } else {
+                    "Порядок изменён. Результат отсортирован, порядок влияет только на статистику по спискам"
+                }));
+            }
+        }
+        KeyCode::Left | KeyCode::Right if key.modifiers.contains(KeyModifiers::SHIFT) => {
+            app.scroll_names(key.code == KeyCode::Right);
+        }
+        // Plain letters are commands, type-ahead goes with Alt
+        KeyCode::Char(c) if key.modifiers.contains(KeyModifiers::ALT) && c.is_alphanumeric() => {
+            let jumped = app.jump_to_letter(c);
+            if !jumped {
+                app.status = Some(format!("Нет списков на «{}»", c));
+                bell(app);
+            }
+        }
+        KeyCode::Up if !app.move_up() => bell(app),
+        KeyCode::Down if !app.move_down() => bell(app),
+        KeyCode::Up | KeyCode::Down => {}
+        KeyCode::Right => open_submenu(app),
+        KeyCode::Enter if app.args.enter_opens_menu && app.current_file().is_some() => open_submenu(app),
+        KeyCode::Char('s') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+            if app.args.review {
+                return Ok(Some(Ok(())));
+            }
+            show_save_summary(app);
+        }
+        KeyCode::Char('/') => app.mode = Mode::Search,
+        KeyCode::Esc if !app.filter.is_empty() => {
+            app.filter.clear();
+            app.apply_filter();
+        }
+        KeyCode::Char('?') | KeyCode::F(1) => {
+            app.mode = Mode::Report {
+                title: String::from("Справка"),
+                lines: ui::help_lines(),
+                scroll: 0,
+            };
+        }
+        // Ctrl+M reaches us as Enter in most terminals, so the plain letter it is
+        KeyCode::Char('m') | KeyCode::Char('M') if refuse_in_review(app) => {}
+        KeyCode::Char('m') | KeyCode::Char('M') => {
+            begin_busy(app)?;
+            app.status = Some(merge_now(app));
+        }
+        KeyCode::Char('w') | KeyCode::Char('W') if refuse_in_review(app) => {}
+        KeyCode::Char('w') | KeyCode::Char('W') => app.mode = Mode::WriteAs { input: String::new() },
+        KeyCode::Char('v') | KeyCode::Char('V') => show_overlap_report(app),
+        KeyCode::Char('s') | KeyCode::Char('S') => show_saved_file(app),
+        KeyCode::Char('x') | KeyCode::Char('X') | KeyCode::Delete if refuse_in_review(app) => {}
+        KeyCode::Char('x') | KeyCode::Char('X') => {
+            let name = app.current_file().map(|e| e.name.clone());
+            app.status = Some(match (app.toggle_marked(), name) {
+                (Some(true), Some(name)) => format!(
+                    "{} отмечен для удаления, отмечено: {} (Delete — удалить отмеченные)",
+                    name, app.marked.len()
+                ),
+                (Some(false), Some(name)) => format!("{}: отметка снята, отмечено: {}", name, app.marked.len()),
+                _ => String::from("Под курсором нет списка"),
+            });
+        }
+        KeyCode::Delete => show_delete_marked_plan(app),
+        KeyCode::Char('d') | KeyCode::Char('D') if refuse_in_review(app) => {}
+        KeyCode::Char('d') | KeyCode::Char('D') => show_dedup_all_plan(app),
+        KeyCode::Char('n') | KeyCode::Char('N') if refuse_in_review(app) => {}
+        KeyCode::Char('n') | KeyCode::Char('N') => show_normalize_plan(app),
+        KeyCode::Char('a') | KeyCode::Char('A') => {
+            let shown = app.toggle_audit();
+            app.status = Some(if app.audit {
+                format!("Аудит: списков, которых не было ни в одном сохранённом выборе: {}", shown)
+            } else {
+                String::from("Аудит выключен, показаны все списки")
+            });
+        }
+        KeyCode::Char('*') if refuse_in_review(app) => {}
+        KeyCode::Char('*') => {
+            let name = app.current_file().map(|e| e.name.clone());
+            app.status = Some(match (app.toggle_favorite(), name) {
+                (Some(Ok(true)), Some(name)) => format!("★ {} в избранном", name),
+                (Some(Ok(false)), Some(name)) => format!("{} убран из избранного", name),
+                (Some(Err(e)), _) => format!("Не удалось сохранить избранное: {}", e),
+                _ => String::from("Под курсором нет списка"),
+            });
+        }
+        KeyCode::Char('h') | KeyCode::Char('H') => {
+            let shown = app.toggle_selected_only();
+            app.status = Some(if app.selected_only {
+                format!("Только выбранные: списков: {} (H — все списки)", shown)
+            } else {
+                String::from("Показаны все списки")
+            });
+        }
+        KeyCode::Char('f') | KeyCode::Char('F') => {
+            let shown = app.toggle_favorites_only();
+            app.status = Some(if app.favorites_only {
+                format!("Избранное: списков: {} (* — добавить или убрать)", shown)
+            } else {
+                String::from("Показаны все списки")
+            });
+        }
+        KeyCode::Char('%') => {
+            app.count_display = app.count_display.next();
+            app.status = Some(format!("Число доменов: {}", app.count_display.label()));
+        }
+        KeyCode::Char('c') | KeyCode::Char('C') => {
+            app.show_save_counts = !app.show_save_counts;
+            app.status = Some(String::from(if app.show_save_counts {
+                "×N — в скольких сохранённых выборах был список"
+            } else {
+                "Число сохранений скрыто"
+            }));
+        }
+        KeyCode::Char('y') | KeyCode::Char('Y') => {
+            // y copies the name, Y the full path
+            if let Some(entry) = app.current_file() {
+                let text = if key.code == KeyCode::Char('Y') {
+                    display_path(&app.lists_dir.join(&entry.name))
+                } else {
+                    entry.name.clone()
+                };
+                app.status = Some(match clipboard::copy(&text) {
+                    Ok(()) => format!("Скопировано: {}", text),
+                    Err(e) => format!("Не удалось скопировать: {}", e),
+                });
+            }
+        }
+        KeyCode::Char('z') | KeyCode::Char('Z') if refuse_in_review(app) => {}
+        KeyCode::Char('z') | KeyCode::Char('Z') => {
+            // Full revert to what's on disk, discarding every toggle since the last save
+            match selection::read(&app.config_path) {
+                Ok(saved) => {
+                    let changes = app.entries.iter()
+                        .filter(|e| !e.is_control() && e.selected != saved.names.contains(&e.name))
+                        .count();
+                    if changes > app.args.confirm_over {
+                        app.mode = Mode::ConfirmReset { names: saved.names, changes, inverse: false };
+                    } else {
+                        reset_selection(app, &saved.names);
                     }
-                    KeyEvent {
-                        code: KeyCode::Char('c'),
-                        modifiers: KeyModifiers::CONTROL,
-                        kind: event::KeyEventKind::Press,
-                        ..
-                    } => {
-                        break 'main Ok(());
+                }
+                Err(e) => app.status = Some(format!("Ошибка: {}", e)),
+            }
+        }
+        KeyCode::Char('i') | KeyCode::Char('I') if refuse_in_review(app) => {}
+        KeyCode::Char('i') | KeyCode::Char('I') => {
+            // The complement of what's on disk, not of the live selection
+            match selection::read(&app.config_path) {
+                Ok(saved) => {
+                    let names: Vec<String> = app.entries.iter()
+                        .filter(|e| !e.is_control() && !saved.names.contains(&e.name))
+                        .map(|e| e.name.clone())
+                        .collect();
+                    let changes = app.entries.iter()
+                        .filter(|e| !e.is_control() && e.selected != names.contains(&e.name))
+                        .count();
+                    if changes > app.args.confirm_over {
+                        app.mode = Mode::ConfirmReset { names, changes, inverse: true };
+                    } else {
+                        select_inverse_of_saved(app, &names);
                     }
-                    _ => {
-                        redraw = false;
+                }
+                Err(e) => app.status = Some(format!("Ошибка: {}", e)),
+            }
+        }
+        KeyCode::Char('o') | KeyCode::Char('O') => {
+            // Switching the sort drops a manual order, otherwise the sort would have no effect
+            if app.custom_order {
+                app.custom_order = false;
+            } else {
+                app.sort_by_selection = !app.sort_by_selection;
+            }
+            app.resort();
+            app.status = Some(String::from(if app.sort_by_selection {
+                "Сортировка: выбранные списки сверху"
+            } else {
+                "Сортировка: по имени"
+            }));
+        }
+        KeyCode::Char('q') | KeyCode::Char('Q') | KeyCode::Esc if app.args.no_action_rows => return Ok(Some(cancel(app))),
+        KeyCode::Left if app.wizard => {
+            app.wizard = false;
+            wizard::reached(app.args.state_dir(), wizard::Step::Template);
+            app.mode = Mode::Wizard { step: wizard::Step::Template, cursor: 0 };
+        }
+        KeyCode::Esc if app.wizard => {
+            app.wizard = false;
+            wizard::finish(app.args.state_dir());
+            app.status = Some(String::from("Мастер закрыт, --wizard откроет его снова"));
+        }
+        KeyCode::Char(' ') | KeyCode::Enter => {
+            match app.current_control() {
+                // Space is the toggle key, on a button it only says how to press it
+                Some(control) if key.code == KeyCode::Char(' ') => {
+                    let action = match control {
+                        ControlRow::Save if app.args.review => "выйти",
+                        ControlRow::Save => "сохранить выбор",
+                        ControlRow::Cancel => "выйти без сохранения",
+                    };
+                    app.status = Some(format!("{} — кнопка, а не список. ENTER — {}", control.label(), action));
+                    bell(app);
+                }
+                None if app.current_file().is_none() => {
+                    app.status = Some(String::from("Под курсором нет списка"));
+                    bell(app);
+                }
+                // Nothing can have changed in review, SAVE only leaves
+                Some(ControlRow::Save) if app.args.review => return Ok(Some(Ok(()))),
+                Some(ControlRow::Save) => show_save_summary(app),
+                Some(ControlRow::Cancel) => return Ok(Some(cancel(app))),
+                _ if refuse_in_review(app) => {}
+                _ => {
+                    app.toggle_current();
+                    if let Some(entry) = app.current_file().filter(|e| e.selected && e.is_empty_list()) {
+                        app.status = Some(format!("Внимание: {} пуст и ничего не добавит в объединение", entry.name));
                     }
                 }
+            }
+        }
+        _ => bell(app),
+    }
+
+    Ok(None)
+}
+
+/// With --bell: BEL for a key that did nothing, the terminal beeps or flashes as it is set up
+/// to. Goes straight to the terminal, so the screen isn't redrawn for it.
+fn bell(app: &App) {
+    if app.args.bell {
+        let mut stdout = io::stdout();
+        let _ = stdout.write_all(b"\x07").and_then(|()| stdout.flush());
+    }
+}
+
+/// With --review: show READ_ONLY and return true, the caller then skips the change
+fn refuse_in_review(app: &mut App) -> bool {
+    if app.args.review {
+        app.status = Some(String::from(READ_ONLY));
+        bell(app);
+    }
+    app.args.review
+}
+
+/// Quit without saving. That's a deliberate discard, unlike a closed window, so the draft goes too.
+fn cancel(app: &App) -> io::Result<()> {
+    // A review session didn't make the draft and doesn't get to throw it away
+    if !app.args.review {
+        draft::remove(app.args.state_dir());
+    }
+    Ok(())
+}
+
+/// Pre-save summary: what will be written, with empty selected lists flagged
+fn show_save_summary(app: &mut App) {
+    // The numbers below are per list, the ones --lazy-counts hasn't reached are counted now
+    for index in 0..app.entries.len() {
+        if app.entries[index].selected {
+            app.ensure_count(index);
+        }
+    }
+
+    let selected: Vec<&FileEntry> = app.entries.iter()
+        .filter(|e| e.selected && !e.is_control())
+        .collect();
+
+    let mut lines = vec![format!("Будет сохранено списков: {}", selected.len()), String::new()];
+    for entry in &selected {
+        lines.push(match entry.domain_count {
+            Some(0) => format!("  ⚠ {} — ПУСТОЙ, ничего не добавит", entry.name),
+            Some(count) => match fs::metadata(remote::content_path(&app.lists_dir, &entry.name)) {
+                Ok(meta) => format!("  {} — доменов: {}, {}", entry.name, count, human_size(meta.len())),
+                Err(_) => format!("  {} — доменов: {}", entry.name, count),
+            },
+            None => format!("  ⚠ {} — не удалось прочитать", entry.name),
+        });
+    }
+
+    // The real merge in memory, so the numbers are the ones SAVE will write
+    let names: Vec<&str> = selected.iter().map(|e| e.name.as_str()).collect();
+    let preview = merge::MergeOptions::from_args(&app.args, &app.lists_dir)
+        .and_then(|options| merge::preview(&app.lists_dir, &names, &options));
+    lines.push(String::new());
+    let mut changes = None;
+    match preview {
+        Ok((stats, output_changes)) => {
+            lines.push(format!("Итог: доменов: {}, размер {}: {}", stats.written, OUTPUT_FILE, human_size(stats.size as u64)));
+            if stats.denied > 0 {
+                lines.push(format!("Убрано по {}: {}", merge::DENYLIST_FILE, stats.denied));
+            }
+            if stats.shuffle_seed.is_some() {
+                lines.push(String::from("Порядок доменов будет перемешан (--shuffle)"));
+            }
+            let redundant = stats.redundant();
+            if !redundant.is_empty() {
+                lines.push(format!(
+                    "⚠ Не добавляют новых доменов, все уже есть в списках выше, можно снять выбор: {}",
+                    redundant.join(", ")
+                ));
+            }
+            changes = output_changes;
+        }
+        Err(e) => lines.push(format!("⚠ Не удалось посчитать итог: {}", e)),
+    }
+
+    let empty = selected.iter().filter(|e| e.is_empty_list()).count();
+    if empty > 0 {
+        lines.push(String::new());
+        lines.push(format!("⚠ Пустых списков среди выбранных: {}", empty));
+    }
+
+    if let Err(e) = file_ops::check_writable(&app.lists_dir, &app.config_path) {
+        lines.push(String::new());
+        lines.push(format!("⚠ Сохранение завершится ошибкой: {}", e));
+    }
+
+    // Advisory only, merging identical lists is harmless but pointless
+    let identical = file_ops::identical_files(&app.lists_dir, &names);
+    if !identical.is_empty() {
+        lines.push(String::new());
+        for group in identical {
+            lines.push(format!("⚠ Совпадают побайтно, достаточно выбрать один: {}", group.join(", ")));
+        }
+    }
+
+    // Last, it's the long part
+    if let Some(changes) = changes {
+        lines.push(String::new());
+        lines.push(format!(
+            "Изменения в {}: +{} / -{}{}",
+            OUTPUT_FILE, changes.added.len(), changes.removed.len(),
+            if app.args.transform_cmd.is_some() { " (без --transform-cmd)" } else { "" }
+        ));
+        for (domains, prefix) in [(&changes.added, ui::ADDED_PREFIX), (&changes.removed, ui::REMOVED_PREFIX)] {
+            lines.extend(domains.iter().take(CHANGES_SHOWN).map(|domain| format!("{}{}", prefix, domain)));
+            if domains.len() > CHANGES_SHOWN {
+                lines.push(format!("{}... и ещё {}", prefix, domains.len() - CHANGES_SHOWN));
+            }
+        }
+    }
+
+    app.mode = Mode::ConfirmSave { lines, scroll: 0 };
+}
+
+
+/// What D would do: the selected local lists with their repeated entries, nothing is written yet.
+/// Remote lists are skipped, their cached copy is replaced on the next download anyway.
+fn show_dedup_all_plan(app: &mut App) {
+    let names: Vec<String> = app.entries.iter()
+        .filter(|e| e.selected && !e.is_control() && !remote::is_remote(&e.name))
+        .map(|e| e.name.clone())
+        .collect();
+    if names.is_empty() {
+        app.status = Some(String::from("Нет выбранных списков"));
+        return;
+    }
 
-                if redraw {
-                    draw_screen(stdout, &entries, current_index, scroll_offset, false)?;
+    let mut files = Vec::new();
+    let mut lines = Vec::new();
+    let mut total = 0;
+    for name in names {
+        match file_ops::find_duplicates(&app.lists_dir.join(&name)) {
+            Ok(repeats) if repeats.is_empty() => {}
+            Ok(repeats) => {
+                const SHOWN: usize = 3;
+                let mut distinct: Vec<&str> = Vec::new();
+                for entry in &repeats {
+                    if !distinct.contains(&entry.as_str()) {
+                        distinct.push(entry);
+                    }
+                }
+                let mut examples = distinct[..distinct.len().min(SHOWN)].join(", ");
+                if distinct.len() > SHOWN {
+                    examples.push_str(", ...");
                 }
+                lines.push(format!("  {} — повторов: {} ({})", name, repeats.len(), examples));
+                total += repeats.len();
+                files.push(name);
             }
+            Err(e) => lines.push(format!("  ⚠ {} — {}", name, e)),
         }
     }
-}
\ No newline at end of file
+
+    if files.is_empty() && lines.is_empty() {
+        app.status = Some(String::from("В выбранных списках дубликатов нет"));
+        return;
+    }
+    lines.insert(0, format!("Будут удалены повторы в файлах: {}, строк: {}", files.len(), total));
+    lines.insert(1, String::new());
+    lines.push(String::new());
+    lines.push(String::from("Первое вхождение каждого домена, комментарии и пустые строки остаются"));
+    app.mode = Mode::ConfirmDedupAll { files, lines, scroll: 0 };
+}
+
+fn handle_confirm_dedup_all_key(app: &mut App, key: KeyEvent) -> io::Result<bool> {
+    match key.code {
+        KeyCode::Enter | KeyCode::Char('y') | KeyCode::Char('Y') => {
+            let Mode::ConfirmDedupAll { files, .. } = std::mem::replace(&mut app.mode, Mode::List) else {
+                return Ok(false);
+            };
+            if files.is_empty() {
+                return Ok(true);
+            }
+            begin_busy(app)?;
+
+            // A failed file doesn't stop the rest, all failures are listed afterwards
+            let mut removed = 0;
+            let mut failed = Vec::new();
+            for name in &files {
+                match file_ops::dedup_file(&app.lists_dir.join(name)) {
+                    Ok(count) => removed += count,
+                    Err(e) => failed.push(format!("  {}", e)),
+                }
+                app.recount(name);
+            }
+
+            let done = format!("Удалено дубликатов: {} в файлах: {}", removed, files.len() - failed.len());
+            if failed.is_empty() {
+                app.status = Some(done);
+            } else {
+                let mut lines = vec![done, String::new(), format!("Не удалось изменить файлов: {}", failed.len())];
+                lines.extend(failed);
+                app.mode = Mode::Report { title: String::from("Удаление дубликатов"), lines, scroll: 0 };
+            }
+        }
+        KeyCode::Esc | KeyCode::Left | KeyCode::Char('n') | KeyCode::Char('N') => app.mode = Mode::List,
+        _ => return Ok(handle_text_view_key(app, key)),
+    }
+
+    Ok(true)
+}
+
+/// The files X marked, deleted with Enter
+fn show_delete_marked_plan(app: &mut App) {
+    // In the order of the list, not the order they were marked in
+    let files: Vec<String> = app.entries.iter()
+        .filter(|e| !e.is_control() && app.marked.contains(&e.name))
+        .map(|e| e.name.clone())
+        .collect();
+    if files.is_empty() {
+        app.status = Some(String::from("Нет отмеченных списков, X — отметить список для удаления"));
+        return;
+    }
+
+    let mut lines = vec![format!("Будут удалены файлы: {}", files.len()), String::new()];
+    for name in &files {
+        let selected = app.file_position(name).is_some_and(|index| app.entries[index].selected);
+        lines.push(format!("  {}{}", name, if selected { " (выбран)" } else { "" }));
+    }
+    lines.push(String::new());
+    lines.push(format!("Из {} они тоже будут убраны. ESC снимает все отметки.", app.config_path.display()));
+    app.mode = Mode::ConfirmDeleteMarked { files, lines, scroll: 0 };
+}
+
+fn handle_confirm_delete_marked_key(app: &mut App, key: KeyEvent) -> io::Result<bool> {
+    match key.code {
+        KeyCode::Enter | KeyCode::Char('y') | KeyCode::Char('Y') => {
+            let Mode::ConfirmDeleteMarked { files, .. } = std::mem::replace(&mut app.mode, Mode::List) else {
+                return Ok(false);
+            };
+            begin_busy(app)?;
+
+            // A failed file doesn't stop the rest, it stays marked and all failures are listed
+            let mut deleted = Vec::new();
+            let mut failed = Vec::new();
+            for name in files {
+                match fs::remove_file(app.lists_dir.join(&name)) {
+                    Ok(()) => {
+                        app.remove_file(&name);
+                        deleted.push(name);
+                    }
+                    Err(e) => failed.push(format!("  {}: {}", name, e)),
+                }
+            }
+
+            // The saved selection would otherwise keep naming files that are gone
+            let mut done = format!("Удалено файлов: {}", deleted.len());
+            match selection::read(&app.config_path) {
+                Ok(saved) if saved.names.iter().any(|name| deleted.contains(name)) => {
+                    let kept: Vec<&str> = saved.names.iter()
+                        .filter(|name| !deleted.contains(name))
+                        .map(String::as_str)
+                        .collect();
+                    if let Err(e) = selection::write(&app.config_path, &kept, app.args.git_friendly) {
+                        failed.push(format!("  {}", e));
+                    }
+                }
+                Ok(_) => {}
+                Err(e) => failed.push(format!("  {}", e)),
+            }
+            app.clamp_cursor();
+
+            if failed.is_empty() {
+                app.status = Some(done);
+            } else {
+                done.push_str(&format!(", ошибок: {}", failed.len()));
+                let mut lines = vec![done, String::new()];
+                lines.extend(failed);
+                app.mode = Mode::Report { title: String::from("Удаление отмеченных"), lines, scroll: 0 };
+            }
+        }
+        KeyCode::Esc | KeyCode::Left | KeyCode::Char('n') | KeyCode::Char('N') => {
+            app.marked.clear();
+            app.mode = Mode::List;
+            app.status = Some(String::from("Удаление отменено, отметки сняты"));
+        }
+        _ => return Ok(handle_text_view_key(app, key)),
+    }
+
+    Ok(true)
+}
+
+/// What N would rename, including the renames skipped for a name collision
+fn show_normalize_plan(app: &mut App) {
+    let plan = match file_ops::plan_normalize(&app.lists_dir, &app.args.prefix, &app.args.suffix) {
+        Ok(plan) => plan,
+        Err(e) => {
+            app.status = Some(format!("Ошибка: {}", e));
+            return;
+        }
+    };
+    if plan.renames.is_empty() && plan.skipped.is_empty() {
+        app.status = Some(String::from("Все имена файлов уже в порядке"));
+        return;
+    }
+
+    let mut lines = vec![format!("Будет переименовано файлов: {}", plan.renames.len()), String::new()];
+    lines.extend(plan.renames.iter().map(|(old_name, new_name)| format!("  {} → {}", old_name, new_name)));
+    if !plan.skipped.is_empty() {
+        lines.push(String::new());
+        lines.push(format!("⚠ Пропущено, новое имя занято: {}", plan.skipped.len()));
+        lines.extend(plan.skipped.iter().map(|(name, reason)| format!("  {} — {}", name, reason)));
+    }
+    lines.push(String::new());
+    let config_name = app.config_path.file_name().unwrap_or_default().to_string_lossy();
+    lines.push(format!("Имена в {} будут исправлены вместе с файлами", config_name));
+    app.mode = Mode::ConfirmNormalize { renames: plan.renames, lines, scroll: 0 };
+}
+
+fn handle_confirm_normalize_key(app: &mut App, key: KeyEvent) -> io::Result<bool> {
+    match key.code {
+        KeyCode::Enter | KeyCode::Char('y') | KeyCode::Char('Y') => {
+            let Mode::ConfirmNormalize { renames, .. } = std::mem::replace(&mut app.mode, Mode::List) else {
+                return Ok(false);
+            };
+            if renames.is_empty() {
+                return Ok(true);
+            }
+            begin_busy(app)?;
+            normalize_names(app, &renames);
+        }
+        KeyCode::Esc | KeyCode::Left | KeyCode::Char('n') | KeyCode::Char('N') => app.mode = Mode::List,
+        _ => return Ok(handle_text_view_key(app, key)),
+    }
+
+    Ok(true)
+}
+
+/// Rename the files, then fix the names in the selection file with one write. When that
+/// write fails the renames are undone, so the files and the selection never disagree.
+fn normalize_names(app: &mut App, renames: &[(String, String)]) {
+    let mut done = Vec::new();
+    let mut failed = Vec::new();
+    for (old_name, new_name) in renames {
+        match fs::rename(app.lists_dir.join(old_name), app.lists_dir.join(new_name)) {
+            Ok(()) => done.push((old_name.clone(), new_name.clone())),
+            Err(e) => failed.push(format!("  {}: {}", old_name, e)),
+        }
+    }
+
+    let updated = selection::read(&app.config_path).and_then(|saved| {
+        let names: Vec<&str> = saved.names.iter()
+            .map(|name| done.iter().find(|(old_name, _)| old_name == name).map_or(name, |(_, new_name)| new_name))
+            .map(String::as_str)
+            .collect();
+        if names.iter().zip(&saved.names).all(|(name, saved)| name == saved) {
+            return Ok(());
+        }
+        selection::write(&app.config_path, &names, app.args.git_friendly)
+    });
+    if let Err(e) = updated {
+        for (old_name, new_name) in done.iter().rev() {
+            let _ = fs::rename(app.lists_dir.join(new_name), app.lists_dir.join(old_name));
+        }
+        app.status = Some(format!("Ошибка: {}, переименование отменено", e));
+        return;
+    }
+
+    app.apply_renames(&done);
+    let summary = format!("Переименовано файлов: {}", done.len());
+    if failed.is_empty() {
+        app.status = Some(summary);
+    } else {
+        let mut lines = vec![summary, String::new(), format!("Не удалось переименовать: {}", failed.len())];
+        lines.extend(failed);
+        app.mode = Mode::Report { title: String::from("Переименование"), lines, scroll: 0 };
+    }
+}
+
+/// Returns Some when the app should exit, None to keep going
+fn handle_confirm_save_key(stdout: &mut io::Stdout, app: &mut App, key: KeyEvent) -> io::Result<Option<io::Result<()>>> {
+    match key.code {
+        KeyCode::Enter | KeyCode::Char('y') | KeyCode::Char('Y') => {
+            if save_and_exit(stdout, app)? {
+                return Ok(Some(Ok(())));
+            }
+            app.mode = Mode::List;
+            app.status = Some(String::from("Объединение отменено"));
+        }
+        KeyCode::Esc | KeyCode::Left | KeyCode::Char('n') | KeyCode::Char('N') => app.mode = Mode::List,
+        _ => {
+            handle_text_view_key(app, key);
+        }
+    }
+
+    Ok(None)
+}
+
+/// Whether Esc was pressed since the last look, other keys are dropped
+fn esc_pressed() -> bool {
+    let mut pressed = false;
+    while let Ok(true) = event::poll(Duration::ZERO) {
+        if let Ok(Event::Key(key)) = event::read() {
+            pressed |= key.kind == event::KeyEventKind::Press && key.code == KeyCode::Esc;
+        }
+    }
+    pressed
+}
+
+/// Write selected.txt, merge the selected lists and show the result before exiting.
+/// Returns false when Esc cancelled the merge, nothing is written then.
+fn save_and_exit(stdout: &mut io::Stdout, app: &App) -> io::Result<bool> {
+    let selected_names = app.selected_names();
+    // Below the summary, the progress and then the result go here
+    let result_line = (SCROLL_AREA_HEIGHT + 4) as u16;
+
+    // All lists are read before anything is written, so Esc between two of them leaves
+    // both selected.txt and the previous list-ultimate.txt as they were
+    let total = selected_names.len();
+    let mut stop = |index: usize, name: &str| {
+        let progress = format!("Объединение {}/{}: {} (ESC — отменить)", index + 1, total, name);
+        let _ = execute!(stdout, cursor::MoveTo(0, result_line), terminal::Clear(ClearType::CurrentLine));
+        print!("{}", progress.dim());
+        let _ = stdout.flush();
+        esc_pressed()
+    };
+    app.timings.begin(timings::MERGE);
+    let prepared = merge::MergeOptions::from_args(&app.args, &app.lists_dir).and_then(|options| {
+        let prepared = merge::prepare(&app.lists_dir, &selected_names, &options, &mut stop)?;
+        Ok(prepared.map(|prepared| (prepared, options)))
+    });
+    app.timings.end(timings::MERGE);
+    let prepared = match prepared {
+        Ok(None) => return Ok(false),
+        Ok(Some(prepared)) => Ok(prepared),
+        Err(e) => Err(e),
+    };
+
+    // Save selected files to config
+    selection::write(&app.config_path, &selected_names, app.args.git_friendly)?;
+    if app.wizard {
+        wizard::finish(app.args.state_dir());
+    }
+    draft::remove(app.args.state_dir());
+    // Only feeds the audit view, not worth failing the save for
+    let mut ever_saved: Vec<&str> = app.ever_saved.iter().map(String::as_str).collect();
+    ever_saved.extend(selected_names.iter().filter(|name| !app.ever_saved.contains(**name)));
+    ever_saved.sort();
+    let _ = state::set(app.args.state_dir(), state::EVER_SAVED, &ever_saved.join("/"));
+    let mut save_counts = app.save_counts.clone();
+    for name in &selected_names {
+        *save_counts.entry(name.to_string()).or_insert(0) += 1;
+    }
+    let mut save_counts: Vec<String> = save_counts.iter()
+        .map(|(name, count)| format!("{}:{}", name, count))
+        .collect();
+    save_counts.sort();
+    let _ = state::set(app.args.state_dir(), state::SAVE_COUNTS, &save_counts.join("/"));
+
+    let save_delay = app.args.save_delay;
+    let exit_note = if save_delay > 0 {
+        format!(" Выход через {} сек...", save_delay)
+    } else {
+        String::new()
+    };
+
+    // Join selected files into list-ultimate.txt
+    app.timings.begin(timings::MERGE_WRITE);
+    let merged = prepared.and_then(|(prepared, options)| merge::write(&app.lists_dir, prepared, &options));
+    app.timings.end(timings::MERGE_WRITE);
+
+    execute!(
+        stdout,
+        cursor::MoveTo(0, result_line),
+        terminal::Clear(ClearType::FromCursorDown)
+    )?;
+    match merged {
+        Err(e) => {
+            println!("{}", format!("Ошибка при объединении файлов: {}.{}", e, exit_note).red());
+        }
+        Ok(stats) => {
+            let mut summary = format!("доменов: {}", stats.written);
+            if stats.pinned > 0 {
+                summary.push_str(&format!(", закреплено в начале: {}", stats.pinned));
+            }
+            if stats.excluded > 0 {
+                summary.push_str(&format!(", исключено: {}", stats.excluded));
+            }
+            if stats.cleaned > 0 {
+                summary.push_str(&format!(", очищено: {}", stats.cleaned));
+            }
+            if stats.idn_collapsed > 0 {
+                summary.push_str(&format!(", сведено IDN: {}", stats.idn_collapsed));
+            }
+            if stats.case_collapsed > 0 {
+                summary.push_str(&format!(", сведено по регистру: {}", stats.case_collapsed));
+            }
+            if stats.denied > 0 {
+                summary.push_str(&format!(", убрано по {}: {}", merge::DENYLIST_FILE, stats.denied));
+            }
+            if let Some(seed) = stats.shuffle_seed {
+                summary.push_str(&format!(", порядок перемешан (--shuffle {})", seed));
+            }
+            if stats.unchanged > 0 {
+                summary.push_str(", файл не изменился и не переписан");
+            }
+            if !stats.invalid.is_empty() {
+                let shown: Vec<&str> = stats.invalid.iter().take(3).map(String::as_str).collect();
+                summary.push_str(&format!(
+                    ", отброшено некорректных: {} ({}{})",
+                    stats.invalid.len(),
+                    shown.join(", "),
+                    if stats.invalid.len() > shown.len() { ", ..." } else { "" }
+                ));
+            }
+            if let Some((added, removed)) = stats.changes {
+                summary.push_str(&format!(", изменения: +{} / -{}", added, removed));
+            }
+            summary.push_str(&format!(", контрольная сумма: {}", checksum::format(stats.checksum)));
+            println!("{}", format!("Успешно! Список сохранен и файлы объединены ({}).{}", summary, exit_note).green());
+            if let Some(backup) = &stats.backup {
+                println!("Предыдущий список сохранён в {}", backup.display());
+            }
+            if let (Some(first), Some(last)) = (stats.chunks.first(), stats.chunks.last()) {
+                let name = |path: &Path| path.file_name().unwrap_or_default().to_string_lossy().into_owned();
+                println!("Записано частей: {} ({} — {})", stats.chunks.len(), name(first), name(last));
+            }
+            for warning in &stats.warnings {
+                println!("{}", format!("Внимание: {}", warning).yellow());
+            }
+            if let Some(path) = &app.args.stats_json {
+                if let Err(e) = stats_json::write(path, &stats) {
+                    println!("{}", format!("Внимание: не удалось записать статистику: {}", e).yellow());
+                }
+            }
+
+            if let Some(command) = &app.args.post_save_cmd {
+                match hook::run(command) {
+                    Ok(()) => println!("{}", format!("Команда после сохранения выполнена: {}", command).green()),
+                    Err(e) => println!("{}", format!("Ошибка команды после сохранения ({}): {}", command, e).red()),
+                }
+            }
+        }
+    }
+
+    stdout.flush()?;
+    if save_delay > 0 {
+        thread::sleep(Duration::from_secs(save_delay));
+    }
+
+    Ok(true)
+}
+
+fn open_submenu(app: &mut App) {
+    if let Some(entry) = app.current_file() {
+        app.mode = Mode::Submenu { file: entry.name.clone(), cursor: 0 };
+    }
+}
+
+/// The selection file exactly as it is on disk, read again every time so outside edits show
+fn show_saved_file(app: &mut App) {
+    let name = app.config_path.file_name().unwrap_or_default().to_string_lossy().into_owned();
+    if let Err(e) = selection::check(&app.config_path) {
+        app.status = Some(format!("Ошибка: {}", e));
+        return;
+    }
+    let lines = if !app.config_path.exists() {
+        vec![format!("{} ещё нет, он появится при первом сохранении", display_path(&app.config_path))]
+    } else {
+        match file_ops::read_lines(&app.config_path) {
+            Ok(lines) if lines.is_empty() => vec![String::from("(файл пуст)")],
+            Ok(lines) => lines,
+            Err(e) => {
+                app.status = Some(format!("Ошибка: {}", e));
+                return;
+            }
+        }
+    };
+    app.mode = Mode::Report { title: format!("{} на диске", name), lines, scroll: 0 };
+}
+
+/// Compare the domains of exactly two selected lists
+fn show_overlap_report(app: &mut App) {
+    let selected: Vec<String> = app.entries.iter()
+        .filter(|e| e.selected && !e.is_control())
+        .map(|e| e.name.clone())
+        .collect();
+
+    let [name_a, name_b] = selected.as_slice() else {
+        app.status = Some(format!("Выберите ровно два списка для сравнения (выбрано: {})", selected.len()));
+        return;
+    };
+
+    let domains_a = file_ops::read_domains(&remote::content_path(&app.lists_dir, name_a));
+    let domains_b = file_ops::read_domains(&remote::content_path(&app.lists_dir, name_b));
+    let (domains_a, domains_b) = match (domains_a, domains_b) {
+        (Ok(a), Ok(b)) => (a, b),
+        (Err(e), _) | (_, Err(e)) => {
+            app.status = Some(format!("Ошибка: {}", e));
+            return;
+        }
+    };
+
+    let mut shared: Vec<&String> = domains_a.intersection(&domains_b).collect();
+    shared.sort();
+
+    let mut lines = vec![
+        format!("A: {} — доменов: {}", name_a, domains_a.len()),
+        format!("B: {} — доменов: {}", name_b, domains_b.len()),
+        String::new(),
+        format!("Только в A: {}", domains_a.len() - shared.len()),
+        format!("Только в B: {}", domains_b.len() - shared.len()),
+        format!("Общие:      {}", shared.len()),
+        String::new(),
+    ];
+
+    if !shared.is_empty() && shared.len() == domains_a.len() {
+        lines.push(String::from("Все домены A есть в B — список A избыточен"));
+    } else if !shared.is_empty() && shared.len() == domains_b.len() {
+        lines.push(String::from("Все домены B есть в A — список B избыточен"));
+    }
+
+    if !shared.is_empty() {
+        lines.push(String::from("Общие домены:"));
+        lines.extend(shared.iter().map(|domain| format!("  {}", domain)));
+    }
+
+    app.mode = Mode::Report {
+        title: String::from("Пересечение списков"),
+        lines,
+        scroll: 0,
+    };
+}
+
+/// Returns whether the screen needs a redraw
+fn handle_submenu_key(app: &mut App, key: KeyEvent) -> io::Result<bool> {
+    let Mode::Submenu { file, cursor } = &mut app.mode else {
+        return Ok(false);
+    };
+
+    match key.code {
+        KeyCode::Up => *cursor = cursor.saturating_sub(1),
+        KeyCode::Down => *cursor = (*cursor + 1).min(FileAction::ALL.len() - 1),
+        KeyCode::Left | KeyCode::Esc => app.mode = Mode::List,
+        KeyCode::Enter | KeyCode::Char(' ') => {
+            let file = file.clone();
+            let action = FileAction::ALL[*cursor];
+            run_file_action(app, file, action)?;
+        }
+        _ => return Ok(false),
+    }
+
+    Ok(true)
+}
+
+fn run_file_action(app: &mut App, file: String, action: FileAction) -> io::Result<()> {
+    let file_path = app.lists_dir.join(&file);
+    app.mode = Mode::List;
+    if action.modifies_file() && refuse_in_review(app) {
+        return Ok(());
+    }
+
+    match action {
+        FileAction::Preview => {
+            match file_ops::read_lines(&file_path) {
+                Ok(lines) => {
+                    let size = fs::metadata(&file_path).map_or(0, |meta| meta.len());
+                    app.mode = Mode::Preview { file, lines, size, scroll: 0, cursor: None, file_lines: None };
+                }
+                Err(e) => app.status = Some(format!("Ошибка: {}", e)),
+            }
+        }
+        FileAction::Dedup => {
+            begin_busy(app)?;
+            app.status = Some(match file_ops::dedup_file(&file_path) {
+                Ok(0) => format!("{}: дубликатов нет", file),
+                Ok(removed) => format!("{}: удалено дубликатов: {}", file, removed),
+                Err(e) => format!("Ошибка: {}", e),
+            });
+            app.invalidate_content(&file);
+        }
+        FileAction::Validate => {
+            app.status = Some(match file_ops::validate_file(&file_path) {
+                Ok(invalid) if invalid.is_empty() => format!("{}: все домены корректны", file),
+                Ok(invalid) => {
+                    let shown: Vec<String> = invalid.iter().take(10).map(|n| n.to_string()).collect();
+                    format!(
+                        "{}: некорректных строк: {} (строки {}{})",
+                        file,
+                        invalid.len(),
+                        shown.join(", "),
+                        if invalid.len() > shown.len() { ", ..." } else { "" }
+                    )
+                }
+                Err(e) => format!("Ошибка: {}", e),
+            });
+        }
+        FileAction::Rename => app.mode = Mode::Rename { input: file.clone(), file },
+        FileAction::Delete => app.mode = Mode::ConfirmDelete { file },
+    }
+
+    Ok(())
+}
+
+/// Scrolling like the other text views, plus Tab to put a cursor on the lines and comment
+/// or uncomment the one under it, or disable and enable it with '!'. W switches between
+/// wrapping and truncating long lines in both. D shows the file without its duplicates and
+/// back, nothing is written: removing them is still the submenu action or D in the list.
+fn handle_preview_key(app: &mut App, key: KeyEvent) -> io::Result<bool> {
+    if key.code == KeyCode::Tab && refuse_in_review(app) {
+        return Ok(false);
+    }
+    let Mode::Preview { file, lines, size, scroll, cursor, file_lines } = &mut app.mode else {
+        return Ok(false);
+    };
+
+    if matches!(key.code, KeyCode::Char('w') | KeyCode::Char('W')) {
+        app.preview_wrap = !app.preview_wrap;
+        return Ok(true);
+    }
+
+    let Some(line) = *cursor else {
+        if matches!(key.code, KeyCode::Char('d') | KeyCode::Char('D')) {
+            match file_lines.take() {
+                Some(original) => *lines = original,
+                None => {
+                    let (kept, _) = file_ops::split_duplicates(lines);
+                    *file_lines = Some(std::mem::replace(lines, kept));
+                }
+            }
+            *scroll = 0;
+            return Ok(true);
+        }
+        // The edits are of the file, not of the deduplicated view
+        if key.code == KeyCode::Tab && file_lines.is_some() {
+            return Ok(false);
+        }
+        if key.code == KeyCode::Tab && !lines.is_empty() {
+            *cursor = Some(*scroll);
+            return Ok(true);
+        }
+        if matches!(key.code, KeyCode::Char('r') | KeyCode::Char('R')) {
+            let (file, lines) = (file.clone(), reason_lines(lines));
+            app.mode = Mode::Report { title: format!("Причины в {}", file), lines, scroll: 0 };
+            return Ok(true);
+        }
+        return Ok(handle_text_view_key(app, key));
+    };
+
+    match key.code {
+        KeyCode::Tab => *cursor = None,
+        KeyCode::Up => *cursor = Some(line.saturating_sub(1)),
+        KeyCode::Down => *cursor = Some((line + 1).min(lines.len() - 1)),
+        KeyCode::Char('#') | KeyCode::Char(' ') if !lines[line].trim().is_empty() => {
+            let file = file.clone();
+            lines[line] = file_ops::toggle_comment(&lines[line]);
+            // The in-memory copy no longer matches the file then, so the preview closes
+            if let Err(e) = file_ops::write_atomic(&app.lists_dir.join(&file), lines) {
+                app.status = Some(format!("Ошибка: {}", e));
+                app.mode = Mode::List;
+                return Ok(true);
+            }
+            *size = fs::metadata(app.lists_dir.join(&file)).map_or(*size, |meta| meta.len());
+            app.recount(&file);
+            app.status = Some(format!("{}: изменена строка {}", file, line + 1));
+        }
+        KeyCode::Char('!') if file_ops::entry_of(&lines[line]).is_some() || file_ops::disabled_entry(&lines[line]).is_some() => {
+            let file = file.clone();
+            lines[line] = file_ops::toggle_disabled(&lines[line]);
+            if let Err(e) = file_ops::write_atomic(&app.lists_dir.join(&file), lines) {
+                app.status = Some(format!("Ошибка: {}", e));
+                app.mode = Mode::List;
+                return Ok(true);
+            }
+            let disabled = file_ops::disabled_entry(&lines[line]).is_some();
+            *size = fs::metadata(app.lists_dir.join(&file)).map_or(*size, |meta| meta.len());
+            app.recount(&file);
+            app.status = Some(format!(
+                "{}: строка {} {}",
+                file, line + 1, if disabled { "отключена" } else { "включена" }
+            ));
+        }
+        KeyCode::Left | KeyCode::Esc => app.mode = Mode::List,
+        _ => return Ok(false),
+    }
+
+    // Keep the edited line on screen
+    if let Mode::Preview { scroll, cursor: Some(line), .. } = &mut app.mode {
+        if *line < *scroll {
+            *scroll = *line;
+        } else if *line >= *scroll + SCROLL_AREA_HEIGHT {
+            *scroll = *line + 1 - SCROLL_AREA_HEIGHT;
+        }
+    }
+    Ok(true)
+}
+
+/// "строка 12: example.com — reason" for every line of a list with a note after "##"
+fn reason_lines(lines: &[String]) -> Vec<String> {
+    let reasons: Vec<String> = lines.iter()
+        .enumerate()
+        .filter_map(|(index, line)| {
+            let reason = file_ops::reason_of(line)?;
+            let entry = file_ops::entry_of(line)?;
+            Some(format!("строка {}: {} — {}", index + 1, entry, reason))
+        })
+        .collect();
+    if reasons.is_empty() {
+        vec![format!("Нет доменов с причиной (домен {} причина)", file_ops::REASON_DELIMITER)]
+    } else {
+        reasons
+    }
+}
+
+fn handle_text_view_key(app: &mut App, key: KeyEvent) -> bool {
+    let max_scroll = ui::max_scroll(app);
+    let (
+        Mode::Preview { scroll, .. } |
+        Mode::Report { scroll, .. } |
+        Mode::ConfirmSave { scroll, .. } |
+        Mode::ConfirmDedupAll { scroll, .. } |
+        Mode::ConfirmDeleteMarked { scroll, .. } |
+        Mode::ConfirmNormalize { scroll, .. }
+    ) = &mut app.mode else {
+        return false;
+    };
+
+    match key.code {
+        KeyCode::Up => *scroll = scroll.saturating_sub(1),
+        KeyCode::Down => *scroll = (*scroll + 1).min(max_scroll),
+        KeyCode::PageUp => *scroll = scroll.saturating_sub(SCROLL_AREA_HEIGHT),
+        KeyCode::PageDown => *scroll = (*scroll + SCROLL_AREA_HEIGHT).min(max_scroll),
+        KeyCode::Left | KeyCode::Esc => app.mode = Mode::List,
+        _ => return false,
+    }
+
+    true
+}
+
+fn handle_rename_key(app: &mut App, key: KeyEvent) -> io::Result<bool> {
+    let Mode::Rename { file, input } = &mut app.mode else {
+        return Ok(false);
+    };
+
+    match key.code {
+        KeyCode::Char(c) => input.push(c),
+        KeyCode::Backspace => {
+            input.pop();
+        }
+        KeyCode::Esc => app.mode = Mode::List,
+        KeyCode::Enter => {
+            let old_name = file.clone();
+            let new_name = input.trim().to_string();
+            app.mode = Mode::List;
+
+            if new_name == old_name {
+                return Ok(true);
+            }
+
+            begin_busy(app)?;
+            let check = file_ops::check_new_name(&app.lists_dir, &new_name, &app.args.prefix, &app.args.suffix);
+            app.status = Some(match check {
+                Err(reason) => format!("Нельзя переименовать: {}", reason),
+                Ok(()) => match fs::rename(app.lists_dir.join(&old_name), app.lists_dir.join(&new_name)) {
+                    Ok(()) => {
+                        app.rename_file(&old_name, &new_name);
+                        format!("{} переименован в {}", old_name, new_name)
+                    }
+                    Err(e) => format!("Не удалось переименовать {}: {}", old_name, e),
+                },
+            });
+        }
+        _ => return Ok(false),
+    }
+
+    Ok(true)
+}
+
+fn handle_write_as_key(app: &mut App, key: KeyEvent) -> io::Result<bool> {
+    let Mode::WriteAs { input } = &mut app.mode else {
+        return Ok(false);
+    };
+
+    match key.code {
+        KeyCode::Char(c) => input.push(c),
+        KeyCode::Backspace => {
+            input.pop();
+        }
+        KeyCode::Tab => {
+            if let Some(completed) = file_ops::complete_path(input) {
+                *input = completed;
+            }
+        }
+        KeyCode::Esc => app.mode = Mode::List,
+        KeyCode::Enter => {
+            let path = PathBuf::from(input.trim());
+            app.mode = Mode::List;
+            if path.as_os_str().is_empty() {
+                return Ok(true);
+            }
+            begin_busy(app)?;
+            app.status = Some(match write_merged_to(app, &path) {
+                Ok(stats) => format!(
+                    "Записано в {}: {} байт, доменов: {}",
+                    display_path(&path), stats.size, stats.written
+                ),
+                Err(e) => format!("Не удалось записать: {}", e),
+            });
+        }
+        _ => return Ok(false),
+    }
+
+    Ok(true)
+}
+
+/// The usual merge of the current selection, with the output options of the command line, while
+/// selected.txt stays as it was. The save hook doesn't run since nothing was saved.
+fn merge_now(app: &App) -> String {
+    let selected_names = app.selected_names();
+    app.timings.begin(timings::MERGE);
+    let prepared = merge::MergeOptions::from_args(&app.args, &app.lists_dir).and_then(|options| {
+        let prepared = merge::prepare(&app.lists_dir, &selected_names, &options, &mut |_, _| false)?
+            .expect("a merge without a stop condition is never stopped");
+        Ok((prepared, options))
+    });
+    app.timings.end(timings::MERGE);
+    app.timings.begin(timings::MERGE_WRITE);
+    let merged = prepared.and_then(|(prepared, options)| merge::write(&app.lists_dir, prepared, &options));
+    app.timings.end(timings::MERGE_WRITE);
+
+    let stats = match merged {
+        Ok(stats) => stats,
+        Err(e) => return format!("Ошибка при объединении файлов: {}", e),
+    };
+    let mut summary = format!(
+        "Объединено без сохранения выбора: списков: {}, доменов: {}",
+        selected_names.len(), stats.written
+    );
+    if let Some((added, removed)) = stats.changes {
+        summary.push_str(&format!(", изменения: +{} / -{}", added, removed));
+    }
+    if !stats.chunks.is_empty() {
+        summary.push_str(&format!(", частей: {}", stats.chunks.len()));
+    }
+    if !stats.warnings.is_empty() {
+        summary.push_str(&format!(" · Внимание: {}", stats.warnings.join("; ")));
+    }
+    if let Some(path) = &app.args.stats_json {
+        if let Err(e) = stats_json::write(path, &stats) {
+            summary.push_str(&format!(" · не удалось записать статистику: {}", e));
+        }
+    }
+    summary
+}
+
+/// The merge of the current selection written to path alone, as one file. selected.txt, the
+/// usual output and the state are left alone, and the save hooks don't run.
+fn write_merged_to(app: &App, path: &Path) -> Result<merge::MergeStats, String> {
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    if !parent.is_dir() {
+        return Err(format!("папки {} нет", display_path(parent)));
+    }
+    if path.is_dir() {
+        return Err(format!("{} — это папка", display_path(path)));
+    }
+    // Overwriting a list or the selection file from here is never what was meant
+    if let Ok(target) = path.canonicalize() {
+        let same = |other: &Path| other.canonicalize().is_ok_and(|other| other == target);
+        if same(&app.config_path) {
+            return Err(format!("{} — это файл выбора", display_path(path)));
+        }
+        if app.entries.iter().any(|e| !e.is_control() && same(&app.lists_dir.join(&e.name))) {
+            return Err(format!("{} — это один из списков", display_path(path)));
+        }
+    }
+
+    let options = merge::MergeOptions {
+        output: Some(path.to_path_buf()),
+        split_size: None,
+        backup: false,
+        state_dir: None,
+        ..merge::MergeOptions::from_args(&app.args, &app.lists_dir).map_err(|e| e.to_string())?
+    };
+    let selected_names = app.selected_names();
+    let prepared = merge::prepare(&app.lists_dir, &selected_names, &options, &mut |_, _| false)
+        .map_err(|e| e.to_string())?
+        .expect("a merge without a stop condition is never stopped");
+    merge::write(&app.lists_dir, prepared, &options).map_err(|e| e.to_string())
+}
+
+/// Live filtering while the query is typed
+fn handle_search_key(app: &mut App, key: KeyEvent) -> bool {
+    match key.code {
+        KeyCode::Char(c) => app.filter.push(c),
+        KeyCode::Backspace => {
+            app.filter.pop();
+        }
+        KeyCode::Tab => app.case_sensitive = !app.case_sensitive,
+        KeyCode::Enter => app.mode = Mode::List,
+        KeyCode::Esc => {
+            app.filter.clear();
+            app.mode = Mode::List;
+        }
+        _ => return false,
+    }
+
+    app.apply_filter();
+    true
+}
+
+fn reset_selection(app: &mut App, names: &[String]) {
+    for entry in app.entries.iter_mut().filter(|e| !e.is_control()) {
+        entry.selected = names.contains(&entry.name);
+        entry.saved = entry.selected;
+    }
+    app.recount_selected();
+    app.resort();
+    app.status = Some(format!("Выбор сброшен к сохранённому (выбрано: {})", app.selected_names().len()));
+}
+
+/// Select exactly the lists in names, which are those not in the saved selection. The S
+/// markers stay, selected.txt on disk doesn't change until the next save.
+fn select_inverse_of_saved(app: &mut App, names: &[String]) {
+    for entry in app.entries.iter_mut().filter(|e| !e.is_control()) {
+        entry.selected = names.contains(&entry.name);
+    }
+    app.recount_selected();
+    app.resort();
+    let selected = app.selected_names().len();
+    app.status = Some(if app.entries.iter().any(|e| e.saved) {
+        format!("Выбраны списки, которых нет в сохранённом выборе (выбрано: {})", selected)
+    } else {
+        format!("Сохранённого выбора нет, выбраны все списки (выбрано: {})", selected)
+    });
+}
+
+fn handle_confirm_reset_key(app: &mut App, key: KeyEvent) -> bool {
+    let Mode::ConfirmReset { names, inverse, .. } = &mut app.mode else {
+        return false;
+    };
+
+    let (names, inverse) = (std::mem::take(names), *inverse);
+    app.mode = Mode::List;
+    if matches!(key.code, KeyCode::Char('y') | KeyCode::Char('Y')) {
+        if inverse {
+            select_inverse_of_saved(app, &names);
+        } else {
+            reset_selection(app, &names);
+        }
+    } else {
+        app.status = Some(String::from("Сброс отменён"));
+    }
+    true
+}
+
+fn handle_wizard_key(app: &mut App, key: KeyEvent) -> bool {
+    let Mode::Wizard { step, cursor } = &mut app.mode else {
+        return false;
+    };
+    let (step, choices) = (*step, wizard::choice_count());
+
+    match key.code {
+        KeyCode::Up if step == wizard::Step::Template => *cursor = cursor.saturating_sub(1),
+        KeyCode::Down if step == wizard::Step::Template => *cursor = (*cursor + 1).min(choices - 1),
+        KeyCode::Left | KeyCode::Backspace if step == wizard::Step::Template => {
+            app.mode = Mode::Wizard { step: wizard::Step::Folder, cursor: 0 };
+            wizard::reached(app.args.state_dir(), wizard::Step::Folder);
+        }
+        KeyCode::Enter | KeyCode::Right if step == wizard::Step::Folder => {
+            app.mode = Mode::Wizard { step: wizard::Step::Template, cursor: 0 };
+            wizard::reached(app.args.state_dir(), wizard::Step::Template);
+        }
+        KeyCode::Enter | KeyCode::Right => {
+            if let Some(template) = wizard::chosen_template(*cursor) {
+                for entry in app.entries.iter_mut().filter(|e| !e.is_control()) {
+                    entry.selected = template.files.contains(&entry.name.as_str());
+                }
+                app.recount_selected();
+                app.resort();
+            }
+            app.mode = Mode::List;
+            app.wizard = true;
+            wizard::reached(app.args.state_dir(), wizard::Step::Selection);
+            app.status = Some(format!("Выбрано списков: {}", app.selected_names().len()));
+        }
+        KeyCode::Esc => {
+            app.mode = Mode::List;
+            wizard::finish(app.args.state_dir());
+            app.status = Some(String::from("Мастер пропущен, --wizard откроет его снова"));
+        }
+        _ => return false,
+    }
+    true
+}
+
+fn handle_restore_draft_key(app: &mut App, key: KeyEvent) -> bool {
+    let Mode::RestoreDraft { names } = &mut app.mode else {
+        return false;
+    };
+
+    let names = std::mem::take(names);
+    app.mode = Mode::List;
+
+    app.status = Some(match key.code {
+        KeyCode::Char('y') | KeyCode::Char('Y') => {
+            for entry in app.entries.iter_mut().filter(|e| !e.is_control()) {
+                entry.selected = names.contains(&entry.name);
+            }
+            app.recount_selected();
+            app.resort();
+            String::from("Черновик восстановлен, нажмите Ctrl+S, чтобы сохранить")
+        }
+        _ => {
+            draft::remove(app.args.state_dir());
+            String::from("Черновик удалён")
+        }
+    });
+
+    true
+}
+
+fn handle_confirm_delete_key(app: &mut App, key: KeyEvent) -> io::Result<bool> {
+    let Mode::ConfirmDelete { file } = &mut app.mode else {
+        return Ok(false);
+    };
+
+    let file = file.clone();
+    app.mode = Mode::List;
+
+    let confirmed = matches!(key.code, KeyCode::Char('y') | KeyCode::Char('Y'));
+    if !confirmed {
+        app.status = Some(String::from("Удаление отменено"));
+        return Ok(true);
+    }
+
+    begin_busy(app)?;
+    app.status = Some(match fs::remove_file(app.lists_dir.join(&file)) {
+        Ok(()) => {
+            app.remove_file(&file);
+            format!("{} удалён", file)
+        }
+        Err(e) => format!("Не удалось удалить {}: {}", file, e),
+    });
+
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn human_size_boundaries() {
+        assert_eq!(human_size(0), "0 байт");
+        assert_eq!(human_size(999), "999 байт");
+        assert_eq!(human_size(1023), "1023 байт");
+        assert_eq!(human_size(1024), "1.0 КБ");
+        assert_eq!(human_size(1536), "1.5 КБ");
+        assert_eq!(human_size(1024 * 1024), "1.0 МБ");
+        assert_eq!(human_size(1024 * 1024 * 3 / 2), "1.5 МБ");
+    }
+}
@@ -0,0 +1,1001 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fs;
+use std::hash::{BuildHasher, Hasher};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::cli::Args;
+use crate::errors::{self, Context, SelectorError};
+use crate::{checksum, file_ops, hook, punycode, remote, state};
+use crate::OUTPUT_FILE;
+
+/// Lists named <EXCLUDE_PREFIX>*<suffix> hold domains that are carved out of the merge
+pub const EXCLUDE_PREFIX: &str = "exclude-";
+
+/// Domains that never go into the output, whatever is selected, see MergeOptions::denylist
+pub const DENYLIST_FILE: &str = ".denylist";
+
+/// Order of the domains in OUTPUT_FILE
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum SortOrder {
+    #[default]
+    Alphabetical,
+    /// Grouped by the last label ("ru", "com"), alphabetical inside a group. That is a plain
+    /// approximation of the public suffix: "co.uk" domains end up among the other "uk" ones.
+    Tld,
+    /// Shortest first, so "example.com" comes before "cdn.example.com". Domains of the same
+    /// length are alphabetical, which keeps the output reproducible.
+    Length,
+}
+
+impl SortOrder {
+    pub fn parse(value: &str) -> Option<SortOrder> {
+        match value {
+            "alpha" => Some(SortOrder::Alphabetical),
+            "tld" => Some(SortOrder::Tld),
+            "length" => Some(SortOrder::Length),
+            _ => None,
+        }
+    }
+}
+
+/// Newline written after every output line. LF everywhere by default: zapret reads the file
+/// on Linux-like systems even when the list is prepared on Windows.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum LineEnding {
+    #[default]
+    Lf,
+    CrLf,
+}
+
+impl LineEnding {
+    pub fn parse(value: &str) -> Option<LineEnding> {
+        match value {
+            "lf" => Some(LineEnding::Lf),
+            "crlf" => Some(LineEnding::CrLf),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LineEnding::Lf => "\n",
+            LineEnding::CrLf => "\r\n",
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct MergeOptions {
+    pub exclude_files: Vec<PathBuf>,
+    /// DENYLIST_FILE in the lists folder when there is one, applied after everything else
+    pub denylist: Option<PathBuf>,
+    /// Reduce URL-like entries ("https://host:443/path") to the bare hostname, drop invalid ones
+    pub clean: bool,
+    /// Write internationalized domains in their punycode form, see punycode.rs
+    pub punycode: bool,
+    /// Download remote (.url) lists instead of only using their cache
+    pub allow_network: bool,
+    /// Copy full-line comments into the output, tagged with their source file. The
+    /// output then keeps the source order so the comments stay next to their domains.
+    pub keep_comments: bool,
+    /// Put a "# --- from name ---" line before the domains of every list, also in source order
+    pub separators: bool,
+    /// Domains written first in the order of this file, before the sorted (or source ordered) rest
+    pub pin_file: Option<PathBuf>,
+    /// Written as a "# semantics: ..." first line when the output has comments anyway
+    pub semantics: Option<Semantics>,
+    /// Start the output with when and by what it was generated, from how many lists and with
+    /// how many domains. Only where comments are, like semantics, or in the dnsmasq format.
+    pub header: bool,
+    /// Ignored with keep_comments or separators, which keep the source order
+    pub sort: SortOrder,
+    /// Seed of a pseudo-random order written instead of sort, with the same exception
+    pub shuffle: Option<u64>,
+    /// --git-friendly: an output file that would get the same bytes isn't rewritten, and the
+    /// header leaves out the generation time, so an unchanged merge changes nothing on disk
+    pub only_changed: bool,
+    /// Write every domain as its first occurrence spells it instead of lowercased. Domains
+    /// are still compared lowercased, for deduplication and everything else.
+    pub preserve_case: bool,
+    pub line_ending: LineEnding,
+    pub format: OutputFormat,
+    /// Copy the previous output to <output>.bak before overwriting it
+    pub backup: bool,
+    /// Write chunks of at most this many domains (see chunk_path) instead of one file
+    pub split_size: Option<usize>,
+    /// Where to write the result, None for OUTPUT_FILE in the lists folder
+    pub output: Option<PathBuf>,
+    /// --transform-cmd: every output file is piped through this shell command and its
+    /// output written instead
+    pub transform_cmd: Option<String>,
+    /// Where state::OUTPUT_CHECKSUM is recorded, None for a one-off copy (W) that --doctor
+    /// doesn't check
+    pub state_dir: Option<PathBuf>,
+}
+
+impl MergeOptions {
+    pub fn from_args(args: &Args, lists_dir: &Path) -> errors::Result<MergeOptions> {
+        Ok(MergeOptions {
+            exclude_files: exclude_files(lists_dir, &args.suffix, &args.exclude)?,
+            denylist: Some(lists_dir.join(DENYLIST_FILE)).filter(|path| path.is_file()),
+            clean: args.clean,
+            punycode: args.punycode,
+            allow_network: args.allow_network,
+            keep_comments: args.keep_comments,
+            separators: args.separators,
+            semantics: args.semantics,
+            header: args.header,
+            preserve_case: args.preserve_case,
+            only_changed: args.git_friendly,
+            shuffle: args.shuffle.map(|seed| seed.unwrap_or_else(random_seed)),
+            pin_file: args.pin_file.clone(),
+            sort: args.sort,
+            line_ending: args.line_ending,
+            format: args.format.clone(),
+            backup: args.backup,
+            split_size: args.split_size,
+            output: args.output.clone(),
+            transform_cmd: args.transform_cmd.clone(),
+            state_dir: Some(args.state_dir().to_path_buf()),
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct FileStats {
+    pub name: String,
+    /// Domain entries read from the file
+    pub domains: usize,
+    /// Entries already provided by an earlier list or repeated within this one
+    pub duplicates: usize,
+    /// Domains this list was the first to provide
+    pub added: usize,
+}
+
+#[derive(Debug, Default)]
+pub struct MergeStats {
+    /// Unique domains written to the output
+    pub written: usize,
+    /// Domains dropped because another selected list already had them
+    pub duplicates: usize,
+    /// Unique domains dropped because an exclude list contains them
+    pub excluded: usize,
+    /// Entries that had a scheme, port, path or query stripped (--clean only)
+    pub cleaned: usize,
+    /// Entries that aren't valid domains (or, with --clean, couldn't be reduced to one) and were dropped
+    pub invalid: Vec<String>,
+    /// Problems that didn't stop the merge, e.g. a remote list served from cache
+    pub warnings: Vec<String>,
+    pub per_file: Vec<FileStats>,
+    /// CRC-32 of the written output
+    pub checksum: u32,
+    /// Domains (added, removed) compared to the previous output, None if there was none
+    pub changes: Option<(usize, usize)>,
+    /// Bytes of the output
+    pub size: usize,
+    /// Where the previous output was copied with backup, the first chunk's copy with split_size
+    pub backup: Option<PathBuf>,
+    /// Files written with split_size, in order
+    pub chunks: Vec<PathBuf>,
+    /// The output file, the name the chunks are numbered after with split_size
+    pub output: PathBuf,
+    /// Domains put at the top from the pin file, they count in written as well
+    pub pinned: usize,
+    /// The label the merge was made with, see MergeOptions::semantics
+    pub semantics: Option<Semantics>,
+    /// With punycode: domains dropped because the same host was already there in the other
+    /// spelling (Unicode or punycode), not counted in duplicates
+    pub idn_collapsed: usize,
+    /// Unique domains dropped by the denylist, pinned ones included
+    pub denied: usize,
+    /// The seed the order was shuffled with, None when it wasn't
+    pub shuffle_seed: Option<u64>,
+    /// With preserve_case: repeats of a domain spelled in another case, not counted in duplicates
+    pub case_collapsed: usize,
+    /// With only_changed: output files left as they were since they had the content already
+    pub unchanged: usize,
+}
+
+impl MergeStats {
+    /// Lists read but with every domain already provided by earlier lists in the merge
+    /// order, deselecting them wouldn't change the output
+    pub fn redundant(&self) -> Vec<&str> {
+        self.per_file.iter()
+            .filter(|file| file.domains > 0 && file.duplicates == file.domains)
+            .map(|file| file.name.as_str())
+            .collect()
+    }
+
+    /// Share of the domains read from the lists that another list already had, 0 to 1
+    pub fn duplicate_ratio(&self) -> f64 {
+        let read: usize = self.per_file.iter().map(|file| file.domains).sum();
+        if read == 0 {
+            0.0
+        } else {
+            self.duplicates as f64 / read as f64
+        }
+    }
+}
+
+/// Exclude lists that live next to the regular lists plus the ones given with --exclude
+pub fn exclude_files(lists_dir: &Path, suffix: &str, extra: &[PathBuf]) -> errors::Result<Vec<PathBuf>> {
+    let mut files: Vec<PathBuf> = file_ops::scan_lists(lists_dir, EXCLUDE_PREFIX, suffix, None)?
+        .into_iter()
+        .map(|name| lists_dir.join(name))
+        .collect();
+    files.extend(extra.iter().cloned());
+    Ok(files)
+}
+
+/// What the merged file is for, --semantics block|allow. Only a label: it's written to the
+/// header comment and the reports, the merge itself is the same for both.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Semantics {
+    /// Domains that are blocked and have to go through zapret
+    Block,
+    /// Domains that must never go through zapret
+    Allow,
+}
+
+impl Semantics {
+    pub fn parse(value: &str) -> Option<Semantics> {
+        match value {
+            "block" => Some(Semantics::Block),
+            "allow" => Some(Semantics::Allow),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Semantics::Block => "block",
+            Semantics::Allow => "allow",
+        }
+    }
+
+    /// For the reports
+    pub fn label(self) -> &'static str {
+        match self {
+            Semantics::Block => "блок-лист (домены для обхода блокировок)",
+            Semantics::Allow => "белый список (домены, которые не трогать)",
+        }
+    }
+}
+
+/// How the domains are written to the merged output, --format. Comment lines stay as they are.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub enum OutputFormat {
+    /// One domain per line, what zapret reads
+    #[default]
+    Plain,
+    Dnsmasq(Dnsmasq),
+}
+
+/// dnsmasq config lines "<directive>=/<domain>/<target>": "server=/a.com/127.0.0.1#5353"
+/// sends the domain to another resolver, "address=/a.com/0.0.0.0" answers it with that address
+#[derive(Debug, Clone, PartialEq)]
+pub struct Dnsmasq {
+    /// "server" or "address"
+    pub directive: &'static str,
+    pub target: String,
+}
+
+impl OutputFormat {
+    fn line(&self, domain: &str) -> String {
+        match self {
+            OutputFormat::Plain => domain.to_string(),
+            OutputFormat::Dnsmasq(dnsmasq) => format!("{}=/{}/{}", dnsmasq.directive, domain, dnsmasq.target),
+        }
+    }
+
+    /// The domains of a previous output written in this format
+    fn read_domains(&self, path: &Path) -> errors::Result<HashSet<String>> {
+        Ok(self.parse_domains(&file_ops::read_lines(path)?.join("\n")))
+    }
+
+    /// The domains of output text in this format
+    fn parse_domains(&self, content: &str) -> HashSet<String> {
+        match self {
+            OutputFormat::Plain => content.lines().filter_map(file_ops::parse_domain_line).collect(),
+            OutputFormat::Dnsmasq(_) => content.lines()
+                .filter_map(|line| line.split('/').nth(1))
+                .filter_map(file_ops::parse_domain_line)
+                .collect(),
+        }
+    }
+}
+
+/// "list-ultimate.txt" with index 2 becomes "list-ultimate-2.txt", chunks are numbered from 1
+pub fn chunk_path(output: &Path, index: usize) -> PathBuf {
+    let stem = output.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match output.extension() {
+        Some(extension) => format!("{}-{}.{}", stem, index, extension.to_string_lossy()),
+        None => format!("{}-{}", stem, index),
+    };
+    output.with_file_name(name)
+}
+
+/// The files a merge to output is on disk as: output itself, or with split_size its parts from 1
+/// on as far as they exist. A merge removes the parts past its count, so these are all of them.
+pub fn output_files(output: &Path, split_size: Option<usize>) -> Vec<PathBuf> {
+    match split_size {
+        Some(_) => (1..).map(|index| chunk_path(output, index)).take_while(|path| path.exists()).collect(),
+        None => vec![output.to_path_buf()],
+    }
+}
+
+/// A merge that has read everything and written nothing yet
+pub struct Prepared {
+    lines: Vec<String>,
+    domains: BTreeSet<String>,
+    stats: MergeStats,
+}
+
+impl Prepared {
+    pub fn stats(&self) -> &MergeStats {
+        &self.stats
+    }
+}
+
+/// Merging the selected lists is prepare, then write: comments, blanks and invalid entries are
+/// dropped, domains are normalized, deduplicated, stripped of excluded ones and put in
+/// alphabetical order (in source order with keep_comments or separators).
+/// stop is asked before each list with its position and name; once it returns true nothing
+/// else is read and None comes back.
+pub fn prepare(
+    lists_dir: &Path,
+    selected: &[&str],
+    options: &MergeOptions,
+    stop: &mut dyn FnMut(usize, &str) -> bool
+) -> errors::Result<Option<Prepared>> {
+    Ok(build(lists_dir, selected, options, stop)?.map(|(lines, domains, stats)| Prepared { lines, domains, stats }))
+}
+
+/// Write a prepared merge to OUTPUT_FILE (or options.output), with split_size to numbered
+/// chunks instead, see chunk_path. options should be the ones the merge was prepared with.
+pub fn write(lists_dir: &Path, prepared: Prepared, options: &MergeOptions) -> errors::Result<MergeStats> {
+    let Prepared { lines, domains, mut stats } = prepared;
+    let output = options.output.clone().unwrap_or_else(|| lists_dir.join(OUTPUT_FILE));
+    stats.output = output.clone();
+    let mut files: Vec<(PathBuf, String)> = match options.split_size {
+        Some(size) => split(&lines, size)
+            .iter()
+            .enumerate()
+            .map(|(index, chunk)| (chunk_path(&output, index + 1), render(chunk, options)))
+            .collect(),
+        None => vec![(output.clone(), render(&lines, options))],
+    };
+    // All of them before anything is written, so a failing command leaves the previous output.
+    // The numbers then describe what the command produced.
+    let mut domains = domains;
+    if let Some(command) = &options.transform_cmd {
+        for (_, content) in &mut files {
+            *content = hook::transform(command, content)
+                .map_err(|message| SelectorError::Command { command: command.clone(), message })?;
+        }
+        let content: String = files.iter().map(|(_, content)| content.as_str()).collect();
+        stats.checksum = checksum::crc32(content.as_bytes());
+        stats.size = content.len();
+        let before = domains.len();
+        domains = options.format.parse_domains(&content).into_iter().collect();
+        stats.written = domains.len();
+        if domains.is_empty() && before > 0 {
+            stats.warnings.push(format!("--transform-cmd не вывела ни одного домена из {}", before));
+        }
+    }
+    // Chunks past the new count are left from a bigger merge, they'd duplicate domains
+    let mut stale = Vec::new();
+    if options.split_size.is_some() {
+        let mut index = files.len() + 1;
+        while chunk_path(&output, index).exists() {
+            stale.push(chunk_path(&output, index));
+            index += 1;
+        }
+    }
+
+    // Read before it's overwritten
+    let previous_paths: Vec<&PathBuf> = files.iter()
+        .map(|(path, _)| path)
+        .chain(&stale)
+        .collect();
+    if let Some(changes) = OutputChanges::against(&previous_paths, &domains, options) {
+        stats.changes = Some((changes.added.len(), changes.removed.len()));
+    }
+
+    for (path, content) in &files {
+        // Neither rewritten nor backed up, the backup would be the same file
+        if options.only_changed && fs::read(path).is_ok_and(|current| current == content.as_bytes()) {
+            stats.unchanged += 1;
+            continue;
+        }
+        if options.backup && path.exists() {
+            // One generation only, the previous backup is replaced
+            let mut backup = path.clone().into_os_string();
+            backup.push(".bak");
+            let backup = PathBuf::from(backup);
+            file_ops::replace_contents(&backup, fs::read(path).reading(path)?)?;
+            stats.backup.get_or_insert(backup);
+        }
+        file_ops::replace_contents(path, content)?;
+    }
+    for path in &stale {
+        fs::remove_file(path).writing(path)?;
+    }
+
+    if options.split_size.is_some() {
+        stats.chunks = files.into_iter().map(|(path, _)| path).collect();
+    }
+    // Remembered so --doctor can tell whether the output was edited by hand afterwards. With
+    // split_size it covers the parts one after another, see output_files.
+    if let Some(state_dir) = &options.state_dir {
+        if let Err(e) = state::set(state_dir, state::OUTPUT_CHECKSUM, &checksum::format(stats.checksum)) {
+            stats.warnings.push(format!("не удалось сохранить контрольную сумму: {}", e));
+        }
+    }
+
+    Ok(stats)
+}
+
+/// What prepare and write would produce, computed without writing anything. Remote lists come from
+/// their cache only, previewing shouldn't start downloads.
+pub fn preview(lists_dir: &Path, selected: &[&str], options: &MergeOptions) -> errors::Result<(MergeStats, Option<OutputChanges>)> {
+    let options = MergeOptions { allow_network: false, ..options.clone() };
+    let Some((_, domains, mut stats)) = build(lists_dir, selected, &options, &mut |_, _| false)? else {
+        unreachable!("a merge without a stop condition was stopped");
+    };
+
+    // The files write would compare with: the output, or every part from 1 on
+    let output = options.output.clone().unwrap_or_else(|| lists_dir.join(OUTPUT_FILE));
+    let paths = output_files(&output, options.split_size);
+    let changes = OutputChanges::against(&paths.iter().collect::<Vec<_>>(), &domains, &options);
+    stats.changes = changes.as_ref().map(|changes| (changes.added.len(), changes.removed.len()));
+    Ok((stats, changes))
+}
+
+/// The domains a merge of selected would write, deduplicated and without writing anything, for
+/// comparing merges in memory. It is the set prepare builds, so the domains are the compared
+/// form: lowercased even with preserve_case, which only changes how write spells them, and
+/// before any transform_cmd. Remote lists are only read from their cache.
+pub fn build_merged_set(lists_dir: &Path, selected: &[&str], options: &MergeOptions) -> errors::Result<BTreeSet<String>> {
+    let options = MergeOptions { allow_network: false, ..options.clone() };
+    match build(lists_dir, selected, &options, &mut |_, _| false)? {
+        Some((_, domains, _)) => Ok(domains),
+        None => unreachable!("a merge without a stop condition was stopped"),
+    }
+}
+
+/// Domains a merge adds to and removes from the output on disk, both in alphabetical order
+pub struct OutputChanges {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+}
+
+impl OutputChanges {
+    /// None when none of the paths exists, an unreadable old output also goes without the comparison
+    fn against(paths: &[&PathBuf], domains: &BTreeSet<String>, options: &MergeOptions) -> Option<OutputChanges> {
+        let existing: Vec<&&PathBuf> = paths.iter().filter(|path| path.exists()).collect();
+        if existing.is_empty() {
+            return None;
+        }
+        let previous: errors::Result<Vec<HashSet<String>>> = existing.iter()
+            .map(|path| options.format.read_domains(path))
+            .collect();
+        let previous: BTreeSet<String> = previous.ok()?.into_iter().flatten().collect();
+        Some(OutputChanges {
+            added: domains.difference(&previous).cloned().collect(),
+            removed: previous.difference(domains).cloned().collect(),
+        })
+    }
+}
+
+/// Cut the output lines after every size-th domain. Comment lines go with the domains that
+/// follow them, so a separator never ends up alone at the end of a chunk. Always at least one chunk.
+fn split(lines: &[String], size: usize) -> Vec<&[String]> {
+    let mut chunks = Vec::new();
+    let (mut start, mut domains) = (0, 0);
+    for (index, line) in lines.iter().enumerate() {
+        if line.starts_with('#') {
+            continue;
+        }
+        domains += 1;
+        if domains == size {
+            chunks.push(&lines[start..=index]);
+            start = index + 1;
+            domains = 0;
+        }
+    }
+    if start < lines.len() || chunks.is_empty() {
+        chunks.push(&lines[start..]);
+    }
+    chunks
+}
+
+fn render(lines: &[String], options: &MergeOptions) -> String {
+    let newline = options.line_ending.as_str();
+    let mut content = String::new();
+    for line in lines {
+        if line.starts_with('#') {
+            content.push_str(line);
+        } else {
+            content.push_str(&options.format.line(line));
+        }
+        content.push_str(newline);
+    }
+    content
+}
+
+/// A seed for --shuffle without one, different on every run
+fn random_seed() -> u64 {
+    // The standard library seeds its hash maps randomly, that is enough for an order
+    std::collections::hash_map::RandomState::new().build_hasher().finish()
+}
+
+/// Fisher-Yates with splitmix64, the same seed gives the same order on every platform
+fn shuffle(items: &mut [String], seed: u64) {
+    let mut state = seed;
+    let mut next = || {
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    };
+    for index in (1..items.len()).rev() {
+        let other = (next() % (index as u64 + 1)) as usize;
+        items.swap(index, other);
+    }
+}
+
+/// "2026-10-14T09:05:00Z": UTC, so the text sorts like the time does
+fn utc_timestamp(time: SystemTime) -> String {
+    let seconds = time.duration_since(UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_secs());
+    let (days, in_day) = (seconds / 86_400, seconds % 86_400);
+    // Gregorian date of the day number, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year, month, day, in_day / 3_600, in_day % 3_600 / 60, in_day % 60
+    )
+}
+
+type Built = (Vec<String>, BTreeSet<String>, MergeStats);
+
+/// The output lines, the set of domains and the stats apart from changes, None when stop said so
+fn build(
+    lists_dir: &Path,
+    selected: &[&str],
+    options: &MergeOptions,
+    stop: &mut dyn FnMut(usize, &str) -> bool
+) -> errors::Result<Option<Built>> {
+    let mut stats = MergeStats { semantics: options.semantics, ..MergeStats::default() };
+
+    // With punycode every domain is compared in its ASCII form, exclusions and pins included
+    let ascii = |domain: String| if options.punycode { punycode::to_ascii(&domain) } else { Some(domain) };
+    let mut excluded = HashSet::new();
+    for file_path in &options.exclude_files {
+        excluded.extend(file_ops::read_domains(file_path)?.into_iter().filter_map(ascii));
+    }
+
+    // With preserve_case, the first spelling of every domain, the pin file's coming first
+    let mut spellings: HashMap<String, String> = HashMap::new();
+
+    // In the order of the pin file, a repeat keeps the first position. Exclude lists apply to them too.
+    let mut pinned = Vec::new();
+    if let Some(path) = &options.pin_file {
+        let mut seen = HashSet::new();
+        for line in file_ops::read_lines(path)? {
+            if let Some(domain) = file_ops::parse_domain_line(&line).and_then(ascii) {
+                if !excluded.contains(&domain) && seen.insert(domain.clone()) {
+                    if options.preserve_case {
+                        spellings.insert(domain.clone(), file_ops::written_domain(&line, &domain));
+                    }
+                    pinned.push(domain);
+                }
+            }
+        }
+    }
+
+    let mut domains = BTreeSet::new();
+    // Counted once however many lists have them, like the denylist
+    let mut excluded_found = HashSet::new();
+    // With punycode, whether each domain was first seen in its Unicode spelling: a repeat in the
+    // other spelling is a collapsed IDN rather than a plain duplicate
+    let mut unicode_first: HashMap<String, bool> = HashMap::new();
+    // Output in source order, only collected with keep_comments or separators
+    let source_order = options.keep_comments || options.separators;
+    let mut annotated = Vec::new();
+    for (index, name) in selected.iter().enumerate() {
+        if stop(index, name) {
+            return Ok(None);
+        }
+        let file_path = if remote::is_remote(name) {
+            let (cached, warning) = remote::resolve(lists_dir, name, options.allow_network);
+            stats.warnings.extend(warning);
+            match cached {
+                Some(path) => path,
+                None => continue,
+            }
+        } else {
+            lists_dir.join(name)
+        };
+        if !file_path.exists() {
+            continue;
+        }
+        // One list in a legacy encoding shouldn't stop the merge of all the others
+        let lines = match file_ops::read_lines(&file_path) {
+            Ok(lines) => lines,
+            Err(SelectorError::Encoding { .. }) => {
+                stats.warnings.push(format!("{} пропущен: не в UTF-8 (для Windows-1251 есть --encoding cp1251)", name));
+                continue;
+            }
+            Err(e) => return Err(e),
+        };
+
+        let mut file_stats = FileStats { name: name.to_string(), domains: 0, duplicates: 0, added: 0 };
+        if options.separators {
+            annotated.push(format!("# --- from {} ---", name));
+        }
+        for line in lines {
+            // A disabled entry isn't a note worth keeping
+            if options.keep_comments && file_ops::disabled_entry(&line).is_none() {
+                if let Some(comment) = line.trim().strip_prefix('#') {
+                    annotated.push(format!("# {}: {}", name, comment.trim()));
+                    continue;
+                }
+            }
+
+            let Some(entry) = file_ops::entry_of(&line) else {
+                continue;
+            };
+
+            let parsed = if options.clean {
+                file_ops::clean_host(&entry)
+            } else {
+                file_ops::parse_domain_line(&line)
+            };
+            let Some(domain) = parsed else {
+                stats.invalid.push(entry);
+                continue;
+            };
+            if options.clean && domain != entry {
+                stats.cleaned += 1;
+            }
+            let unicode = !domain.is_ascii();
+            let Some(domain) = ascii(domain) else {
+                stats.invalid.push(entry);
+                continue;
+            };
+
+            let spelling = options.preserve_case.then(|| file_ops::written_domain(&line, &domain));
+
+            file_stats.domains += 1;
+            if excluded.contains(&domain) {
+                excluded_found.insert(domain);
+            } else if !domains.insert(domain.clone()) {
+                if options.punycode && unicode_first.get(&domain) != Some(&unicode) {
+                    stats.idn_collapsed += 1;
+                } else if spelling.is_some() && spellings.get(&domain) != spelling.as_ref() {
+                    stats.case_collapsed += 1;
+                } else {
+                    stats.duplicates += 1;
+                }
+                file_stats.duplicates += 1;
+            } else {
+                if options.punycode {
+                    unicode_first.insert(domain.clone(), unicode);
+                }
+                if let Some(spelling) = spelling {
+                    spellings.entry(domain.clone()).or_insert(spelling);
+                }
+                file_stats.added += 1;
+                if source_order {
+                    annotated.push(domain);
+                }
+            }
+        }
+        stats.per_file.push(file_stats);
+    }
+    stats.excluded = excluded_found.len();
+
+    // Last, over whatever the lists, the excludes and the pin file left
+    if let Some(path) = &options.denylist {
+        let denylist: HashSet<String> = file_ops::read_domains(path)?.into_iter().filter_map(ascii).collect();
+        let denied: HashSet<&String> = domains.iter().chain(&pinned).filter(|domain| denylist.contains(*domain)).collect();
+        stats.denied = denied.len();
+        domains.retain(|domain| !denylist.contains(domain));
+        pinned.retain(|domain| !denylist.contains(domain));
+        annotated.retain(|line| !denylist.contains(line));
+    }
+
+    let rest = if source_order {
+        if options.shuffle.is_some() {
+            stats.warnings.push(String::from("--shuffle не применён: с --keep-comments и --separators порядок исходный"));
+        }
+        annotated
+    } else if let Some(seed) = options.shuffle {
+        let mut shuffled: Vec<String> = domains.iter().cloned().collect();
+        shuffle(&mut shuffled, seed);
+        stats.shuffle_seed = Some(seed);
+        shuffled
+    } else {
+        // The set is alphabetical already and the sorts are stable, which keeps it inside
+        // a TLD or a length
+        let mut sorted: Vec<String> = domains.iter().cloned().collect();
+        match options.sort {
+            SortOrder::Alphabetical => {}
+            SortOrder::Tld => sorted.sort_by(|a, b| a.rsplit('.').next().cmp(&b.rsplit('.').next())),
+            SortOrder::Length => sorted.sort_by_key(|domain| domain.chars().count()),
+        }
+        sorted
+    };
+
+    let mut lines = Vec::new();
+    // A bare output stays bare for zapret, the label only goes where comments already are
+    if let (true, Some(semantics)) = (source_order, options.semantics) {
+        lines.push(format!("# semantics: {}list", semantics.as_str()));
+    }
+    if options.separators && !pinned.is_empty() {
+        lines.push(String::from("# --- pinned ---"));
+    }
+    // Comment lines start with '#' and never equal a domain, nor have a spelling
+    let spell = |line: String| spellings.get(&line).cloned().unwrap_or(line);
+    lines.extend(pinned.iter().cloned().map(spell));
+    let pinned_set: HashSet<&String> = pinned.iter().collect();
+    lines.extend(rest.into_iter().filter(|line| !pinned_set.contains(line)).map(spell));
+    stats.pinned = pinned.len();
+    domains.extend(pinned);
+    if options.header && (source_order || matches!(options.format, OutputFormat::Dnsmasq(_))) {
+        let mut header = Vec::new();
+        if !options.only_changed {
+            header.push(format!("# generated: {}", utc_timestamp(SystemTime::now())));
+        }
+        header.push(format!("# by: {} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION")));
+        header.push(format!("# lists: {}", stats.per_file.len()));
+        header.push(format!("# domains: {}", domains.len()));
+        // After the semantics line, which stays the first one
+        let at = usize::from(source_order && options.semantics.is_some());
+        lines.splice(at..at, header);
+    }
+
+    // Over the whole output, which is also the chunks one after another
+    let content = render(&lines, options);
+    stats.checksum = checksum::crc32(content.as_bytes());
+    stats.size = content.len();
+    stats.written = domains.len();
+    Ok(Some((lines, domains, stats)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::scratch;
+
+    fn merge(lists_dir: &Path, selected: &[&str], options: &MergeOptions) -> Prepared {
+        prepare(lists_dir, selected, options, &mut |_, _| false).unwrap().unwrap()
+    }
+
+    /// Merges and writes to options.output, which the tests point into the scratch folder
+    fn merge_to_disk(lists_dir: &Path, selected: &[&str], options: &MergeOptions) -> MergeStats {
+        write(lists_dir, merge(lists_dir, selected, options), options).unwrap()
+    }
+
+    #[test]
+    fn an_excluded_domain_counts_once() {
+        let dir = scratch::dir("excluded-once");
+        fs::write(dir.join("list-a.txt"), "a.com\nskip.com\nSKIP.com\n").unwrap();
+        fs::write(dir.join("list-b.txt"), " skip.com \nb.com\n").unwrap();
+        fs::write(dir.join("exclude-x.txt"), "skip.com\nother.com\n").unwrap();
+        let options = MergeOptions { exclude_files: vec![dir.join("exclude-x.txt")], ..MergeOptions::default() };
+
+        let stats = merge(&dir, &["list-a.txt", "list-b.txt"], &options).stats;
+        assert_eq!(stats.excluded, 1);
+        assert_eq!(stats.written, 2);
+    }
+
+    #[test]
+    fn the_reported_size_is_what_was_written() {
+        let dir = scratch::dir("size-line-ending");
+        fs::write(dir.join("list-a.txt"), "b.com\r\na.com\r\n# note\r\n").unwrap();
+        fs::write(dir.join("list-b.txt"), "c.com\nа.рф\n").unwrap();
+        let output = dir.join("out.txt");
+
+        let mut sizes = Vec::new();
+        for line_ending in [LineEnding::Lf, LineEnding::CrLf] {
+            let options = MergeOptions { line_ending, output: Some(output.clone()), ..MergeOptions::default() };
+            let stats = merge_to_disk(&dir, &["list-a.txt", "list-b.txt"], &options);
+            let written = fs::read(&output).unwrap();
+            assert_eq!(stats.size, written.len());
+            assert_eq!(stats.checksum, checksum::crc32(&written));
+            sizes.push(stats.size);
+        }
+        // One more byte for each of the four lines
+        assert_eq!(sizes[1], sizes[0] + 4);
+    }
+
+    fn set(domains: &[&str]) -> BTreeSet<String> {
+        domains.iter().map(|domain| domain.to_string()).collect()
+    }
+
+    /// The lists of fixture, in merge order
+    const FIXTURE_LISTS: [&str; 2] = ["list-video.txt", "list-social.txt"];
+
+    /// Lists with the usual noise: comments, notes, disabled entries, case, a trailing dot, CRLF
+    fn fixture(test: &str) -> PathBuf {
+        let dir = scratch::dir(test);
+        fs::write(dir.join("list-video.txt"), "# video\r\nYouTube.com\r\nytimg.com.\r\n\r\ngooglevideo.com ## streams\r\n").unwrap();
+        fs::write(dir.join("list-social.txt"), "*.vk.com\nyoutube.com\n#!facebook.com\nt.me # telegram\nnot a domain\n").unwrap();
+        fs::write(dir.join("exclude-local.txt"), "t.me\n").unwrap();
+        fs::write(dir.join("pin.txt"), "first.example\n").unwrap();
+        dir
+    }
+
+    #[test]
+    fn the_merged_set_is_normalized_and_deduplicated() {
+        let dir = fixture("merged-set");
+        let options = MergeOptions::default();
+        let domains = build_merged_set(&dir, &FIXTURE_LISTS, &options).unwrap();
+        assert_eq!(domains, set(&["googlevideo.com", "t.me", "vk.com", "ytimg.com", "youtube.com"]));
+
+        assert_eq!(build_merged_set(&dir, &["list-social.txt"], &options).unwrap(), set(&["t.me", "vk.com", "youtube.com"]));
+        assert_eq!(build_merged_set(&dir, &[], &options).unwrap(), BTreeSet::new());
+        // A list that isn't there is skipped like in the file merge
+        assert_eq!(build_merged_set(&dir, &["list-gone.txt"], &options).unwrap(), BTreeSet::new());
+    }
+
+    #[test]
+    fn the_merged_set_follows_the_merge_options() {
+        let dir = fixture("merged-set-options");
+        fs::write(dir.join(DENYLIST_FILE), "ytimg.com\n").unwrap();
+        let options = MergeOptions {
+            exclude_files: vec![dir.join("exclude-local.txt")],
+            denylist: Some(dir.join(DENYLIST_FILE)),
+            pin_file: Some(dir.join("pin.txt")),
+            ..MergeOptions::default()
+        };
+        let domains = build_merged_set(&dir, &FIXTURE_LISTS, &options).unwrap();
+        assert_eq!(domains, set(&["first.example", "googlevideo.com", "vk.com", "youtube.com"]));
+    }
+
+    #[test]
+    fn the_written_output_is_the_merged_set() {
+        let dir = fixture("merged-set-written");
+        let options = MergeOptions { output: Some(dir.join("out.txt")), ..MergeOptions::default() };
+        let domains = build_merged_set(&dir, &FIXTURE_LISTS, &options).unwrap();
+
+        let stats = merge_to_disk(&dir, &FIXTURE_LISTS, &options);
+        let written = fs::read_to_string(dir.join("out.txt")).unwrap();
+        assert_eq!(written.lines().collect::<Vec<_>>(), domains.iter().collect::<Vec<_>>());
+        assert_eq!(stats.written, domains.len());
+    }
+
+    #[test]
+    fn an_unchanged_merge_leaves_the_files_alone() {
+        let dir = fixture("merge-unchanged");
+        let output = dir.join("out.txt");
+        for split_size in [None, Some(2)] {
+            let options = MergeOptions {
+                output: Some(output.clone()),
+                split_size,
+                only_changed: true,
+                backup: true,
+                header: true,
+                keep_comments: true,
+                ..MergeOptions::default()
+            };
+            let first = merge_to_disk(&dir, &FIXTURE_LISTS, &options);
+            let files = if split_size.is_some() { first.chunks.clone() } else { vec![output.clone()] };
+            let before: Vec<(Vec<u8>, SystemTime)> = files.iter()
+                .map(|path| (fs::read(path).unwrap(), scratch::backdate(path)))
+                .collect();
+
+            let second = merge_to_disk(&dir, &FIXTURE_LISTS, &options);
+            assert_eq!(second.unchanged, files.len());
+            assert_eq!(second.backup, None);
+            for (path, (bytes, time)) in files.iter().zip(&before) {
+                assert_eq!(&fs::read(path).unwrap(), bytes);
+                assert_eq!(&scratch::modified(path), time);
+            }
+        }
+    }
+
+    #[test]
+    fn timestamps_are_utc_dates() {
+        let at = |seconds: u64| utc_timestamp(UNIX_EPOCH + Duration::from_secs(seconds));
+        assert_eq!(at(0), "1970-01-01T00:00:00Z");
+        assert_eq!(at(951_782_400), "2000-02-29T00:00:00Z");
+        assert_eq!(at(1_760_000_000), "2025-10-09T08:53:20Z");
+        assert_eq!(at(4_102_444_799), "2099-12-31T23:59:59Z");
+    }
+
+    #[test]
+    fn the_header_comes_first_and_only_where_comments_are() {
+        let dir = fixture("header");
+        let options = MergeOptions { header: true, separators: true, semantics: Some(Semantics::Block), ..MergeOptions::default() };
+        let output = merge(&dir, &FIXTURE_LISTS, &options).lines;
+        assert_eq!(output[0], "# semantics: blocklist");
+        assert!(output[1].starts_with("# generated: "));
+        assert_eq!(output[2], format!("# by: {} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION")));
+        assert_eq!(output[3..5], ["# lists: 2", "# domains: 5"]);
+
+        // --git-friendly leaves out the time, which differs on every merge
+        let output = merge(&dir, &FIXTURE_LISTS, &MergeOptions { only_changed: true, ..options }).lines;
+        assert!(output[1].starts_with("# by: "));
+
+        // A bare output stays bare
+        let output = merge(&dir, &FIXTURE_LISTS, &MergeOptions { header: true, ..MergeOptions::default() }).lines;
+        assert!(output.iter().all(|line| !line.starts_with('#')));
+    }
+
+    #[test]
+    fn the_same_seed_gives_the_same_order() {
+        let dir = fixture("shuffle");
+        let options = MergeOptions { shuffle: Some(42), pin_file: Some(dir.join("pin.txt")), ..MergeOptions::default() };
+        let first = merge(&dir, &FIXTURE_LISTS, &options);
+        assert_eq!(first.lines, merge(&dir, &FIXTURE_LISTS, &options).lines);
+        assert_eq!(first.stats.shuffle_seed, Some(42));
+        // Pinned domains stay on top, the rest is the same set in another order
+        assert_eq!(first.lines[0], "first.example");
+        let mut lines = first.lines;
+        lines.sort();
+        let mut expected = merge(&dir, &FIXTURE_LISTS, &MergeOptions { shuffle: None, ..options }).lines;
+        expected.sort();
+        assert_eq!(lines, expected);
+
+        let mut items: Vec<String> = (0..50).map(|n| n.to_string()).collect();
+        let original = items.clone();
+        shuffle(&mut items, 7);
+        assert_ne!(items, original);
+    }
+
+    #[test]
+    fn preserve_case_keeps_the_first_spelling() {
+        let dir = fixture("preserve-case");
+        let output = dir.join("out.txt");
+        let written = |preserve_case: bool| {
+            let options = MergeOptions { preserve_case, output: Some(output.clone()), ..MergeOptions::default() };
+            let stats = merge_to_disk(&dir, &FIXTURE_LISTS, &options);
+            let lines: Vec<String> = fs::read_to_string(&output).unwrap().lines().map(String::from).collect();
+            (stats, lines)
+        };
+
+        let (stats, lines) = written(true);
+        assert!(lines.contains(&String::from("YouTube.com")));
+        assert!(!lines.contains(&String::from("youtube.com")));
+        assert_eq!((stats.case_collapsed, stats.duplicates), (1, 0));
+
+        let (stats, lines) = written(false);
+        assert!(lines.contains(&String::from("youtube.com")));
+        assert!(!lines.contains(&String::from("YouTube.com")));
+        assert_eq!((stats.case_collapsed, stats.duplicates), (0, 1));
+    }
+
+    #[test]
+    fn the_recorded_checksum_covers_all_the_parts() {
+        let dir = fixture("checksum-parts");
+        let output = dir.join("out.txt");
+        for split_size in [None, Some(2)] {
+            let options = MergeOptions {
+                output: Some(output.clone()),
+                split_size,
+                state_dir: Some(dir.clone()),
+                ..MergeOptions::default()
+            };
+            merge_to_disk(&dir, &FIXTURE_LISTS, &options);
+            let written: Vec<u8> = output_files(&output, split_size).iter().flat_map(|path| fs::read(path).unwrap()).collect();
+            let stored = state::get(&dir, state::OUTPUT_CHECKSUM);
+            assert_eq!(stored, Some(checksum::format(checksum::crc32(&written))));
+        }
+        assert_eq!(output_files(&output, Some(2)).len(), 3);
+    }
+}
@@ -0,0 +1,131 @@
+//! --punycode: internationalized domains ("пример.рф") turned into their ASCII form
+//! ("xn--e1afmkfd.xn--p1ai"), so a list with both spellings of a host dedups them.
+//! The label encoding is Punycode (RFC 3492). The other IDNA mapping steps (Unicode
+//! normalization, the UTS #46 tables) aren't done: the labels are only lowercased, which covers
+//! the domains lists actually contain.
+
+const BASE: u32 = 36;
+const T_MIN: u32 = 1;
+const T_MAX: u32 = 26;
+const SKEW: u32 = 38;
+const DAMP: u32 = 700;
+const INITIAL_BIAS: u32 = 72;
+const INITIAL_N: u32 = 128;
+
+/// Prefix of an encoded label
+const ACE_PREFIX: &str = "xn--";
+
+/// The ASCII form of a domain, labels that are ASCII already are kept as they are.
+/// None when a label can't be encoded.
+pub fn to_ascii(domain: &str) -> Option<String> {
+    if domain.is_ascii() {
+        return Some(domain.to_string());
+    }
+    let labels: Option<Vec<String>> = domain.split('.')
+        .map(|label| {
+            if label.is_ascii() {
+                Some(label.to_string())
+            } else {
+                encode(&label.to_lowercase()).map(|encoded| format!("{}{}", ACE_PREFIX, encoded))
+            }
+        })
+        .collect();
+    Some(labels?.join("."))
+}
+
+/// RFC 3492 section 6.3, None on overflow
+fn encode(input: &str) -> Option<String> {
+    let code_points: Vec<u32> = input.chars().map(u32::from).collect();
+    let mut output: String = input.chars().filter(char::is_ascii).collect();
+    let basic = output.len() as u32;
+    if basic > 0 {
+        output.push('-');
+    }
+
+    let (mut n, mut delta, mut bias) = (INITIAL_N, 0u32, INITIAL_BIAS);
+    let mut handled = basic;
+    while (handled as usize) < code_points.len() {
+        let next = code_points.iter().copied().filter(|&c| c >= n).min()?;
+        delta = delta.checked_add((next - n).checked_mul(handled + 1)?)?;
+        n = next;
+        for &c in &code_points {
+            if c < n {
+                delta = delta.checked_add(1)?;
+            }
+            if c == n {
+                let mut q = delta;
+                let mut k = BASE;
+                loop {
+                    let t = if k <= bias {
+                        T_MIN
+                    } else if k >= bias + T_MAX {
+                        T_MAX
+                    } else {
+                        k - bias
+                    };
+                    if q < t {
+                        break;
+                    }
+                    output.push(digit(t + (q - t) % (BASE - t)));
+                    q = (q - t) / (BASE - t);
+                    k += BASE;
+                }
+                output.push(digit(q));
+                bias = adapt(delta, handled + 1, handled == basic);
+                delta = 0;
+                handled += 1;
+            }
+        }
+        delta = delta.checked_add(1)?;
+        n += 1;
+    }
+    Some(output)
+}
+
+fn adapt(delta: u32, points: u32, first: bool) -> u32 {
+    let mut delta = if first { delta / DAMP } else { delta / 2 };
+    delta += delta / points;
+    let mut k = 0;
+    while delta > ((BASE - T_MIN) * T_MAX) / 2 {
+        delta /= BASE - T_MIN;
+        k += BASE;
+    }
+    k + (BASE - T_MIN + 1) * delta / (delta + SKEW)
+}
+
+fn digit(value: u32) -> char {
+    match value {
+        0..=25 => char::from(b'a' + value as u8),
+        _ => char::from(b'0' + (value - 26) as u8),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ascii(domain: &str) -> String {
+        to_ascii(domain).unwrap()
+    }
+
+    #[test]
+    fn known_vectors() {
+        assert_eq!(ascii("пример.рф"), "xn--e1afmkfd.xn--p1ai");
+        assert_eq!(ascii("münchen.de"), "xn--mnchen-3ya.de");
+        assert_eq!(ascii("例え.テスト"), "xn--r8jz45g.xn--zckzah");
+    }
+
+    #[test]
+    fn ascii_domains_pass_through() {
+        assert_eq!(ascii("example.com"), "example.com");
+        assert_eq!(ascii("xn--e1afmkfd.xn--p1ai"), "xn--e1afmkfd.xn--p1ai");
+    }
+
+    #[test]
+    fn labels_are_encoded_one_by_one() {
+        assert_eq!(ascii("bücher.example.com"), "xn--bcher-kva.example.com");
+        // ASCII inside a non-ASCII label goes first, before the delimiter
+        assert_eq!(ascii("mail.google-пример.рф"), "mail.xn--google--ehg0a4b5amd.xn--p1ai");
+        assert_eq!(ascii("ПРИМЕР.РФ"), "xn--e1afmkfd.xn--p1ai");
+    }
+}
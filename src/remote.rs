@@ -0,0 +1,87 @@
+//! Remote lists: a `<prefix>*.url` file holds the URL of a domain list. With --allow-network it
+//! is downloaded at merge time and cached, offline runs and failed downloads reuse the cache.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::file_ops;
+
+pub const REMOTE_SUFFIX: &str = ".url";
+const CACHE_DIR: &str = ".cache";
+const FETCH_TIMEOUT_SECS: &str = "15";
+
+pub fn is_remote(name: &str) -> bool {
+    name.ends_with(REMOTE_SUFFIX)
+}
+
+/// Where the downloaded content of a remote list is kept
+pub fn cache_path(lists_dir: &Path, name: &str) -> PathBuf {
+    let stem = name.strip_suffix(REMOTE_SUFFIX).unwrap_or(name);
+    lists_dir.join(CACHE_DIR).join(format!("{}.txt", stem))
+}
+
+/// File holding the domains of a list: the list itself, or the cache for a remote one
+pub fn content_path(lists_dir: &Path, name: &str) -> PathBuf {
+    if is_remote(name) {
+        cache_path(lists_dir, name)
+    } else {
+        lists_dir.join(name)
+    }
+}
+
+/// First non-comment line of the .url file, only http(s) is accepted
+fn read_url(url_file: &Path) -> Result<String, String> {
+    let lines = file_ops::read_lines(url_file).map_err(|e| e.to_string())?;
+    let url = lines.iter()
+        .map(|line| line.trim())
+        .find(|line| !line.is_empty() && !line.starts_with('#'))
+        .ok_or("файл не содержит адреса")?;
+
+    if url.starts_with("https://") || url.starts_with("http://") {
+        Ok(url.to_string())
+    } else {
+        Err(format!("поддерживаются только адреса http(s): {}", url))
+    }
+}
+
+/// Download with the system curl (shipped with Windows 10+, macOS and most Linux setups)
+fn fetch(url: &str) -> Result<Vec<u8>, String> {
+    let output = Command::new("curl")
+        .args(["--fail", "--silent", "--show-error", "--location", "--max-time", FETCH_TIMEOUT_SECS, url])
+        .output()
+        .map_err(|e| format!("не удалось запустить curl: {}", e))?;
+
+    if output.status.success() {
+        Ok(output.stdout)
+    } else {
+        Err(String::from_utf8_lossy(&output.stderr).trim().to_string())
+    }
+}
+
+/// Path to read the domains of a remote list from, plus a warning when the fresh copy couldn't
+/// be used. None means there is nothing to read at all; the merge skips the list then.
+pub fn resolve(lists_dir: &Path, name: &str, allow_network: bool) -> (Option<PathBuf>, Option<String>) {
+    let cache = cache_path(lists_dir, name);
+    let cached = cache.is_file().then(|| cache.clone());
+
+    if !allow_network {
+        let warning = cached.is_none()
+            .then(|| format!("{}: нет кэша, запустите с --allow-network для загрузки", name));
+        return (cached, warning);
+    }
+
+    let downloaded = read_url(&lists_dir.join(name))
+        .and_then(|url| fetch(&url))
+        .and_then(|content| {
+            fs::create_dir_all(cache.parent().unwrap_or(lists_dir))
+                .and_then(|_| fs::write(&cache, content))
+                .map_err(|e| format!("не удалось сохранить кэш: {}", e))
+        });
+
+    match downloaded {
+        Ok(()) => (Some(cache), None),
+        Err(e) if cached.is_some() => (cached, Some(format!("{}: {}, используется кэш", name, e))),
+        Err(e) => (None, Some(format!("{}: {}, список пропущен", name, e))),
+    }
+}
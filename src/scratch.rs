@@ -0,0 +1,26 @@
+//! Throwaway folders for the tests that need files
+
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// An empty folder under the system temp dir, unique to the test name and the test run
+pub fn dir(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("{}-{}-{}", env!("CARGO_PKG_NAME"), name, process::id()));
+    let _ = fs::remove_dir_all(&path);
+    fs::create_dir_all(&path).expect("create a scratch folder");
+    path
+}
+
+/// Set the modification time of a file far back, so a rewrite of it can't go unnoticed
+/// however coarse the file system's timestamps are
+pub fn backdate(path: &Path) -> SystemTime {
+    let time = UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+    File::options().write(true).open(path).and_then(|file| file.set_modified(time)).expect("backdate a file");
+    time
+}
+
+pub fn modified(path: &Path) -> SystemTime {
+    fs::metadata(path).and_then(|metadata| metadata.modified()).expect("read a modification time")
+}
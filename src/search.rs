@@ -0,0 +1,88 @@
+//! Content search: which lists contain a domain matching the query.
+//! Files are read on a background thread the first time they are searched and kept in memory.
+
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+
+use crate::{file_ops, remote};
+
+/// A filter starting with this searches inside the lists instead of their names
+pub const CONTENT_PREFIX: char = '&';
+
+pub struct ContentIndex {
+    /// Domains of every loaded list, by file name. Unreadable files are cached as empty.
+    domains: HashMap<String, Vec<String>>,
+    /// Names handed to a loader thread and not received yet
+    loading: HashSet<String>,
+    sender: Sender<(String, Vec<String>)>,
+    receiver: Receiver<(String, Vec<String>)>,
+}
+
+impl ContentIndex {
+    pub fn new() -> ContentIndex {
+        let (sender, receiver) = mpsc::channel();
+        ContentIndex {
+            domains: HashMap::new(),
+            loading: HashSet::new(),
+            sender,
+            receiver,
+        }
+    }
+
+    /// Start loading the given lists unless they are cached or already on their way
+    pub fn request(&mut self, lists_dir: &Path, names: &[String]) {
+        let missing: Vec<String> = names.iter()
+            .filter(|name| !self.domains.contains_key(*name) && !self.loading.contains(*name))
+            .cloned()
+            .collect();
+        if missing.is_empty() {
+            return;
+        }
+
+        self.loading.extend(missing.iter().cloned());
+        let lists_dir = lists_dir.to_path_buf();
+        let sender = self.sender.clone();
+        thread::spawn(move || {
+            for name in missing {
+                let domains = file_ops::read_lines(&remote::content_path(&lists_dir, &name))
+                    .map(|lines| lines.iter().filter_map(|line| file_ops::parse_domain_line(line)).collect())
+                    .unwrap_or_default();
+                if sender.send((name, domains)).is_err() {
+                    return;
+                }
+            }
+        });
+    }
+
+    /// Take in whatever the loaders finished. Returns true if anything new arrived.
+    pub fn poll(&mut self) -> bool {
+        let mut received = false;
+        while let Ok((name, domains)) = self.receiver.try_recv() {
+            // A file invalidated while it was loading is requested again later
+            if self.loading.remove(&name) {
+                self.domains.insert(name, domains);
+                received = true;
+            }
+        }
+        received
+    }
+
+    pub fn is_loading(&self) -> bool {
+        !self.loading.is_empty()
+    }
+
+    /// Number of domains of the list containing query, None while the list isn't loaded yet.
+    /// query has to be lowercase already.
+    pub fn count(&self, name: &str, query: &str) -> Option<usize> {
+        let domains = self.domains.get(name)?;
+        Some(domains.iter().filter(|domain| domain.contains(query)).count())
+    }
+
+    /// Forget a list that changed on disk
+    pub fn invalidate(&mut self, name: &str) {
+        self.domains.remove(name);
+        self.loading.remove(name);
+    }
+}
@@ -0,0 +1,141 @@
+//! The saved selection: selected.txt, one list name per line, or with --toml a
+//! [selection] table in selector.toml for users who keep their setup in TOML
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::cli::Args;
+use crate::errors::{self, Context, SelectorError};
+use crate::file_ops;
+
+pub const SELECTED_FILE: &str = "selected.txt";
+pub const TOML_FILE: &str = "selector.toml";
+
+/// Contents of the selection file
+pub struct Selection {
+    /// Names in the saved order, which is also the merge order
+    pub names: Vec<String>,
+    /// Blank and repeated entries that were skipped, the next save writes the file without them
+    pub dropped: usize,
+}
+
+/// Where the selection is stored for these options
+pub fn path(args: &Args) -> PathBuf {
+    args.state_dir().join(if args.toml { TOML_FILE } else { SELECTED_FILE })
+}
+
+/// The selection file has to be a file if it exists at all
+pub fn check(path: &Path) -> errors::Result<()> {
+    if path.is_dir() {
+        return Err(SelectorError::IsDirectory { path: path.to_path_buf() });
+    }
+    Ok(())
+}
+
+fn is_toml(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| extension == "toml")
+}
+
+/// Read the names, empty when there's no saved selection yet. Names are trimmed,
+/// blanks and repeats dropped, order kept.
+pub fn read(path: &Path) -> errors::Result<Selection> {
+    check(path)?;
+    let mut selection = Selection { names: Vec::new(), dropped: 0 };
+    if !path.exists() {
+        return Ok(selection);
+    }
+
+    let raw = if is_toml(path) { read_toml(path)? } else { file_ops::read_lines(path)? };
+    for line in raw {
+        let name = line.trim();
+        if name.is_empty() || selection.names.iter().any(|known| known == name) {
+            selection.dropped += 1;
+        } else {
+            selection.names.push(name.to_string());
+        }
+    }
+
+    Ok(selection)
+}
+
+/// `files` of the [selection] table
+fn read_toml(path: &Path) -> errors::Result<Vec<String>> {
+    let parse_error = |message: String| SelectorError::Parse { path: path.to_path_buf(), message };
+
+    let table: toml::Table = fs::read_to_string(path).reading(path)?
+        .parse()
+        .map_err(|e: toml::de::Error| parse_error(e.message().to_string()))?;
+    let Some(files) = table.get("selection").and_then(|selection| selection.get("files")) else {
+        return Ok(Vec::new());
+    };
+
+    files.as_array()
+        .ok_or_else(|| parse_error(String::from("selection.files должен быть массивом строк")))?
+        .iter()
+        .map(|file| file.as_str().map(String::from))
+        .collect::<Option<Vec<String>>>()
+        .ok_or_else(|| parse_error(String::from("selection.files должен быть массивом строк")))
+}
+
+/// Store the names in the given order. In selector.toml everything outside selection.files is
+/// kept. With only_changed (--git-friendly) a file that has the selection already is left alone.
+pub fn write(path: &Path, names: &[&str], only_changed: bool) -> errors::Result<()> {
+    check(path)?;
+    let replace = |content: String| if only_changed {
+        file_ops::replace_if_changed(path, content).map(|_| ())
+    } else {
+        file_ops::replace_contents(path, content)
+    };
+    if !is_toml(path) {
+        let content: String = names.iter().map(|name| format!("{}\n", name)).collect();
+        return replace(content);
+    }
+
+    let mut table: toml::Table = match fs::read_to_string(path) {
+        Ok(content) => content.parse().map_err(|e: toml::de::Error| SelectorError::Parse {
+            path: path.to_path_buf(),
+            message: e.message().to_string(),
+        })?,
+        Err(_) => toml::Table::new(),
+    };
+
+    let files = names.iter().map(|name| toml::Value::String(name.to_string())).collect();
+    let selection = table.entry("selection")
+        .or_insert_with(|| toml::Value::Table(toml::Table::new()));
+    if let Some(selection) = selection.as_table_mut() {
+        selection.insert(String::from("files"), toml::Value::Array(files));
+    } else {
+        *selection = toml::Value::Table(toml::Table::from_iter([
+            (String::from("files"), toml::Value::Array(files)),
+        ]));
+    }
+
+    replace(table.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scratch;
+
+    #[test]
+    fn an_unchanged_selection_is_not_rewritten() {
+        let dir = scratch::dir("selection-unchanged");
+        let names = ["list-b.txt", "list-a.txt"];
+        for file in [SELECTED_FILE, TOML_FILE] {
+            let path = dir.join(file);
+            write(&path, &names, true).unwrap();
+            let bytes = fs::read(&path).unwrap();
+            let time = scratch::backdate(&path);
+
+            write(&path, &names, true).unwrap();
+            assert_eq!(fs::read(&path).unwrap(), bytes);
+            assert_eq!(scratch::modified(&path), time);
+            assert_eq!(read(&path).unwrap().names, names);
+
+            // A different order is a change
+            write(&path, &["list-a.txt", "list-b.txt"], true).unwrap();
+            assert_ne!(scratch::modified(&path), time);
+        }
+    }
+}
@@ -0,0 +1,58 @@
+//! .selector_state: small "key=value" facts the tool remembers between runs. It lives in the
+//! state folder, the lists folder unless --state-dir says otherwise.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::file_ops;
+
+pub const STATE_FILE: &str = ".selector_state";
+
+/// CRC-32 of the merged output (list-ultimate.txt, the -o file or the --split-size parts
+/// one after another) as it was last written by a merge
+pub const OUTPUT_CHECKSUM: &str = "output_crc32";
+
+/// Fingerprint of everything the last --apply merged, for --if-changed
+pub const SOURCES_CHECKSUM: &str = "sources_crc32";
+
+/// Recently toggled lists for the "Недавние" section, newest first, separated by '/'
+pub const RECENT: &str = "recent";
+
+/// Every list that was part of a saved selection at some point, separated by '/', for the audit view
+pub const EVER_SAVED: &str = "ever_saved";
+
+/// How many saved selections contained each list, "name:count" separated by '/'
+pub const SAVE_COUNTS: &str = "save_counts";
+
+/// Step of an unfinished first-start wizard, or "done", see wizard.rs
+pub const WIZARD: &str = "wizard";
+
+fn path(state_dir: &Path) -> PathBuf {
+    state_dir.join(STATE_FILE)
+}
+
+/// All stored values, empty if there is no state file yet
+pub fn read(state_dir: &Path) -> BTreeMap<String, String> {
+    let content = fs::read_to_string(path(state_dir)).unwrap_or_default();
+    content.lines()
+        .filter_map(|line| line.split_once('='))
+        .map(|(key, value)| (key.trim().to_string(), value.trim().to_string()))
+        .collect()
+}
+
+pub fn get(state_dir: &Path, key: &str) -> Option<String> {
+    read(state_dir).remove(key)
+}
+
+/// Store one value, keeping the others
+pub fn set(state_dir: &Path, key: &str, value: &str) -> io::Result<()> {
+    let mut values = read(state_dir);
+    values.insert(key.to_string(), value.to_string());
+
+    let content: String = values.iter()
+        .map(|(key, value)| format!("{}={}\n", key, value))
+        .collect();
+    file_ops::replace_contents(&path(state_dir), content).map_err(io::Error::from)
+}
@@ -0,0 +1,102 @@
+//! --stats-json: the numbers of a finished merge as a JSON file for dashboards and monitoring.
+//!
+//! The schema is stable, fields are only ever added:
+//!
+//! ```text
+//! {
+//!   "timestamp": 1760000000,         // seconds since the Unix epoch, when the file was written
+//!   "output": "lists/list-ultimate.txt",
+//!   "chunks": ["..."],               // files written with --split-size, otherwise empty
+//!   "unique": 14200,                 // domains written
+//!   "duplicates": 310,               // dropped because an earlier list had them
+//!   "excluded": 12,                  // unique domains dropped by exclude lists
+//!   "cleaned": 0,                    // entries reduced to a hostname by --clean
+//!   "invalid": 3,                    // entries that weren't domains and were dropped
+//!   "size": 181234,                  // bytes of the whole output
+//!   "checksum": "1a2b3c4d",          // CRC-32 of the whole output, as in .selector_state
+//!   "changes": {"added": 5, "removed": 1},   // against the previous output, null without one
+//!   "files": [{"name": "list-a.txt", "domains": 120, "duplicates": 4, "added": 116}],
+//!   "warnings": ["..."],
+//!   "semantics": "block",            // --semantics block or allow, null without it
+//!   "pinned": 2,                     // domains from --pin-file at the top, counted in unique too
+//!   "idn_collapsed": 0,              // with --punycode, repeats of a host in its other spelling
+//!   "denied": 0,                     // dropped by lists/.denylist, applied after everything else
+//!   "shuffle_seed": 42,              // the --shuffle seed the order was made with, null when sorted
+//!   "case_collapsed": 0,             // with --preserve-case, repeats of a domain in another case
+//!   "unchanged": 0                   // with --git-friendly, output files not rewritten as they had the content
+//! }
+//! ```
+
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::errors;
+use crate::merge::MergeStats;
+use crate::{checksum, file_ops};
+
+pub fn write(path: &Path, stats: &MergeStats) -> errors::Result<()> {
+    file_ops::replace_contents(path, render(stats))
+}
+
+fn render(stats: &MergeStats) -> String {
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_secs());
+    let chunks: Vec<String> = stats.chunks.iter().map(|chunk| string(&chunk.to_string_lossy())).collect();
+    let changes = match stats.changes {
+        Some((added, removed)) => format!("{{\"added\": {}, \"removed\": {}}}", added, removed),
+        None => String::from("null"),
+    };
+    let files: Vec<String> = stats.per_file.iter()
+        .map(|file| format!(
+            "    {{\"name\": {}, \"domains\": {}, \"duplicates\": {}, \"added\": {}}}",
+            string(&file.name), file.domains, file.duplicates, file.added
+        ))
+        .collect();
+    let warnings: Vec<String> = stats.warnings.iter().map(|warning| string(warning)).collect();
+
+    let fields = [
+        format!("\"timestamp\": {}", timestamp),
+        format!("\"output\": {}", string(&stats.output.to_string_lossy())),
+        format!("\"chunks\": [{}]", chunks.join(", ")),
+        format!("\"unique\": {}", stats.written),
+        format!("\"duplicates\": {}", stats.duplicates),
+        format!("\"excluded\": {}", stats.excluded),
+        format!("\"cleaned\": {}", stats.cleaned),
+        format!("\"invalid\": {}", stats.invalid.len()),
+        format!("\"size\": {}", stats.size),
+        format!("\"checksum\": {}", string(&checksum::format(stats.checksum))),
+        format!("\"changes\": {}", changes),
+        if files.is_empty() {
+            String::from("\"files\": []")
+        } else {
+            format!("\"files\": [\n{}\n  ]", files.join(",\n"))
+        },
+        format!("\"warnings\": [{}]", warnings.join(", ")),
+        format!("\"semantics\": {}", stats.semantics.map_or(String::from("null"), |semantics| string(semantics.as_str()))),
+        format!("\"pinned\": {}", stats.pinned),
+        format!("\"idn_collapsed\": {}", stats.idn_collapsed),
+        format!("\"denied\": {}", stats.denied),
+        format!("\"shuffle_seed\": {}", stats.shuffle_seed.map_or(String::from("null"), |seed| seed.to_string())),
+        format!("\"case_collapsed\": {}", stats.case_collapsed),
+        format!("\"unchanged\": {}", stats.unchanged),
+    ];
+    format!("{{\n  {}\n}}\n", fields.join(",\n  "))
+}
+
+/// A JSON string literal, also used by validate_json
+pub fn string(text: &str) -> String {
+    let mut quoted = String::with_capacity(text.len() + 2);
+    quoted.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if c.is_control() => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
@@ -0,0 +1,47 @@
+//! Starting selections built into the binary, picked with --template while there is no saved
+//! selection yet. They only name files, a lists folder may have some of them or none.
+
+#[derive(Debug)]
+pub struct Template {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub files: &'static [&'static str],
+}
+
+pub const TEMPLATES: &[Template] = &[
+    Template {
+        name: "ru",
+        description: "рекомендуемый набор для России",
+        files: &[
+            "list-youtube.txt",
+            "list-discord.txt",
+            "list-instagram.txt",
+            "list-facebook.txt",
+            "list-twitter.txt",
+            "list-rutracker.txt",
+            "list-linkedin.txt",
+        ],
+    },
+    Template {
+        name: "video",
+        description: "видео и стриминг",
+        files: &["list-youtube.txt", "list-twitch.txt", "list-vimeo.txt"],
+    },
+    Template {
+        name: "minimal",
+        description: "только YouTube и Discord",
+        files: &["list-youtube.txt", "list-discord.txt"],
+    },
+];
+
+pub fn find(name: &str) -> Option<&'static Template> {
+    TEMPLATES.iter().find(|template| template.name == name)
+}
+
+/// "ru (рекомендуемый набор для России), video (...)" for error messages
+pub fn describe_all() -> String {
+    TEMPLATES.iter()
+        .map(|template| format!("{} ({})", template.name, template.description))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
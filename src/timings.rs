@@ -0,0 +1,47 @@
+//! --timings: wall-clock time of the scan, count and merge phases, printed to stderr on exit.
+//! Off by default, then nothing is recorded.
+
+use std::cell::RefCell;
+use std::time::{Duration, Instant};
+
+pub const SCAN: &str = "сканирование";
+pub const COUNT: &str = "подсчёт";
+/// Reading and deduplicating the selected lists
+pub const MERGE: &str = "объединение";
+/// Writing the merged output
+pub const MERGE_WRITE: &str = "запись";
+
+pub struct Timings {
+    enabled: bool,
+    /// In the order the phases started, the duration is None while a phase is running
+    phases: RefCell<Vec<(&'static str, Instant, Option<Duration>)>>,
+}
+
+impl Timings {
+    pub fn new(enabled: bool) -> Timings {
+        Timings { enabled, phases: RefCell::new(Vec::new()) }
+    }
+
+    pub fn begin(&self, phase: &'static str) {
+        if self.enabled {
+            self.phases.borrow_mut().push((phase, Instant::now(), None));
+        }
+    }
+
+    /// Ends the last running phase of that name, a phase that wasn't begun is ignored
+    pub fn end(&self, phase: &'static str) {
+        let mut phases = self.phases.borrow_mut();
+        if let Some(entry) = phases.iter_mut().rev().find(|entry| entry.0 == phase && entry.2.is_none()) {
+            entry.2 = Some(entry.1.elapsed());
+        }
+    }
+
+    pub fn print(&self) {
+        for (phase, _, duration) in self.phases.borrow().iter() {
+            match duration {
+                Some(duration) => eprintln!("время: {:<14} {:>10.1} мс", phase, duration.as_secs_f64() * 1000.0),
+                None => eprintln!("время: {:<14} {:>10}", phase, "не завершено"),
+            }
+        }
+    }
+}
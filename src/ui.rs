@@ -0,0 +1,915 @@
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::io::{self, Write};
+use crossterm::{
+    terminal::{self, ClearType},
+    style::Stylize,
+    cursor,
+    queue,
+};
+
+use crate::app::{App, CountDisplay, FileAction, FileEntry, Mode, OTHER_CATEGORY};
+use crate::{file_ops, width, wizard};
+use crate::{human_size, FOOTER_LINE, HEADER_LINES, SCROLL_AREA_HEIGHT, STATUS_LINE, VISIBLE_ITEMS};
+
+const BACK_HINT: &str = "↑↓ для прокрутки, ← или ESC для возврата";
+const PREVIEW_HINT: &str = "↑↓ для прокрутки, TAB для правки, D — без дубликатов, R — причины, W — перенос строк, ← или ESC для возврата";
+const CLEANED_HINT: &str = "↑↓ для прокрутки, D — файл как есть, W — перенос строк, ← или ESC для возврата";
+const EDIT_HINT: &str = "↑↓ строка, # или ПРОБЕЛ закомментировать/раскомментировать, ! отключить/включить домен, W — перенос строк, TAB — закончить правку";
+
+/// Lines of the output changes in the save summary, drawn green and red
+pub const ADDED_PREFIX: &str = "  + ";
+pub const REMOVED_PREFIX: &str = "  - ";
+
+/// Below this many columns the header is abbreviated and list names are cut harder
+const NARROW_WIDTH: usize = 40;
+
+pub fn draw(stdout: &mut io::Stdout, app: &App, clear_screen: bool) -> io::Result<()> {
+    match &app.mode {
+        Mode::Submenu { file, cursor } => draw_submenu(stdout, app, file, *cursor),
+        Mode::Preview { file, lines, size, scroll, cursor, file_lines } => {
+            let disabled = lines.iter().filter(|line| file_ops::disabled_entry(line).is_some()).count();
+            let title = if let Some(file_lines) = file_lines {
+                format!(
+                    "Просмотр {} без дубликатов, строк убрано: {} (файл не изменён)",
+                    file, file_lines.len() - lines.len()
+                )
+            } else if disabled > 0 {
+                format!("Просмотр {} ({}, отключено: {})", file, human_size(*size), disabled)
+            } else {
+                format!("Просмотр {} ({})", file, human_size(*size))
+            };
+            let hint = match (cursor, file_lines) {
+                (Some(_), _) => EDIT_HINT,
+                (None, Some(_)) => CLEANED_HINT,
+                (None, None) => PREVIEW_HINT,
+            };
+            let style = LineStyle {
+                highlight: app.highlight_term(),
+                marking: cursor.map_or(Marking::Plain, Marking::Cursor),
+                overflow: preview_overflow(app),
+            };
+            draw_text_view(stdout, &title, lines, *scroll, hint, style)
+        }
+        Mode::Report { title, lines, scroll } => draw_text_view(stdout, title, lines, *scroll, BACK_HINT, PLAIN),
+        Mode::Wizard { step, cursor } => {
+            let (lines, choice) = wizard::page(app, *step, *cursor);
+            let hint = match step {
+                wizard::Step::Folder => "ENTER — дальше, ESC — пропустить мастер",
+                _ => "↑↓ выбор, ENTER — дальше, ← — назад, ESC — пропустить мастер",
+            };
+            let marking = choice.map_or(Marking::Plain, Marking::Cursor);
+            draw_text_view(stdout, &wizard::title(*step), &lines, 0, hint, LineStyle { marking, ..PLAIN })
+        }
+        Mode::ConfirmSave { lines, scroll } => draw_text_view(
+            stdout,
+            &if app.wizard {
+                format!("Мастер, шаг {} из {}: сохранение", wizard::STEP_COUNT, wizard::STEP_COUNT)
+            } else {
+                String::from("Сохранение")
+            },
+            lines,
+            *scroll,
+            "ENTER — сохранить и объединить, ESC — вернуться к выбору",
+            LineStyle { marking: Marking::Diff, ..PLAIN },
+        ),
+        Mode::ConfirmNormalize { lines, scroll, .. } => draw_text_view(
+            stdout,
+            "Переименование",
+            lines,
+            *scroll,
+            "ENTER — переименовать, ESC — отмена",
+            PLAIN,
+        ),
+        Mode::ConfirmDedupAll { lines, scroll, .. } => draw_text_view(
+            stdout,
+            "Удаление дубликатов",
+            lines,
+            *scroll,
+            "ENTER — удалить дубликаты, ESC — отмена",
+            PLAIN,
+        ),
+        Mode::ConfirmDeleteMarked { lines, scroll, .. } => draw_text_view(
+            stdout,
+            "Удаление отмеченных",
+            lines,
+            *scroll,
+            "ENTER — удалить файлы, ESC — отмена",
+            PLAIN,
+        ),
+        _ => draw_screen(stdout, app, clear_screen),
+    }
+}
+
+/// What the list screen showed, taken before a key is handled so the redraw after it
+/// can tell a plain cursor move from anything else
+pub struct Frame {
+    /// Hash of everything on the screen except the highlight and the position counter
+    content: u64,
+    cursor: Option<Highlight>,
+}
+
+/// The highlighted row and the screen line it is on
+#[derive(Clone, Copy, PartialEq)]
+struct Highlight {
+    line: u16,
+    kind: RowKind,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum RowKind {
+    Control(usize),
+    Recent(usize),
+    List(usize),
+}
+
+impl Frame {
+    pub fn of(app: &App) -> Frame {
+        let mut hasher = DefaultHasher::new();
+        terminal_width().hash(&mut hasher);
+        app.entries.hash(&mut hasher);
+        app.scroll_offset.hash(&mut hasher);
+        app.name_offset.hash(&mut hasher);
+        app.filter.hash(&mut hasher);
+        app.case_sensitive.hash(&mut hasher);
+        app.status.hash(&mut hasher);
+        app.recent_rows().hash(&mut hasher);
+        app.content.is_loading().hash(&mut hasher);
+        app.favorites_only.hash(&mut hasher);
+        app.selected_only.hash(&mut hasher);
+        app.count_display.hash(&mut hasher);
+        app.show_save_counts.hash(&mut hasher);
+        app.pending_counts.len().hash(&mut hasher);
+        for entry in &app.entries {
+            app.match_counts.get(&entry.name).hash(&mut hasher);
+            app.favorites.contains(&entry.name).hash(&mut hasher);
+            app.marked.contains(&entry.name).hash(&mut hasher);
+        }
+
+        Frame { content: hasher.finish(), cursor: highlight(app) }
+    }
+}
+
+/// Where draw_screen puts the highlighted row, None if it is scrolled out or there is none
+fn highlight(app: &App) -> Option<Highlight> {
+    let recent = app.recent_rows();
+    // Below the scroll indicator line, and the section title when there are recent lists
+    let list_top = HEADER_LINES + 1 + if recent.is_empty() { 0 } else { recent.len() + 1 };
+
+    if let Some(pos) = app.recent_cursor {
+        let index = *recent.get(pos)?;
+        return Some(Highlight { line: (HEADER_LINES + 2 + pos) as u16, kind: RowKind::Recent(index) });
+    }
+    if app.current_index < app.num_control_entries() {
+        // Control rows follow the header and breadcrumb lines
+        let line = (2 + app.current_index) as u16;
+        return Some(Highlight { line, kind: RowKind::Control(app.current_index) });
+    }
+
+    let pos = app.visible_files().iter().position(|&index| index == app.current_index)?;
+    let row = pos.checked_sub(app.scroll_offset).filter(|&row| row < app.page_size())?;
+    Some(Highlight { line: (list_top + row) as u16, kind: RowKind::List(app.current_index) })
+}
+
+/// Redraw after a key in the list: when only the cursor moved, just the row it left, the row
+/// it landed on and the status line with the position counter are rewritten. Anything else,
+/// including a cursor that can't be located, gets the usual draw.
+pub fn redraw(stdout: &mut io::Stdout, app: &App, before: &Frame) -> io::Result<()> {
+    let after = Frame::of(app);
+    let (Mode::List, Some(old), Some(new), true) = (&app.mode, before.cursor, after.cursor, before.content == after.content) else {
+        return draw(stdout, app, false);
+    };
+    if old == new {
+        return Ok(());
+    }
+
+    let width = terminal_width();
+    for (highlight, highlighted) in [(old, false), (new, true)] {
+        queue!(stdout, cursor::MoveTo(0, highlight.line), terminal::Clear(ClearType::CurrentLine))?;
+        match highlight.kind {
+            RowKind::Control(index) => draw_control_row(stdout, app, index, highlighted)?,
+            RowKind::Recent(index) | RowKind::List(index) => {
+                draw_list_row(stdout, app, &app.entries[index], highlighted, width)?
+            }
+        }
+    }
+
+    let file_entries: Vec<_> = app.visible_files()
+        .into_iter()
+        .map(|index| (index, &app.entries[index]))
+        .collect();
+    let visible_end = app.scroll_offset.saturating_add(app.page_size()).min(file_entries.len());
+    draw_status_line(stdout, app, &file_entries, visible_end, width)?;
+
+    stdout.flush()
+}
+
+fn draw_screen(stdout: &mut io::Stdout, app: &App, clear_screen: bool) -> io::Result<()> {
+    let entries = &app.entries;
+    let current_index = app.current_index;
+    let scroll_offset = app.scroll_offset;
+
+    if clear_screen {
+        queue!(stdout, terminal::Clear(ClearType::All))?;
+    }
+
+    queue!(stdout, cursor::MoveTo(0, 0))?;
+
+    // Header section, abbreviated on narrow terminals where the full one would wrap
+    let width = terminal_width();
+    let narrow = width < NARROW_WIDTH;
+    queue!(stdout, terminal::Clear(ClearType::CurrentLine))?;
+    if let Some(comparison) = &app.compare {
+        let header = format!(
+            "СРАВНЕНИЕ {} (A) и {} (B): ↑↓ навигация, → просмотр, ENTER на СОХРАНИТЬ или ОТМЕНА для выхода",
+            comparison.names.0, comparison.names.1
+        );
+        writeln!(stdout, "{}", truncate_start(&header, width.saturating_sub(1)).yellow())?;
+    } else if app.args.review {
+        let header = if narrow { "ТОЛЬКО ЧТЕНИЕ ↑↓ → ? справка" } else { "РЕЖИМ ТОЛЬКО ДЛЯ ЧТЕНИЯ: ↑↓ для навигации, → для просмотра, ENTER на СОХРАНИТЬ или ОТМЕНА для выхода" };
+        writeln!(stdout, "{}", truncate_start(header, width.saturating_sub(1)).yellow())?;
+    } else if narrow {
+        writeln!(stdout, "{}", truncate_start("↑↓ ␣ выбор ^S сохр. ? справка", width.saturating_sub(1)))?;
+    } else {
+        let select_keys = if app.args.enter_opens_menu {
+            "ПРОБЕЛ для выбора, ENTER для действий"
+        } else {
+            "ПРОБЕЛ или ENTER для выбора"
+        };
+        writeln!(stdout, "Используйте ↑↓ для навигации, {}, Ctrl+S для сохранения, ? для справки", select_keys)?;
+    }
+
+    // Which folder is being edited, the tool depends on where it was started from
+    let mut breadcrumb = format!("Папка: {}", app.lists_dir_display);
+    if !app.filter.is_empty() {
+        let case = if app.case_sensitive { ", с учётом регистра" } else { "" };
+        breadcrumb = format!("Фильтр: «{}»{} (ESC — сбросить) · {}", app.filter, case, breadcrumb);
+    }
+    if app.favorites_only {
+        breadcrumb = format!("Избранное (F — все списки) · {}", breadcrumb);
+    }
+    if app.selected_only {
+        breadcrumb = format!("Только выбранные (H — все списки) · {}", breadcrumb);
+    }
+    if app.wizard {
+        breadcrumb = format!(
+            "{} — ПРОБЕЛ отмечает, Ctrl+S — дальше, ← — назад, ESC — закрыть мастер · {}",
+            wizard::title(wizard::Step::Selection), breadcrumb
+        );
+    }
+    if app.audit {
+        breadcrumb = format!("Аудит: ни разу не сохранённые списки (A — выключить) · {}", breadcrumb);
+    }
+    queue!(stdout, terminal::Clear(ClearType::CurrentLine))?;
+    let mut room = width.saturating_sub(1);
+    // Stays for the whole session, saving would fail at the very end
+    if let Some(warning) = &app.output_warning {
+        let banner = truncate_start(&format!("⚠ Сохранение не получится: {} · ", warning), room);
+        room = room.saturating_sub(width::str_width(&banner));
+        write!(stdout, "{}", banner.red())?;
+    }
+    // Unsaved changes, kept outside the dimmed part so it stays visible
+    if app.is_dirty() {
+        let badge = if narrow { "● " } else { "● изменено · " };
+        write!(stdout, "{}", badge.yellow())?;
+        room = room.saturating_sub(width::str_width(badge));
+    }
+    if room > 0 {
+        writeln!(stdout, "{}", truncate_start(&breadcrumb, room).dim())?;
+    } else {
+        writeln!(stdout)?;
+    }
+
+    // Draw control options first
+    for index in 0..app.num_control_entries() {
+        draw_control_row(stdout, app, index, index == current_index)?;
+    }
+
+    writeln!(stdout)?; // Extra empty line after control options
+    writeln!(stdout)?; // Separator line
+
+    // Clear the scroll area
+    for _ in 0..SCROLL_AREA_HEIGHT {
+        writeln!(stdout, "{}", " ".repeat(50))?; // Clear line with spaces
+    }
+
+    // Move back to start of scroll area
+    queue!(stdout, cursor::MoveTo(0, HEADER_LINES as u16))?;
+
+    // Get file entries (non-control entries) that pass the filter
+    let file_entries: Vec<_> = app.visible_files()
+        .into_iter()
+        .map(|index| (index, &entries[index]))
+        .collect();
+
+    let total_files = file_entries.len();
+    let visible_end = scroll_offset.saturating_add(app.page_size()).min(total_files);
+
+    // Show scroll indicator if needed
+    if scroll_offset > 0 {
+        writeln!(stdout, "{}", if narrow { " ↑ ещё" } else { " ↑ Прокрутите вверх для большего количества файлов" })?;
+    } else {
+        writeln!(stdout)?; // Keep spacing consistent
+    }
+
+    // Recently toggled lists, pinned above the scrolling part
+    let recent_rows = app.recent_rows();
+    if !recent_rows.is_empty() {
+        writeln!(stdout, "{}", " Недавние:".dim())?;
+        for (pos, &index) in recent_rows.iter().enumerate() {
+            let highlighted = app.recent_cursor == Some(pos);
+            draw_list_row(stdout, app, &entries[index], highlighted, width)?;
+        }
+    }
+
+    // Draw visible file entries
+    let visible_entries = &file_entries[scroll_offset..visible_end];
+    for (real_index, entry) in visible_entries {
+        let highlighted = *real_index == current_index && app.recent_cursor.is_none();
+        draw_list_row(stdout, app, entry, highlighted, width)?;
+    }
+
+    if app.content_query().is_some() && app.content.is_loading() {
+        writeln!(stdout, "{}", "  Поиск по содержимому…".dim())?;
+    } else if file_entries.is_empty() && !app.filter.is_empty() {
+        writeln!(stdout, "{}", "  Нет списков, подходящих под фильтр".dim())?;
+    }
+
+    // Move to the bottom scroll indicator position
+    queue!(stdout, cursor::MoveTo(0, (HEADER_LINES + VISIBLE_ITEMS + 1) as u16))?;
+
+    // Show scroll indicator if needed
+    if visible_end < total_files {
+        writeln!(stdout, "{}", if narrow { " ↓ ещё" } else { " ↓ Прокрутите вниз для большего количества файлов" })?;
+    }
+
+    draw_status_line(stdout, app, &file_entries, visible_end, width)?;
+
+    // Statistics footer
+    queue!(
+        stdout,
+        cursor::MoveTo(0, FOOTER_LINE as u16),
+        terminal::Clear(ClearType::CurrentLine)
+    )?;
+    write!(stdout, "{}", stats_footer(app, terminal_width()).dim())?;
+
+    stdout.flush()
+}
+
+/// Status line, prompts of the inline modes take precedence over messages
+fn draw_status_line(
+    stdout: &mut io::Stdout,
+    app: &App,
+    file_entries: &[(usize, &FileEntry)],
+    visible_end: usize,
+    width: usize
+) -> io::Result<()> {
+    let narrow = width < NARROW_WIDTH;
+    queue!(
+        stdout,
+        cursor::MoveTo(0, STATUS_LINE as u16),
+        terminal::Clear(ClearType::CurrentLine)
+    )?;
+    match &app.mode {
+        Mode::Rename { file, input } => {
+            write!(stdout, "Новое имя для {} (ESC — отмена): {}_", file, input)?;
+        }
+        Mode::WriteAs { input } => {
+            write!(stdout, "Записать объединённый список в (TAB — дополнить, ESC — отмена): {}_", input)?;
+        }
+        Mode::RestoreDraft { names } => {
+            let prompt = format!("Найден несохранённый выбор (списков: {}). Восстановить? (y/n)", names.len());
+            write!(stdout, "{}", prompt.yellow())?;
+        }
+        Mode::Search => {
+            let case = if app.case_sensitive { "Аа" } else { "аа" };
+            write!(stdout, "Поиск [{}] (TAB — регистр, ENTER — готово, ESC — сбросить): {}_", case, app.filter)?;
+        }
+        Mode::ConfirmDelete { file } => {
+            write!(stdout, "{}", format!("Удалить {}? (y/n)", file).red())?;
+        }
+        Mode::ConfirmReset { changes, inverse, .. } => {
+            let prompt = if *inverse {
+                format!("Выбрать списки, которых нет в сохранённом выборе? Изменится списков: {} (y/n)", changes)
+            } else {
+                format!("Сбросить выбор к сохранённому? Изменится списков: {} (y/n)", changes)
+            };
+            write!(stdout, "{}", prompt.yellow())?;
+        }
+        _ => {
+            let position = position_indicator(app, file_entries, visible_end);
+            let reserved = position.as_ref().map_or(0, |position| width::str_width(position) + 1);
+            if let Some(status) = &app.status {
+                let status = if narrow || reserved > 0 {
+                    truncate_start(status, width.saturating_sub(reserved + 1))
+                } else {
+                    status.clone()
+                };
+                write!(stdout, "{}", status.yellow())?;
+            }
+            if let Some(position) = position {
+                queue!(stdout, cursor::MoveTo(width.saturating_sub(reserved) as u16, STATUS_LINE as u16))?;
+                write!(stdout, "{}", position.dim())?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn draw_control_row(stdout: &mut io::Stdout, app: &App, index: usize, highlighted: bool) -> io::Result<()> {
+    let Some(row) = app.entries[index].control else {
+        return Ok(());
+    };
+    let line = format!(
+        "{}  {}",
+        if highlighted { app.args.markers.cursor } else { ' ' },
+        row.label()
+    );
+
+    if highlighted {
+        writeln!(stdout, "{}", line.reverse())
+    } else {
+        writeln!(stdout, "{}", line)
+    }
+}
+
+fn draw_list_row(
+    stdout: &mut io::Stdout,
+    app: &App,
+    entry: &FileEntry,
+    highlighted: bool,
+    width: usize
+) -> io::Result<()> {
+    let glyphs = app.args.markers;
+    let cursor = if highlighted { glyphs.cursor } else { ' ' };
+    let markers = match &app.compare {
+        Some(comparison) => format!("{} [{}] ", cursor, comparison.tag(&entry.name)),
+        None => format!(
+            "{} [{}] {}{} ",
+            cursor,
+            if entry.selected { glyphs.selected } else { glyphs.unselected },
+            if entry.saved { "S" } else { " " },
+            if app.marked.contains(&entry.name) {
+                "✗"
+            } else if app.favorites.contains(&entry.name) {
+                "★"
+            } else {
+                " "
+            }
+        ),
+    };
+    let markers = markers + &count_column(app, entry) + &save_count_column(app, entry);
+    let mut name = entry.name.clone();
+    if let Some(count) = app.match_counts.get(&entry.name) {
+        name.push_str(&format!("  (совпадений: {})", count));
+    }
+    name = clip_window(&name, app.name_offset, width.saturating_sub(width::str_width(&markers) + 1));
+
+    if highlighted {
+        writeln!(stdout, "{}", format!("{}{}", markers, name).reverse())
+    } else if entry.changed_recently {
+        // Colour only, the cursor is reversed and the selection has its own column
+        writeln!(stdout, "{}{}", markers, name.cyan())
+    } else {
+        writeln!(stdout, "{}{}", markers, name)
+    }
+}
+
+/// "  1423  12% " before the name, as wide in both modes and on every row so the names stay
+/// aligned. The share is of the selected domains and left blank for unselected lists.
+fn count_column(app: &App, entry: &FileEntry) -> String {
+    if app.count_display == CountDisplay::Hidden {
+        return String::new();
+    }
+    let digits = app.entries.iter()
+        .filter_map(|e| e.domain_count)
+        .max()
+        .map_or(1, |max| max.to_string().len());
+    let count = match entry.domain_count {
+        Some(count) => count.to_string(),
+        None if app.pending_counts.contains(&entry.name) => String::from("…"),
+        None => String::from("?"),
+    };
+    let total = app.selected_total();
+    let share = match entry.domain_count {
+        Some(count) if app.count_display == CountDisplay::Share && entry.selected && total > 0 => {
+            let percent = count * 100 / total;
+            if percent == 0 && count > 0 { String::from("<1%") } else { format!("{}%", percent) }
+        }
+        _ => String::new(),
+    };
+    format!("{:>digits$} {:>4} ", count, share, digits = digits)
+}
+
+/// " ×12 " before the name with C, padded to the widest count
+fn save_count_column(app: &App, entry: &FileEntry) -> String {
+    if !app.show_save_counts {
+        return String::new();
+    }
+    let digits = app.save_counts.values().max().map_or(1, |max| max.to_string().len());
+    let count = app.save_counts.get(&entry.name).copied().unwrap_or(0);
+    format!("×{:<digits$} ", count, digits = digits)
+}
+
+/// "↑↓ 12/47" at the right of the status line once the list doesn't fit the scroll area:
+/// the cursor's place among the lists passing the filter and where more of them are hidden
+fn position_indicator(app: &App, file_entries: &[(usize, &FileEntry)], visible_end: usize) -> Option<String> {
+    let total = file_entries.len();
+    if total <= app.page_size() {
+        return None;
+    }
+
+    let current = match app.recent_cursor {
+        Some(pos) => app.recent_rows().get(pos).copied(),
+        None => Some(app.current_index),
+    };
+    let position = file_entries.iter()
+        .position(|(index, _)| Some(*index) == current)
+        .map_or(String::from("-"), |pos| (pos + 1).to_string());
+    let arrows = format!(
+        "{}{}",
+        if app.scroll_offset > 0 { "↑" } else { " " },
+        if visible_end < total { "↓" } else { " " }
+    );
+    Some(format!("{} {}/{}", arrows, position, total))
+}
+
+/// "Выбрано доменов: 14200 (social: 4200, video: 8900, другое: 1100)", reduced to the
+/// total alone when no selected list has a category or the breakdown doesn't fit
+fn stats_footer(app: &App, width: usize) -> String {
+    if !app.pending_counts.is_empty() {
+        let pending = app.entries.iter().filter(|e| e.selected && app.pending_counts.contains(&e.name)).count();
+        if pending > 0 {
+            return format!("Выбрано доменов: … (подсчёт, осталось выбранных списков: {})", pending);
+        }
+    }
+    let total = app.selected_total();
+    let summary = format!("Выбрано доменов: {}", total);
+    if app.selected_domains.keys().all(|category| category == OTHER_CATEGORY) {
+        return summary;
+    }
+
+    // Keep "другое" last, it's the leftovers
+    let mut parts: Vec<String> = app.selected_domains.iter()
+        .filter(|(category, _)| *category != OTHER_CATEGORY)
+        .map(|(category, (_, count))| format!("{}: {}", category, count))
+        .collect();
+    if let Some((_, count)) = app.selected_domains.get(OTHER_CATEGORY) {
+        parts.push(format!("{}: {}", OTHER_CATEGORY, count));
+    }
+
+    let detailed = format!("{} ({})", summary, parts.join(", "));
+    if width::str_width(&detailed) < width {
+        detailed
+    } else {
+        summary
+    }
+}
+
+fn terminal_width() -> usize {
+    match terminal::size() {
+        Ok((cols, _)) if cols > 0 => cols as usize,
+        _ => 80,
+    }
+}
+
+/// Shorten text to max_cols columns by cutting its beginning, the end of a path is the informative part
+fn truncate_start(text: &str, max_cols: usize) -> String {
+    if width::str_width(text) <= max_cols {
+        return text.to_string();
+    }
+
+    let room = max_cols.saturating_sub(1);
+    let mut used = 0;
+    let mut start = text.len();
+    for (index, c) in text.char_indices().rev() {
+        used += width::char_width(c);
+        if used > room {
+            break;
+        }
+        start = index;
+    }
+    format!("…{}", &text[start..])
+}
+
+/// Show max_cols columns of text starting at the offset-th character, with "…" marking the
+/// clipped sides. A wide character that doesn't fit whole is left out rather than split.
+fn clip_window(text: &str, offset: usize, max_cols: usize) -> String {
+    let rest: String = text.chars().skip(offset).collect();
+    if offset == 0 && width::str_width(text) <= max_cols {
+        return text.to_string();
+    }
+
+    let clipped_left = offset > 0;
+    let room = max_cols.saturating_sub(clipped_left as usize);
+    let clipped_right = width::str_width(&rest) > room;
+    let room = room.saturating_sub(clipped_right as usize);
+
+    let mut shown = String::new();
+    let mut used = 0;
+    for c in rest.chars() {
+        used += width::char_width(c);
+        if used > room {
+            break;
+        }
+        shown.push(c);
+    }
+    if clipped_left {
+        shown.insert(0, '…');
+    }
+    if clipped_right {
+        shown.push('…');
+    }
+    shown
+}
+
+/// Content of the help overlay opened with ? or F1
+pub fn help_lines() -> Vec<String> {
+    [
+        "↑ / ↓          навигация по списку",
+        "ПРОБЕЛ/ENTER   выбрать список, ENTER также нажимает кнопку",
+        "               [*] — выбран сейчас, S — в сохранённом selected.txt, ★ — в избранном,",
+        "               ✗ — отмечен для удаления, голубое имя — файл изменён недавно (--changed-hours)",
+        "Alt+буква      к следующему списку на эту букву (без префикса list-)",
+        "→              действия с файлом (с --enter-opens-menu также ENTER)",
+        "               просмотр, дубликаты, проверка, переименование, удаление",
+        "TAB в просмотре курсор по строкам, # или ПРОБЕЛ закомментирует строку,",
+        "               ! отключит домен (#!домен) или включит обратно",
+        "R в просмотре   домены с причиной, записанной как «домен ## причина»",
+        "D в просмотре   файл, каким его оставит удаление дубликатов, без записи",
+        "Ctrl+S         сохранить выбор и объединить списки",
+        "/              фильтр по имени (ESC — сбросить), совпадения подсвечиваются в просмотре",
+        "/&текст        списки, в которых есть домен с этим текстом",
+        "TAB в поиске   учитывать регистр в именах / не учитывать (--case-sensitive)",
+        "Z              сбросить выбор к сохранённому в selected.txt",
+        "I              выбрать ровно те списки, которых нет в сохранённом selected.txt",
+        "V              пересечение двух выбранных списков",
+        "S              содержимое selected.txt на диске как есть (ESC — закрыть)",
+        "D              удалить дубликаты во всех выбранных списках (с подтверждением)",
+        "N              привести имена файлов к виду list-имя.txt (с подтверждением)",
+        "*              добавить список в избранное или убрать, выбор не меняется",
+        "F              показать только избранное / все списки",
+        "H              показать только выбранные / все списки, для проверки перед сохранением",
+        "X              отметить список для удаления или снять отметку, выбор не меняется",
+        "Delete         удалить все отмеченные файлы (с подтверждением, ESC снимает отметки)",
+        "A              аудит: только списки, которых не было ни в одном сохранённом выборе",
+        "%              число доменов в строках: скрыто / количество / с долей от выбранных",
+        "C              в скольких сохранённых выборах был каждый список (×N)",
+        "M              объединить выбранное в list-ultimate.txt, не сохраняя selected.txt",
+        "W              записать объединённый список выбранного в другой файл, selected.txt не меняется",
+        "Y / Shift+Y    скопировать имя списка / полный путь в буфер обмена",
+        "O              сортировка: по имени / выбранные сверху (сбрасывает ручной порядок)",
+        "Shift+↑ / ↓    переместить список: порядок объединения, виден с --keep-comments, --separators, --preserve-case",
+        "Shift+← / →    прокрутить длинные имена, … — имя обрезано с этой стороны",
+        "Q / ESC        выход без сохранения (с --no-action-rows)",
+        "? или F1       эта справка, клавиши списка меняются в [keys] файла selector.toml",
+        "Ctrl+C         выход без сохранения",
+    ]
+    .iter()
+    .map(|line| line.to_string())
+    .collect()
+}
+
+fn draw_submenu(stdout: &mut io::Stdout, app: &App, file: &str, current: usize) -> io::Result<()> {
+    queue!(stdout, terminal::Clear(ClearType::All), cursor::MoveTo(0, 0))?;
+    writeln!(stdout, "Используйте ↑↓ для выбора действия, ENTER для выполнения, ← или ESC для возврата\n")?;
+    writeln!(stdout, "Действия с файлом {}:\n", file)?;
+
+    for (index, action) in FileAction::ALL.iter().enumerate() {
+        let line = format!("{}  {}", if index == current { app.args.markers.cursor } else { ' ' }, action.label());
+        if index == current {
+            writeln!(stdout, "{}", line.reverse())?;
+        } else {
+            writeln!(stdout, "{}", line)?;
+        }
+    }
+
+    stdout.flush()
+}
+
+/// Char ranges of case-insensitive occurrences of needle in text
+fn match_ranges(text: &str, needle: &str) -> Vec<(usize, usize)> {
+    let fold = |c: char| c.to_lowercase().next().unwrap_or(c);
+    let text: Vec<char> = text.chars().map(fold).collect();
+    let needle: Vec<char> = needle.chars().map(fold).collect();
+    if needle.is_empty() || needle.len() > text.len() {
+        return Vec::new();
+    }
+
+    let mut ranges = Vec::new();
+    let mut start = 0;
+    while start + needle.len() <= text.len() {
+        if text[start..start + needle.len()] == needle[..] {
+            ranges.push((start, start + needle.len()));
+            start += needle.len();
+        } else {
+            start += 1;
+        }
+    }
+    ranges
+}
+
+/// Write a line with every occurrence of highlight marked, black on yellow reads on light and dark themes
+fn write_highlighted(stdout: &mut io::Stdout, line: &str, highlight: &str) -> io::Result<()> {
+    let chars: Vec<char> = line.chars().collect();
+    let mut written = 0;
+    for (start, end) in match_ranges(line, highlight) {
+        let before: String = chars[written..start].iter().collect();
+        let matched: String = chars[start..end].iter().collect();
+        write!(stdout, "{}{}", before, matched.black().on_yellow())?;
+        written = end;
+    }
+
+    let rest: String = chars[written..].iter().collect();
+    write!(stdout, "{}", rest)
+}
+
+/// Lines draw_text_view sets apart beyond the highlight
+#[derive(Clone, Copy)]
+enum Marking {
+    Plain,
+    /// The edit cursor of the preview
+    Cursor(usize),
+    /// ADDED_PREFIX and REMOVED_PREFIX lines in green and red
+    Diff,
+}
+
+/// What draw_text_view does with a line wider than the terminal
+#[derive(Clone, Copy)]
+enum Overflow {
+    /// One row ending in "…"
+    Truncate,
+    /// Continued on the next rows, at most this many, the last one ending in "…" if it still doesn't fit
+    Wrap(usize),
+}
+
+/// Rows at most one wrapped preview line takes, so a long entry leaves room for the others
+const PREVIEW_WRAP_ROWS: usize = 4;
+
+#[derive(Clone, Copy)]
+struct LineStyle<'a> {
+    /// Marked like a search match in the list view, "" for nothing
+    highlight: &'a str,
+    marking: Marking,
+    overflow: Overflow,
+}
+
+/// The reports and confirmations: nothing marked, long lines wrapped as far as the screen allows
+const PLAIN: LineStyle = LineStyle { highlight: "", marking: Marking::Plain, overflow: Overflow::Wrap(SCROLL_AREA_HEIGHT) };
+
+/// The last column is left free, a row filling it makes some terminals wrap on their own
+fn text_columns() -> usize {
+    terminal_width().saturating_sub(1).max(1)
+}
+
+fn preview_overflow(app: &App) -> Overflow {
+    if app.preview_wrap { Overflow::Wrap(PREVIEW_WRAP_ROWS) } else { Overflow::Truncate }
+}
+
+/// How far the text view of the current mode scrolls: the first line from which the rest fits
+/// in the area, wrapped lines taking all their rows
+pub fn max_scroll(app: &App) -> usize {
+    let (lines, overflow) = match &app.mode {
+        Mode::Preview { lines, .. } => (lines, preview_overflow(app)),
+        Mode::Report { lines, .. } |
+        Mode::ConfirmSave { lines, .. } |
+        Mode::ConfirmDedupAll { lines, .. } |
+        Mode::ConfirmDeleteMarked { lines, .. } |
+        Mode::ConfirmNormalize { lines, .. } => (lines, PLAIN.overflow),
+        _ => return 0,
+    };
+    let max_cols = text_columns();
+    let mut rows = 0;
+    for (index, line) in lines.iter().enumerate().rev() {
+        rows += line_rows(line, max_cols, overflow).len();
+        if rows > SCROLL_AREA_HEIGHT {
+            return index + 1;
+        }
+    }
+    0
+}
+
+/// The rows a line takes in max_cols columns
+fn line_rows(line: &str, max_cols: usize, overflow: Overflow) -> Vec<String> {
+    let max_rows = match overflow {
+        Overflow::Truncate => 1,
+        Overflow::Wrap(max_rows) => max_rows.max(1),
+    };
+    let mut rows = Vec::new();
+    let mut rest = line;
+    while rows.len() + 1 < max_rows && width::str_width(rest) > max_cols {
+        let mut used = 0;
+        let mut split = 0;
+        for (index, c) in rest.char_indices() {
+            used += width::char_width(c);
+            if used > max_cols && split > 0 {
+                break;
+            }
+            split = index + c.len_utf8();
+        }
+        rows.push(rest[..split].to_string());
+        rest = &rest[split..];
+    }
+    rows.push(clip_window(rest, 0, max_cols));
+    rows
+}
+
+/// Read-only scrollable text, used by the file preview and the reports
+fn draw_text_view(
+    stdout: &mut io::Stdout,
+    title: &str,
+    lines: &[String],
+    scroll: usize,
+    hint: &str,
+    style: LineStyle
+) -> io::Result<()> {
+    queue!(stdout, terminal::Clear(ClearType::All), cursor::MoveTo(0, 0))?;
+    writeln!(stdout, "{} — {}\n", title, hint)?;
+
+    let max_cols = text_columns();
+    let row_count = |range: std::ops::RangeInclusive<usize>| -> usize {
+        lines[range].iter().map(|line| line_rows(line, max_cols, style.overflow).len()).sum()
+    };
+
+    // Lines above a wrapped one can push the edit cursor below the area, start later then
+    let mut start = scroll.min(lines.len());
+    if let Marking::Cursor(cursor) = style.marking {
+        while start < cursor && cursor < lines.len() && row_count(start..=cursor) > SCROLL_AREA_HEIGHT {
+            start += 1;
+        }
+    }
+
+    let mut used = 0;
+    let mut end = start;
+    for (index, line) in lines.iter().enumerate().skip(start) {
+        let rows = line_rows(line, max_cols, style.overflow);
+        if used + rows.len() > SCROLL_AREA_HEIGHT && index > start {
+            break;
+        }
+        for row in rows.iter().take(SCROLL_AREA_HEIGHT - used) {
+            match style.marking {
+                Marking::Cursor(cursor) if cursor == index => write!(stdout, "{}", row.as_str().reverse())?,
+                Marking::Diff if line.starts_with(ADDED_PREFIX) => write!(stdout, "{}", row.as_str().green())?,
+                Marking::Diff if line.starts_with(REMOVED_PREFIX) => write!(stdout, "{}", row.as_str().red())?,
+                _ => write_highlighted(stdout, row, style.highlight)?,
+            }
+            writeln!(stdout)?;
+        }
+        used += rows.len();
+        end = index + 1;
+    }
+
+    if lines.is_empty() {
+        writeln!(stdout, "{}", "(файл пуст)".dim())?;
+    } else {
+        queue!(stdout, cursor::MoveTo(0, (SCROLL_AREA_HEIGHT + 3) as u16))?;
+        write!(stdout, "{}", format!("строки {}-{} из {}", start + 1, end, lines.len()).dim())?;
+    }
+
+    stdout.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn truncate_start_counts_columns() {
+        assert_eq!(truncate_start("список.txt", 10), "список.txt");
+        assert_eq!(truncate_start("список-youtube.txt", 8), "…ube.txt");
+        assert_eq!(truncate_start("список-日本語.txt", 17), "список-日本語.txt");
+        // 日 would make it 11 columns, so it is left out and the result is a column short
+        let cut = truncate_start("список-日本語.txt", 10);
+        assert_eq!(cut, "…本語.txt");
+        assert_eq!(width::str_width(&cut), 9);
+    }
+
+    #[test]
+    fn clip_window_counts_columns() {
+        assert_eq!(clip_window("список-ютуб.txt", 0, 15), "список-ютуб.txt");
+        assert_eq!(clip_window("список-ютуб.txt", 2, 8), "…исок-ю…");
+        assert_eq!(clip_window("список-ютуб.txt", 7, 10), "…ютуб.txt");
+    }
+
+    #[test]
+    fn clip_window_never_splits_a_wide_character() {
+        // 語 would end in the column of the "…"
+        let clipped = clip_window("日本語中文.txt", 0, 6);
+        assert_eq!(clipped, "日本…");
+        assert_eq!(width::str_width(&clipped), 5);
+        assert_eq!(clip_window("日本語中文.txt", 1, 6), "…本語…");
+        assert_eq!(clip_window("日本語中文.txt", 3, 9), "…中文.txt");
+    }
+
+    #[test]
+    fn long_lines_end_in_an_ellipsis_or_wrap() {
+        assert_eq!(line_rows("abcdefghij", 4, Overflow::Truncate), ["abc…"]);
+        assert_eq!(line_rows("abcd", 4, Overflow::Truncate), ["abcd"]);
+        assert_eq!(line_rows("abcdefghij", 4, Overflow::Wrap(4)), ["abcd", "efgh", "ij"]);
+        // At most that many rows, the last one cut
+        assert_eq!(line_rows("abcdefghijklmnop", 4, Overflow::Wrap(2)), ["abcd", "efg…"]);
+        // A wide character goes to the next row whole
+        assert_eq!(line_rows("日本語中", 3, Overflow::Wrap(4)), ["日", "本", "語", "中"]);
+        assert_eq!(line_rows("a日本", 4, Overflow::Wrap(4)), ["a日", "本"]);
+    }
+}
@@ -0,0 +1,67 @@
+//! --validate-json: the invalid entries of every list as a JSON file, so CI can gate on list quality.
+//!
+//! ```text
+//! {
+//!   "timestamp": 1760000000,         // seconds since the Unix epoch
+//!   "dir": "lists",
+//!   "invalid": 2,                    // invalid entries over all files
+//!   "files": [
+//!     {"name": "list-a.txt", "invalid": [{"line": 3, "text": "https://a.com/x", "reason": "..."}]},
+//!     {"name": "list-b.txt", "invalid": []}
+//!   ],
+//!   "errors": [{"name": "list-c.txt", "error": "..."}]   // files that couldn't be read
+//! }
+//! ```
+//!
+//! "reason" is the note after "##" on the line (file_ops::REASON_DELIMITER), only present
+//! when the line has one.
+
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::errors;
+use crate::file_ops::{self, InvalidEntry};
+use crate::stats_json::string;
+
+/// Invalid entries of one list
+pub struct FileReport {
+    pub name: String,
+    pub invalid: Vec<InvalidEntry>,
+}
+
+pub fn write(path: &Path, lists_dir: &Path, files: &[FileReport], failed: &[(String, String)]) -> errors::Result<()> {
+    file_ops::replace_contents(path, render(lists_dir, files, failed))
+}
+
+fn render(lists_dir: &Path, files: &[FileReport], failed: &[(String, String)]) -> String {
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_secs());
+    let total: usize = files.iter().map(|file| file.invalid.len()).sum();
+    let files: Vec<String> = files.iter()
+        .map(|file| {
+            let invalid: Vec<String> = file.invalid.iter()
+                .map(|entry| {
+                    let reason = entry.reason.as_ref()
+                        .map_or(String::new(), |reason| format!(", \"reason\": {}", string(reason)));
+                    format!("{{\"line\": {}, \"text\": {}{}}}", entry.line, string(&entry.text), reason)
+                })
+                .collect();
+            format!("    {{\"name\": {}, \"invalid\": [{}]}}", string(&file.name), invalid.join(", "))
+        })
+        .collect();
+    let failed: Vec<String> = failed.iter()
+        .map(|(name, error)| format!("{{\"name\": {}, \"error\": {}}}", string(name), string(error)))
+        .collect();
+
+    let fields = [
+        format!("\"timestamp\": {}", timestamp),
+        format!("\"dir\": {}", string(&lists_dir.to_string_lossy())),
+        format!("\"invalid\": {}", total),
+        if files.is_empty() {
+            String::from("\"files\": []")
+        } else {
+            format!("\"files\": [\n{}\n  ]", files.join(",\n"))
+        },
+        format!("\"errors\": [{}]", failed.join(", ")),
+    ];
+    format!("{{\n  {}\n}}\n", fields.join(",\n  "))
+}
@@ -0,0 +1,63 @@
+//! Terminal column width of characters, enough of Unicode East Asian Width for list names
+
+/// Columns a character takes: 0 for combining marks and controls, 2 for wide East Asian
+/// characters and emoji, 1 otherwise
+pub fn char_width(c: char) -> usize {
+    let code = c as u32;
+    if c.is_control() || is_combining(code) {
+        0
+    } else if is_wide(code) {
+        2
+    } else {
+        1
+    }
+}
+
+/// Columns a string takes, the display width of a name rather than its byte or char count
+pub fn str_width(text: &str) -> usize {
+    text.chars().map(char_width).sum()
+}
+
+fn is_combining(code: u32) -> bool {
+    matches!(code,
+        0x0300..=0x036F | 0x0483..=0x0489 | 0x0591..=0x05BD | 0x0610..=0x061A |
+        0x064B..=0x065F | 0x0E31 | 0x0E34..=0x0E3A | 0x1AB0..=0x1AFF | 0x1DC0..=0x1DFF |
+        0x200B..=0x200F | 0x20D0..=0x20FF | 0xFE00..=0xFE0F | 0xFE20..=0xFE2F
+    )
+}
+
+fn is_wide(code: u32) -> bool {
+    matches!(code,
+        0x1100..=0x115F | 0x2E80..=0x303E | 0x3041..=0x33FF | 0x3400..=0x4DBF |
+        0x4E00..=0x9FFF | 0xA000..=0xA4CF | 0xAC00..=0xD7A3 | 0xF900..=0xFAFF |
+        0xFE30..=0xFE4F | 0xFF00..=0xFF60 | 0xFFE0..=0xFFE6 | 0x1F300..=0x1F64F |
+        0x1F900..=0x1F9FF | 0x20000..=0x3FFFD
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cyrillic_takes_a_column_per_letter() {
+        assert_eq!(str_width("список"), 6);
+        assert_eq!(str_width("пример.рф"), 9);
+        assert_eq!(str_width("list-ютуб.txt"), 13);
+    }
+
+    #[test]
+    fn cjk_takes_two_columns() {
+        assert_eq!(char_width('日'), 2);
+        assert_eq!(str_width("日本語"), 6);
+        assert_eq!(str_width("list-日本.txt"), 13);
+        assert_eq!(str_width("한국"), 4);
+    }
+
+    #[test]
+    fn combining_marks_take_none() {
+        assert_eq!(str_width("e\u{301}"), 1);
+        assert_eq!(str_width("й"), 1);
+        assert_eq!(str_width("и\u{306}"), 1);
+    }
+}
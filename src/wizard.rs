@@ -0,0 +1,125 @@
+//! --wizard, and the first start in a fresh folder: a few pages before the usual interface that
+//! confirm the lists folder and offer a template, then the list view itself for the selection
+//! and the save summary to save and merge. Esc on a page leaves for the normal interface.
+//! The step reached is kept in .selector_state, so a wizard closed half-way resumes there.
+
+use std::path::Path;
+
+use crate::app::App;
+use crate::{state, template};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Step {
+    Folder,
+    Template,
+    /// The list view with a banner, Ctrl+S goes on to the save summary
+    Selection,
+}
+
+/// The save summary is the last step
+pub const STEP_COUNT: usize = 4;
+
+/// Stored once the wizard was finished or skipped, it isn't offered on its own again
+const DONE: &str = "done";
+
+impl Step {
+    pub fn number(self) -> usize {
+        match self {
+            Step::Folder => 1,
+            Step::Template => 2,
+            Step::Selection => 3,
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Step::Folder => "folder",
+            Step::Template => "template",
+            Step::Selection => "selection",
+        }
+    }
+
+    fn parse(value: &str) -> Option<Step> {
+        [Step::Folder, Step::Template, Step::Selection].into_iter().find(|step| step.as_str() == value)
+    }
+}
+
+/// Where to start: the step of an unfinished wizard, the first one with --wizard or in a folder
+/// that has neither a saved selection nor a state file yet, None otherwise
+pub fn start(state_dir: &Path, forced: bool, has_config: bool) -> Option<Step> {
+    let values = state::read(state_dir);
+    match values.get(state::WIZARD).map(String::as_str) {
+        _ if forced => Some(Step::Folder),
+        Some(DONE) => None,
+        Some(step) => Step::parse(step),
+        None if values.is_empty() && !has_config => Some(Step::Folder),
+        None => None,
+    }
+}
+
+/// Remember the step for resuming, not worth an error if it fails
+pub fn reached(state_dir: &Path, step: Step) {
+    let _ = state::set(state_dir, state::WIZARD, step.as_str());
+}
+
+pub fn finish(state_dir: &Path) {
+    let _ = state::set(state_dir, state::WIZARD, DONE);
+}
+
+pub fn title(step: Step) -> String {
+    let name = match step {
+        Step::Folder => "папка со списками",
+        Step::Template => "начальный выбор",
+        Step::Selection => "выбор списков",
+    };
+    format!("Мастер, шаг {} из {}: {}", step.number(), STEP_COUNT, name)
+}
+
+/// Choices of the template page: keeping the selection as it is, then every template
+pub fn choice_count() -> usize {
+    template::TEMPLATES.len() + 1
+}
+
+/// Text of a page and the line of the choice under the cursor
+pub fn page(app: &App, step: Step, cursor: usize) -> (Vec<String>, Option<usize>) {
+    let lists = app.entries.iter().filter(|e| !e.is_control()).count();
+    match step {
+        Step::Folder => {
+            let mut lines = vec![
+                format!("Папка со списками: {}", app.lists_dir_display),
+                format!("Откуда: {}", app.args.dir_source.describe()),
+                format!("Найдено списков ({}*{}): {}", app.args.prefix, app.args.suffix, lists),
+                String::new(),
+            ];
+            if lists == 0 {
+                lines.push(String::from("⚠ Списков нет: положите файлы списков в эту папку и запустите программу снова."));
+            }
+            lines.push(String::from("Другая папка: запустите программу с --dir ПУТЬ или задайте ZAPRET_LISTS_DIR."));
+            (lines, None)
+        }
+        Step::Template => {
+            let selected = app.selected_names().len();
+            let mut lines = vec![
+                String::from("С чего начать выбор? Списки потом можно отметить и снять вручную."),
+                String::new(),
+                format!("  Оставить как есть (выбрано: {})", selected),
+            ];
+            for template in template::TEMPLATES {
+                let present = template.files.iter()
+                    .filter(|name| app.entries.iter().any(|e| e.name == **name))
+                    .count();
+                lines.push(format!(
+                    "  Шаблон «{}»: {} (есть списков: {} из {})",
+                    template.name, template.description, present, template.files.len()
+                ));
+            }
+            (lines, Some(2 + cursor))
+        }
+        Step::Selection => unreachable!("the selection step is the list view"),
+    }
+}
+
+/// The template of a choice on the template page, None for keeping the selection
+pub fn chosen_template(cursor: usize) -> Option<&'static template::Template> {
+    cursor.checked_sub(1).and_then(|index| template::TEMPLATES.get(index))
+}